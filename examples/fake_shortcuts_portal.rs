@@ -0,0 +1,184 @@
+//! A stand-in for the GlobalShortcuts portal, to try `backend = "portal"` in `[input]` without a
+//! desktop that supports it.
+//!
+//! It has to own the portal name on the bus wf_overlay uses, so run both on a private one. The
+//! screencast portal isn't there, so use the `folder` capture source:
+//!
+//! ```sh
+//! dbus-run-session -- sh -c '(sleep 3; cargo run) & cargo run --example fake_shortcuts_portal'
+//! ```
+//!
+//! Once wf_overlay bound its shortcuts, type the name of an action like `trigger` and press enter
+//! to press and release its shortcut.
+//!
+//! The tests of src/input/portal.rs use it too, run them with
+//! `dbus-run-session -- cargo test portal -- --ignored`.
+use std::{
+    collections::HashMap,
+    io::BufRead,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use zbus::{
+    Connection, blocking, interface,
+    message::Header,
+    zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value},
+};
+
+const DESKTOP_PATH: &str = "/org/freedesktop/portal/desktop";
+const INTERFACE: &str = "org.freedesktop.portal.GlobalShortcuts";
+
+/// Who to send activations to
+struct Bound {
+    client: String,
+    session: String,
+}
+
+struct GlobalShortcuts(Arc<Mutex<Option<Bound>>>);
+
+/// The sender as it appears in request and session paths, `:1.42` becomes `1_42`
+fn path_part(header: &Header<'_>) -> String {
+    header
+        .sender()
+        .map(|s| s.trim_start_matches(':').replace('.', "_"))
+        .unwrap_or_default()
+}
+
+fn option<'a>(options: &'a HashMap<String, OwnedValue>, key: &str) -> Option<&'a str> {
+    options.get(key).and_then(|v| v.downcast_ref::<&str>().ok())
+}
+
+/// Answer a request right away, through the `Response` signal of its request object
+async fn respond(
+    conn: &Connection,
+    header: &Header<'_>,
+    options: &HashMap<String, OwnedValue>,
+    results: HashMap<&str, Value<'_>>,
+) -> zbus::fdo::Result<OwnedObjectPath> {
+    let token = option(options, "handle_token").unwrap_or("request");
+    let path = format!("{DESKTOP_PATH}/request/{}/{token}", path_part(header));
+    conn.emit_signal(
+        header.sender().cloned(),
+        path.as_str(),
+        "org.freedesktop.portal.Request",
+        "Response",
+        &(0u32, results),
+    )
+    .await?;
+    Ok(ObjectPath::try_from(path)
+        .map_err(zbus::Error::from)?
+        .into())
+}
+
+#[interface(name = "org.freedesktop.portal.GlobalShortcuts")]
+impl GlobalShortcuts {
+    #[zbus(property, name = "version")]
+    fn version(&self) -> u32 {
+        1
+    }
+
+    async fn create_session(
+        &self,
+        options: HashMap<String, OwnedValue>,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] conn: &Connection,
+    ) -> zbus::fdo::Result<OwnedObjectPath> {
+        let token = option(&options, "session_handle_token").unwrap_or("session");
+        let session = format!("{DESKTOP_PATH}/session/{}/{token}", path_part(&header));
+        println!("Created session {session}");
+        let results = HashMap::from([("session_handle", Value::from(session))]);
+        respond(conn, &header, &options, results).await
+    }
+
+    async fn bind_shortcuts(
+        &self,
+        session: OwnedObjectPath,
+        shortcuts: Vec<(String, HashMap<String, OwnedValue>)>,
+        _parent_window: String,
+        options: HashMap<String, OwnedValue>,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] conn: &Connection,
+    ) -> zbus::fdo::Result<OwnedObjectPath> {
+        let bound: Vec<(String, HashMap<&str, Value>)> = shortcuts
+            .iter()
+            .map(|(id, info)| {
+                let description = option(info, "description").unwrap_or_default();
+                let trigger = option(info, "preferred_trigger").unwrap_or("unassigned");
+                println!("Bound {id} ({description}) to {trigger}");
+                let info = HashMap::from([
+                    ("description", Value::from(description.to_string())),
+                    ("trigger_description", Value::from(trigger.to_string())),
+                ]);
+                (id.clone(), info)
+            })
+            .collect();
+        *self.0.lock().unwrap() = Some(Bound {
+            client: header.sender().map(|s| s.to_string()).unwrap_or_default(),
+            session: session.to_string(),
+        });
+        let results = HashMap::from([("shortcuts", Value::from(bound))]);
+        respond(conn, &header, &options, results).await
+    }
+}
+
+/// The stand-in, serving the portal on the session bus until it is dropped
+pub struct FakePortal {
+    conn: blocking::Connection,
+    bound: Arc<Mutex<Option<Bound>>>,
+}
+
+impl FakePortal {
+    /// Take the portal name on the session bus
+    pub fn start() -> zbus::Result<Self> {
+        let bound = Arc::new(Mutex::new(None));
+        let conn = blocking::connection::Builder::session()?
+            .name("org.freedesktop.portal.Desktop")?
+            .serve_at(DESKTOP_PATH, GlobalShortcuts(bound.clone()))?
+            .build()?;
+        Ok(Self { conn, bound })
+    }
+
+    /// Press and release the shortcut `id`. Returns false if no shortcuts are bound yet.
+    pub fn activate(&self, id: &str) -> zbus::Result<bool> {
+        let Some(Bound { client, session }) = &*self.bound.lock().unwrap() else {
+            return Ok(false);
+        };
+        for signal in ["Activated", "Deactivated"] {
+            let timestamp = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            let body = (
+                ObjectPath::try_from(session.as_str())?,
+                id,
+                timestamp,
+                HashMap::<&str, Value>::new(),
+            );
+            self.conn.emit_signal(
+                Some(client.as_str()),
+                DESKTOP_PATH,
+                INTERFACE,
+                signal,
+                &body,
+            )?;
+            thread::sleep(Duration::from_millis(100));
+        }
+        Ok(true)
+    }
+}
+
+fn main() -> zbus::Result<()> {
+    let portal = FakePortal::start()?;
+    println!("Waiting for shortcuts to be bound, then type the name of one to activate it");
+
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        let id = line.trim();
+        if !id.is_empty() && !portal.activate(id)? {
+            println!("Nothing is bound yet");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,170 @@
+//! Reads the input devices for wf_overlay, and only tells it which actions were pressed.
+//!
+//! wf_overlay starts this with `backend = "helper"` in `[input]`, handing it one end of a Unix
+//! socket as stdin. This is the only process that needs to read the input devices, e.g. with
+//! `sudo chgrp input wf_input_helper && sudo chmod g+s wf_input_helper`. Events that don't
+//! complete a keybind are dropped here, so typed text never reaches the overlay.
+//!
+//! The keybinds come from [`HELPER_KEYBINDS_FILE`], which only root can change, not from whoever
+//! started the helper. Otherwise any process of the user could bind every key and read them all.
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::{
+        fd::AsFd,
+        unix::{fs::MetadataExt, net::UnixStream},
+    },
+    path::Path,
+    process::ExitCode,
+};
+
+use bevy::{
+    ecs::system::SystemState,
+    log::tracing_subscriber,
+    prelude::*,
+    tasks::{IoTaskPool, TaskPool},
+};
+use serde::Deserialize;
+use wf_input::{
+    actions::{Action, Keybinds},
+    buttons::{GlobalButtons, GlobalButtonsMut, InputButton},
+    devices::{InputMessage, start_watching},
+    protocol::{HELPER_KEYBINDS_FILE, HelperConfig, HelperMessage},
+};
+
+#[derive(Deserialize)]
+struct TrustedConfig {
+    #[serde(default)]
+    keybinds: Keybinds,
+}
+
+/// The keybinds from [`HELPER_KEYBINDS_FILE`], or the defaults if it doesn't exist
+fn trusted_keybinds() -> Result<Keybinds, String> {
+    let path = Path::new(HELPER_KEYBINDS_FILE);
+    let Ok(meta) = std::fs::metadata(path) else {
+        return Ok(Keybinds::default());
+    };
+    // the directory could be swapped out otherwise
+    for checked in [path, path.parent().unwrap_or(path)] {
+        let meta = std::fs::metadata(checked).map_err(|e| format!("{}: {e}", checked.display()))?;
+        if meta.uid() != 0 || meta.mode() & 0o022 != 0 {
+            return Err(format!(
+                "{} has to be owned by root and only writable by it",
+                checked.display()
+            ));
+        }
+    }
+    if !meta.is_file() {
+        return Err(format!("{HELPER_KEYBINDS_FILE} isn't a file"));
+    }
+    let src = std::fs::read_to_string(path).map_err(|e| format!("{HELPER_KEYBINDS_FILE}: {e}"))?;
+    let config: TrustedConfig =
+        toml_edit::de::from_str(&src).map_err(|e| format!("{HELPER_KEYBINDS_FILE}: {e}"))?;
+    Ok(config.keybinds)
+}
+
+fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+
+    let socket = match std::io::stdin().as_fd().try_clone_to_owned() {
+        Ok(fd) => UnixStream::from(fd),
+        Err(e) => {
+            error!("Can't use stdin: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if socket.local_addr().is_err() {
+        error!("wf_input_helper is started by wf_overlay, set backend = \"helper\" in [input]");
+        return ExitCode::FAILURE;
+    }
+
+    let mut reader = BufReader::new(&socket);
+    let mut line = String::new();
+    let config: HelperConfig = match reader
+        .read_line(&mut line)
+        .map_err(|e| e.to_string())
+        .and_then(|_| serde_json::from_str(&line).map_err(|e| e.to_string()))
+    {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Can't read the config sent by wf_overlay: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let keybinds = match trusted_keybinds() {
+        Ok(keybinds) => keybinds,
+        Err(e) => {
+            error!("Can't use the keybinds: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut line = serde_json::to_vec(&HelperMessage::Keybinds(keybinds.clone()))
+        .expect("messages can be serialized");
+    line.push(b'\n');
+    if (&socket).write_all(&line).is_err() {
+        return ExitCode::SUCCESS;
+    }
+
+    IoTaskPool::get_or_init(TaskPool::default);
+    let (tx, rx) = crossbeam_channel::unbounded();
+    start_watching(config.devices, tx);
+
+    // wf_overlay closed its end, usually because it exited
+    let closed = socket.try_clone().expect("could clone socket");
+    std::thread::spawn(move || {
+        let _ = std::io::copy(&mut BufReader::new(closed), &mut std::io::sink());
+        std::process::exit(0);
+    });
+
+    let mut world = World::new();
+    world.init_resource::<ButtonInput<KeyCode>>();
+    world.init_resource::<ButtonInput<MouseButton>>();
+    world.init_resource::<ButtonInput<GamepadButton>>();
+    world.init_resource::<ButtonInput<Action>>();
+    let mut write: SystemState<GlobalButtonsMut> = SystemState::new(&mut world);
+    let mut read: SystemState<(GlobalButtons, ResMut<ButtonInput<Action>>)> =
+        SystemState::new(&mut world);
+
+    // every event is a frame of its own
+    for message in rx {
+        let mut buttons = write.get_mut(&mut world);
+        buttons.keys.clear();
+        buttons.mouse.clear();
+        buttons.gamepad.clear();
+        let forward = match message {
+            InputMessage::Event(event) => {
+                for (button, pressed) in InputButton::changes(&event) {
+                    buttons.set(button, pressed);
+                }
+                None
+            }
+            InputMessage::Added { id, name } => Some(HelperMessage::Added { id, name }),
+            InputMessage::Removed { id } => {
+                buttons.release_all();
+                Some(HelperMessage::Removed { id })
+            }
+            InputMessage::Ready => Some(HelperMessage::Ready),
+            InputMessage::Action { .. } => None,
+        };
+
+        let (buttons, mut actions) = read.get_mut(&mut world);
+        actions.clear();
+        keybinds.update(&buttons, &mut actions);
+        let pressed = actions
+            .get_just_pressed()
+            .map(|a| HelperMessage::Pressed(*a));
+        let released = actions
+            .get_just_released()
+            .map(|a| HelperMessage::Released(*a));
+        for message in forward.into_iter().chain(pressed).chain(released) {
+            let mut line = serde_json::to_vec(&message).expect("messages can be serialized");
+            line.push(b'\n');
+            if (&socket).write_all(&line).is_err() {
+                return ExitCode::SUCCESS;
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
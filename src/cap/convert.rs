@@ -0,0 +1,417 @@
+//! Converting captured frames in any supported [`VideoFormat`] to RGBA
+use image::RgbaImage;
+
+use super::VideoFormat;
+
+/// Where the channels of a pixel are
+enum PixelLayout {
+    /// One byte per channel, with the index of each channel in the pixel
+    Bytes {
+        bpp: usize,
+        r: usize,
+        g: usize,
+        b: usize,
+        a: Option<usize>,
+    },
+    /// A little endian 32-bit word with 10-bit color and 2-bit alpha channels, with the shift of each channel
+    Packed10 {
+        r: u32,
+        g: u32,
+        b: u32,
+        a: Option<u32>,
+    },
+}
+
+impl PixelLayout {
+    fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelLayout::Bytes { bpp, .. } => *bpp,
+            PixelLayout::Packed10 { .. } => 4,
+        }
+    }
+
+    fn to_rgba(&self, px: &[u8]) -> [u8; 4] {
+        match *self {
+            PixelLayout::Bytes { r, g, b, a, .. } => {
+                [px[r], px[g], px[b], a.map_or(u8::MAX, |a| px[a])]
+            }
+            PixelLayout::Packed10 { r, g, b, a } => {
+                let word = u32::from_le_bytes([px[0], px[1], px[2], px[3]]);
+                // drop the two lowest bits of each 10 bit channel
+                let channel = |shift: u32| (((word >> shift) & 0x3ff) >> 2) as u8;
+                let alpha = a.map_or(u8::MAX, |shift| ((word >> shift) & 0b11) as u8 * 85);
+                [channel(r), channel(g), channel(b), alpha]
+            }
+        }
+    }
+}
+
+impl VideoFormat {
+    fn layout(&self) -> Option<PixelLayout> {
+        use PixelLayout::*;
+        let bytes = |r, g, b, a| Bytes { bpp: 4, r, g, b, a };
+        Some(match self {
+            VideoFormat::Bgra => bytes(2, 1, 0, Some(3)),
+            VideoFormat::Rgba => bytes(0, 1, 2, Some(3)),
+            VideoFormat::BGRx => bytes(2, 1, 0, None),
+            VideoFormat::RGBx => bytes(0, 1, 2, None),
+            VideoFormat::xRGB => bytes(1, 2, 3, None),
+            VideoFormat::xBGR => bytes(3, 2, 1, None),
+            VideoFormat::Argb => bytes(1, 2, 3, Some(0)),
+            VideoFormat::Abgr => bytes(3, 2, 1, Some(0)),
+            VideoFormat::Rgb => Bytes {
+                bpp: 3,
+                r: 0,
+                g: 1,
+                b: 2,
+                a: None,
+            },
+            VideoFormat::Bgr => Bytes {
+                bpp: 3,
+                r: 2,
+                g: 1,
+                b: 0,
+                a: None,
+            },
+            VideoFormat::xRGB_210LE => Packed10 {
+                r: 20,
+                g: 10,
+                b: 0,
+                a: None,
+            },
+            VideoFormat::xBGR_210LE => Packed10 {
+                r: 0,
+                g: 10,
+                b: 20,
+                a: None,
+            },
+            VideoFormat::RGBx_102LE => Packed10 {
+                r: 22,
+                g: 12,
+                b: 2,
+                a: None,
+            },
+            VideoFormat::BGRx_102LE => Packed10 {
+                r: 2,
+                g: 12,
+                b: 22,
+                a: None,
+            },
+            VideoFormat::ARGB_210LE => Packed10 {
+                r: 20,
+                g: 10,
+                b: 0,
+                a: Some(30),
+            },
+            VideoFormat::ABGR_210LE => Packed10 {
+                r: 0,
+                g: 10,
+                b: 20,
+                a: Some(30),
+            },
+            VideoFormat::RGBA_102LE => Packed10 {
+                r: 22,
+                g: 12,
+                b: 2,
+                a: Some(0),
+            },
+            VideoFormat::BGRA_102LE => Packed10 {
+                r: 2,
+                g: 12,
+                b: 22,
+                a: Some(0),
+            },
+            VideoFormat::Other(_) => return None,
+        })
+    }
+
+    /// Size of a pixel in bytes, if the format is supported
+    pub fn bytes_per_pixel(&self) -> Option<usize> {
+        self.layout().map(|l| l.bytes_per_pixel())
+    }
+}
+
+/// The part of a buffer the chunk says contains the frame
+pub fn chunk_slice(mem: &[u8], offset: usize, size: usize) -> &[u8] {
+    let end = if size == 0 {
+        mem.len()
+    } else {
+        (offset + size).min(mem.len())
+    };
+    mem.get(offset..end).unwrap_or_default()
+}
+
+// optimizes very well. see: https://github.com/image-rs/image/pull/2712
+pub fn from_raw_bgra(width: u32, height: u32, container: Vec<u8>) -> Option<RgbaImage> {
+    let mut img = RgbaImage::from_raw(width, height, container)?;
+
+    let (chunked, _) = img.as_chunks_mut::<4>();
+
+    for p in chunked {
+        let bgra = u32::from_be_bytes(*p);
+        let argb = bgra.swap_bytes();
+        let rgba = argb.rotate_left(8);
+        *p = rgba.to_be_bytes();
+    }
+
+    Some(img)
+}
+
+/// Convert a frame with rows `stride` bytes apart to RGBA.
+///
+/// Tightly packed 8-bit RGBA and BGRA frames are converted in place, everything else is copied.
+pub fn to_rgba(
+    format: &VideoFormat,
+    width: u32,
+    height: u32,
+    stride: usize,
+    mut data: Vec<u8>,
+) -> Option<RgbaImage> {
+    let layout = format.layout()?;
+    let row_len = width as usize * layout.bytes_per_pixel();
+    if stride < row_len || height == 0 {
+        return None;
+    }
+    let needed = stride * (height as usize - 1) + row_len;
+    if data.len() < needed {
+        return None;
+    }
+
+    if stride == row_len {
+        match format {
+            VideoFormat::Bgra => {
+                data.truncate(needed);
+                return from_raw_bgra(width, height, data);
+            }
+            VideoFormat::BGRx => {
+                data.truncate(needed);
+                let mut img = from_raw_bgra(width, height, data)?;
+                img.pixels_mut().for_each(|p| p[3] = u8::MAX);
+                return Some(img);
+            }
+            VideoFormat::Rgba => {
+                data.truncate(needed);
+                return RgbaImage::from_raw(width, height, data);
+            }
+            VideoFormat::RGBx => {
+                data.truncate(needed);
+                let mut img = RgbaImage::from_raw(width, height, data)?;
+                img.pixels_mut().for_each(|p| p[3] = u8::MAX);
+                return Some(img);
+            }
+            _ => {}
+        }
+    }
+
+    let bpp = layout.bytes_per_pixel();
+    let mut out = Vec::with_capacity(width as usize * height as usize * 4);
+    for row in data.chunks(stride).take(height as usize) {
+        for px in row[..row_len].chunks_exact(bpp) {
+            out.extend_from_slice(&layout.to_rgba(px));
+        }
+    }
+    RgbaImage::from_raw(width, height, out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Expected pixels of the 2x2 test frame, in RGB
+    const PIXELS: [[u8; 3]; 4] = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [0x12, 0x34, 0x56]];
+    /// Bytes before the frame in the buffer, where the chunk starts
+    const OFFSET: usize = 5;
+    /// Bytes after each row
+    const PADDING: usize = 3;
+
+    /// A buffer with the frame at `OFFSET`, rows padded by `PADDING` bytes of garbage.
+    /// Returns it and the stride.
+    fn buffer(encode: impl Fn([u8; 3]) -> Vec<u8>) -> (Vec<u8>, usize) {
+        let rows: Vec<Vec<u8>> = PIXELS
+            .chunks(2)
+            .map(|row| row.iter().flat_map(|&px| encode(px)).collect())
+            .collect();
+        let stride = rows[0].len() + PADDING;
+        let mut buf = vec![0xAA; OFFSET];
+        for row in rows {
+            buf.extend_from_slice(&row);
+            buf.extend_from_slice(&[0xAA; PADDING]);
+        }
+        (buf, stride)
+    }
+
+    fn convert(format: VideoFormat, encode: impl Fn([u8; 3]) -> Vec<u8>) -> RgbaImage {
+        let (buf, stride) = buffer(encode);
+        let chunk = chunk_slice(&buf, OFFSET, buf.len() - OFFSET);
+        to_rgba(&format, 2, 2, stride, chunk.to_vec()).expect("frame converts")
+    }
+
+    fn assert_pixels(img: &RgbaImage) {
+        assert_pixels_with_alpha(img, u8::MAX);
+    }
+
+    fn assert_pixels_with_alpha(img: &RgbaImage, alpha: u8) {
+        let expected: Vec<[u8; 4]> = PIXELS.iter().map(|&[r, g, b]| [r, g, b, alpha]).collect();
+        let actual: Vec<[u8; 4]> = img.pixels().map(|p| p.0).collect();
+        assert_eq!(actual, expected);
+    }
+
+    /// The frame tightly packed, after `OFFSET` bytes of garbage
+    fn convert_packed(format: VideoFormat, encode: impl Fn([u8; 3]) -> Vec<u8>) -> RgbaImage {
+        let data: Vec<u8> = PIXELS.iter().flat_map(|&px| encode(px)).collect();
+        let stride = data.len() / 2;
+        let mut buf = vec![0xAA; OFFSET];
+        buf.extend_from_slice(&data);
+        let chunk = chunk_slice(&buf, OFFSET, data.len());
+        to_rgba(&format, 2, 2, stride, chunk.to_vec()).expect("frame converts")
+    }
+
+    /// A little endian word with 10-bit channels at the shifts, and the padding bits set
+    fn packed10([r, g, b]: [u8; 3], shifts: [u32; 3], padding: u32) -> Vec<u8> {
+        // the lowest two bits are dropped when converting, so they shouldn't matter
+        let channel = |v: u8| ((v as u32) << 2) | 0b11;
+        let word =
+            (channel(r) << shifts[0]) | (channel(g) << shifts[1]) | (channel(b) << shifts[2]);
+        (word | padding).to_le_bytes().to_vec()
+    }
+
+    #[test]
+    fn bgra() {
+        let encode = |[r, g, b]: [u8; 3]| vec![b, g, r, 0x80];
+        assert_pixels_with_alpha(&convert(VideoFormat::Bgra, encode), 0x80);
+        assert_pixels_with_alpha(&convert_packed(VideoFormat::Bgra, encode), 0x80);
+    }
+
+    #[test]
+    fn rgba() {
+        let encode = |[r, g, b]: [u8; 3]| vec![r, g, b, 0x80];
+        assert_pixels_with_alpha(&convert(VideoFormat::Rgba, encode), 0x80);
+        assert_pixels_with_alpha(&convert_packed(VideoFormat::Rgba, encode), 0x80);
+    }
+
+    #[test]
+    fn argb() {
+        let encode = |[r, g, b]: [u8; 3]| vec![0x80, r, g, b];
+        assert_pixels_with_alpha(&convert(VideoFormat::Argb, encode), 0x80);
+    }
+
+    #[test]
+    fn abgr() {
+        let encode = |[r, g, b]: [u8; 3]| vec![0x80, b, g, r];
+        assert_pixels_with_alpha(&convert(VideoFormat::Abgr, encode), 0x80);
+    }
+
+    #[test]
+    fn xrgb() {
+        assert_pixels(&convert(VideoFormat::xRGB, |[r, g, b]| vec![0x7F, r, g, b]));
+    }
+
+    #[test]
+    fn xbgr() {
+        assert_pixels(&convert(VideoFormat::xBGR, |[r, g, b]| vec![0x7F, b, g, r]));
+    }
+
+    #[test]
+    fn bgrx() {
+        assert_pixels(&convert(VideoFormat::BGRx, |[r, g, b]| vec![b, g, r, 0x7F]));
+    }
+
+    #[test]
+    fn rgbx() {
+        assert_pixels(&convert(VideoFormat::RGBx, |[r, g, b]| vec![r, g, b, 0x7F]));
+    }
+
+    #[test]
+    fn bgrx_tightly_packed() {
+        let encode = |[r, g, b]: [u8; 3]| vec![b, g, r, 0x7F];
+        assert_pixels(&convert_packed(VideoFormat::BGRx, encode));
+    }
+
+    #[test]
+    fn rgbx_tightly_packed() {
+        let encode = |[r, g, b]: [u8; 3]| vec![r, g, b, 0x7F];
+        assert_pixels(&convert_packed(VideoFormat::RGBx, encode));
+    }
+
+    #[test]
+    fn rgb() {
+        assert_pixels(&convert(VideoFormat::Rgb, |[r, g, b]| vec![r, g, b]));
+    }
+
+    #[test]
+    fn bgr() {
+        assert_pixels(&convert(VideoFormat::Bgr, |[r, g, b]| vec![b, g, r]));
+    }
+
+    #[test]
+    fn xrgb_210le() {
+        let encode = |px| packed10(px, [20, 10, 0], 0b11 << 30);
+        assert_pixels(&convert(VideoFormat::xRGB_210LE, encode));
+    }
+
+    #[test]
+    fn xbgr_210le() {
+        let encode = |px| packed10(px, [0, 10, 20], 0b11 << 30);
+        assert_pixels(&convert(VideoFormat::xBGR_210LE, encode));
+    }
+
+    #[test]
+    fn rgbx_102le() {
+        let encode = |px| packed10(px, [22, 12, 2], 0b11);
+        assert_pixels(&convert(VideoFormat::RGBx_102LE, encode));
+    }
+
+    #[test]
+    fn bgrx_102le() {
+        let encode = |px| packed10(px, [2, 12, 22], 0b11);
+        assert_pixels(&convert(VideoFormat::BGRx_102LE, encode));
+    }
+
+    /// 2-bit alpha of 0b10 is 170 in 8 bits
+    const ALPHA_10: u8 = 170;
+
+    #[test]
+    fn argb_210le() {
+        let encode = |px| packed10(px, [20, 10, 0], 0b10 << 30);
+        assert_pixels_with_alpha(&convert(VideoFormat::ARGB_210LE, encode), ALPHA_10);
+    }
+
+    #[test]
+    fn abgr_210le() {
+        let encode = |px| packed10(px, [0, 10, 20], 0b10 << 30);
+        assert_pixels_with_alpha(&convert(VideoFormat::ABGR_210LE, encode), ALPHA_10);
+    }
+
+    #[test]
+    fn rgba_102le() {
+        let encode = |px| packed10(px, [22, 12, 2], 0b10);
+        assert_pixels_with_alpha(&convert(VideoFormat::RGBA_102LE, encode), ALPHA_10);
+    }
+
+    #[test]
+    fn bgra_102le() {
+        let encode = |px| packed10(px, [2, 12, 22], 0b10);
+        assert_pixels_with_alpha(&convert(VideoFormat::BGRA_102LE, encode), ALPHA_10);
+    }
+
+    #[test]
+    fn from_raw_bgra_swaps_channels() {
+        let img = from_raw_bgra(1, 1, vec![0x56, 0x34, 0x12, 0x80]).unwrap();
+        assert_eq!(img.get_pixel(0, 0).0, [0x12, 0x34, 0x56, 0x80]);
+    }
+
+    #[test]
+    fn unknown_format_is_rejected() {
+        let format = VideoFormat::Other("NV12".to_string());
+        assert!(to_rgba(&format, 1, 1, 4, vec![0; 4]).is_none());
+    }
+
+    #[test]
+    fn short_buffer_is_rejected() {
+        let (buf, stride) = buffer(|[r, g, b]| vec![r, g, b]);
+        let chunk = chunk_slice(&buf, OFFSET, buf.len() - OFFSET);
+        // the last row is cut short
+        let short = chunk[..chunk.len() - PADDING - 1].to_vec();
+        assert!(to_rgba(&VideoFormat::Rgb, 2, 2, stride, short).is_none());
+    }
+}
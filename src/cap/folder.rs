@@ -0,0 +1,170 @@
+//! Replays the PNG files in a directory as frames, for testing without a game
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+
+use super::{CaptureSource, CaptureState, FrameSink, ScreencastMeta, VideoFormat};
+
+pub struct FolderSource {
+    /// A directory of PNGs, or a single PNG
+    pub path: PathBuf,
+    pub interval: Duration,
+    /// Start over after the last image
+    pub repeat: bool,
+}
+
+impl CaptureSource for FolderSource {
+    fn start(self: Box<Self>, sink: FrameSink) {
+        std::thread::Builder::new()
+            .name("folder capture".to_string())
+            .spawn(move || {
+                if let Err(e) = self.run(&sink) {
+                    error!("Folder capture failed: {e}");
+                    sink.send_status(CaptureState::Failed, Some(e.to_string()));
+                }
+            })
+            .expect("could spawn thread");
+    }
+}
+
+impl FolderSource {
+    fn images(&self) -> std::io::Result<Vec<PathBuf>> {
+        if self.path.is_file() {
+            return Ok(vec![self.path.clone()]);
+        }
+        let mut images: Vec<PathBuf> = std::fs::read_dir(&self.path)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+            })
+            .collect();
+        // screenshots are named by timestamp, so this replays them in order
+        images.sort();
+        Ok(images)
+    }
+
+    fn run(&self, sink: &FrameSink) -> anyhow::Result<()> {
+        let images = self.images()?;
+        if images.is_empty() {
+            anyhow::bail!("No PNG files found in {}", self.path.display());
+        }
+        sink.send_status(CaptureState::Streaming, None);
+        let mut size = (0, 0);
+        let mut last_copy = None;
+        loop {
+            for path in &images {
+                // the image is "on screen" for one interval, but only loaded if a frame is wanted
+                if !sink.wants_frame(last_copy) {
+                    std::thread::sleep(self.interval);
+                    continue;
+                }
+                let img = match image::open(path) {
+                    Ok(img) => img.into_rgba8(),
+                    Err(e) => {
+                        warn!("Skipping {}: {e}", path.display());
+                        continue;
+                    }
+                };
+                debug!("Replaying {}", path.display());
+                if img.dimensions() != size {
+                    size = img.dimensions();
+                    sink.send_meta(ScreencastMeta {
+                        width: size.0,
+                        height: size.1,
+                        format: VideoFormat::Rgba,
+                        crop: None,
+                        area: None,
+                    });
+                }
+                let stride = img.width() as usize * 4;
+                sink.send_frame(img.into_raw(), stride, Instant::now(), None);
+                last_copy = Some(Instant::now());
+                std::thread::sleep(self.interval);
+            }
+            if !self.repeat {
+                info!("Replayed all images in {}", self.path.display());
+                // no more frames are coming, so triggering can't work anymore
+                sink.send_status(
+                    CaptureState::Failed,
+                    Some(format!("Replay of {} finished", self.path.display())),
+                );
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::state::app::StatesPlugin;
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+    use crate::cap::{CaptureArea, LatestImage, capture_channels, receive_frames};
+
+    #[test]
+    fn png_reaches_latest_image() {
+        let dir = std::env::temp_dir().join(format!("wf_overlay_folder_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let expected =
+            RgbaImage::from_fn(4, 3, |x, y| Rgba([x as u8 * 60, y as u8 * 80, 200, 255]));
+        expected.save(dir.join("capture.png")).unwrap();
+
+        let (sink, receiver, latest) = capture_channels();
+        receiver.request_frame();
+        let mut app = App::new();
+        app.add_plugins(StatesPlugin)
+            .init_state::<CaptureState>()
+            .init_resource::<CaptureArea>()
+            .insert_resource(receiver)
+            .insert_resource(latest)
+            .add_systems(Update, receive_frames);
+        Box::new(FolderSource {
+            path: dir.clone(),
+            interval: Duration::from_millis(10),
+            repeat: false,
+        })
+        .start(sink);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let frame = loop {
+            app.update();
+            let mut latest = app.world_mut().resource_mut::<LatestImage>();
+            // the meta may arrive an update after the frame
+            if latest.latest_info().is_some() && latest.capture_size() != UVec2::ZERO {
+                break latest.get_latest_rgba();
+            }
+            assert!(Instant::now() < deadline, "no frame was received");
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(frame, Some(expected));
+    }
+
+    #[test]
+    fn end_of_replay_is_reported() {
+        let dir = std::env::temp_dir().join(format!("wf_overlay_replay_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        RgbaImage::new(2, 2).save(dir.join("capture.png")).unwrap();
+
+        let (sink, receiver, _latest) = capture_channels();
+        Box::new(FolderSource {
+            path: dir.clone(),
+            interval: Duration::from_millis(1),
+            repeat: false,
+        })
+        .start(sink);
+
+        let timeout = Duration::from_secs(5);
+        let streaming = receiver.status.recv_timeout(timeout).unwrap();
+        assert_eq!(streaming.state, CaptureState::Streaming);
+        let finished = receiver.status.recv_timeout(timeout).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(finished.state, CaptureState::Failed);
+        assert!(finished.message.unwrap().contains("finished"));
+    }
+}
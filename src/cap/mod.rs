@@ -0,0 +1,528 @@
+//! Screen capture: a [`CaptureSource`] feeds frames into [`LatestImage`]
+//!
+//! Frames are only copied out of the source when they are wanted: once after
+//! [`ScreencastReceiver::request_frame`], or continuously while
+//! [`ScreencastReceiver::set_continuous`] is enabled.
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, Sender, bounded};
+use image::RgbaImage;
+
+use crate::{
+    ERROR_COLOR, PlatOverlayPhase, STATUS_COLOR,
+    config::{CaptureBackend, ConfigManager},
+};
+
+mod convert;
+#[cfg(feature = "folder")]
+mod folder;
+#[cfg(feature = "portal")]
+mod portal;
+mod transform;
+mod trigger;
+#[cfg(feature = "x11")]
+mod x11;
+
+pub use transform::CaptureTransform;
+pub use trigger::TriggerCapture;
+
+/// Plugin for capturing the screen, with the source selected in the config
+pub struct CapturePlugin;
+
+impl Plugin for CapturePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LatestImage>()
+            .init_resource::<CaptureMessage>()
+            .init_resource::<TriggerCapture>()
+            .init_resource::<CaptureTransform>()
+            .init_resource::<CaptureArea>()
+            .init_state::<CaptureState>()
+            .add_systems(Startup, (setup_capture, setup_status_text))
+            .add_systems(OnEnter(PlatOverlayPhase::Ocr), trigger::start_trigger)
+            .add_systems(
+                Update,
+                (
+                    receive_status,
+                    receive_frames,
+                    transform::update_capture_transform,
+                    trigger::collect_trigger_frames.run_if(in_state(PlatOverlayPhase::Ocr)),
+                    update_status_text.run_if(
+                        state_changed::<CaptureState>
+                            .or(resource_changed::<CaptureMessage>)
+                            .or(resource_changed::<ConfigManager>),
+                    ),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Something which produces frames, like a screencast or a folder of screenshots
+pub trait CaptureSource: Send + 'static {
+    /// Start producing frames into `sink`, in the background.
+    fn start(self: Box<Self>, sink: FrameSink);
+}
+
+/// How often frames are copied while continuous capture is enabled
+const CONTINUOUS_INTERVAL: Duration = Duration::from_millis(250);
+/// How many frame buffers are kept around for reuse
+const POOL_SIZE: usize = 2;
+
+/// How capture is doing, as reported by the source
+#[derive(States, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum CaptureState {
+    /// Waiting for the source, e.g. for the user to pick a screen
+    #[default]
+    Connecting,
+    Streaming,
+    /// A frame was requested, but none arrived in time
+    Stalled,
+    /// Capture stopped and won't recover without a restart
+    Failed,
+}
+
+/// Where the captured monitor or window is on the desktop, in compositor coordinates
+#[derive(Resource, Default, PartialEq)]
+pub struct CaptureArea(pub Option<IRect>);
+
+/// Details about the current [`CaptureState`] for the user, like why it failed
+#[derive(Resource, Default)]
+pub struct CaptureMessage(pub Option<String>);
+
+struct CaptureStatus {
+    state: CaptureState,
+    message: Option<String>,
+}
+
+/// Which frames the app currently wants, shared between the app and the capture source
+#[derive(Default)]
+struct CaptureControl {
+    /// A single frame was requested and not yet delivered
+    requested: AtomicBool,
+    continuous: AtomicBool,
+}
+
+/// The sending half of the frame channels, which only ever hold the latest frame and meta
+#[derive(Clone)]
+pub struct FrameSink {
+    frames: Sender<ScreencastFrame>,
+    frames_rx: Receiver<ScreencastFrame>,
+    meta: Sender<ScreencastMeta>,
+    meta_rx: Receiver<ScreencastMeta>,
+    status: Sender<CaptureStatus>,
+    seq: Arc<AtomicU64>,
+    pool: Receiver<Vec<u8>>,
+    recycle: Sender<Vec<u8>>,
+    control: Arc<CaptureControl>,
+}
+
+impl FrameSink {
+    /// Whether the next frame should be copied, given when the last one was.
+    ///
+    /// Sources should check this before copying anything, so idle capture costs next to nothing.
+    pub fn wants_frame(&self, last_copy: Option<Instant>) -> bool {
+        if self.control.requested.load(Ordering::Acquire) {
+            return true;
+        }
+        self.control.continuous.load(Ordering::Acquire)
+            && last_copy.is_none_or(|last| last.elapsed() >= CONTINUOUS_INTERVAL)
+    }
+
+    /// Whether frames are wanted right now, so sources which can should produce them faster
+    pub fn is_active(&self) -> bool {
+        self.control.requested.load(Ordering::Acquire)
+            || self.control.continuous.load(Ordering::Acquire)
+    }
+
+    /// Copy `data` into a pooled buffer and send it
+    pub fn send_frame_from(
+        &self,
+        data: &[u8],
+        stride: usize,
+        captured: Instant,
+        pts: Option<Duration>,
+    ) {
+        let mut buf = self.pool.try_recv().unwrap_or_default();
+        buf.clear();
+        buf.extend_from_slice(data);
+        self.send_frame(buf, stride, captured, pts);
+    }
+
+    /// Send a frame whose rows start `stride` bytes apart, showing the screen at `captured`
+    pub fn send_frame(&self, data: Vec<u8>, stride: usize, captured: Instant, pts: Option<Duration>) {
+        // drain first, so the channel only ever contains the newest frame
+        while let Ok(old) = self.frames_rx.try_recv() {
+            let _ = self.recycle.try_send(old.data);
+        }
+        let info = FrameInfo {
+            seq: self.seq.fetch_add(1, Ordering::Relaxed),
+            pts,
+            captured,
+        };
+        let _ = self.frames.send(ScreencastFrame { data, stride, info });
+        self.control.requested.store(false, Ordering::Release);
+    }
+
+    pub fn send_status(&self, state: CaptureState, message: Option<String>) {
+        let _ = self.status.send(CaptureStatus { state, message });
+    }
+
+    pub fn send_meta(&self, meta: ScreencastMeta) {
+        while self.meta_rx.try_recv().is_ok() {}
+        let _ = self.meta.send(meta);
+    }
+}
+
+#[derive(Resource)]
+pub struct ScreencastReceiver {
+    frames: Receiver<ScreencastFrame>,
+    meta: Receiver<ScreencastMeta>,
+    status: Receiver<CaptureStatus>,
+    control: Arc<CaptureControl>,
+}
+impl ScreencastReceiver {
+    /// Ask the source for a single new frame
+    pub fn request_frame(&self) {
+        self.control.requested.store(true, Ordering::Release);
+    }
+
+    /// Receive frames regularly until disabled, for watching the screen
+    pub fn set_continuous(&self, continuous: bool) {
+        self.control.continuous.store(continuous, Ordering::Release);
+    }
+}
+
+/// A captured screencast frame
+#[derive(Clone)]
+pub struct ScreencastFrame {
+    data: Vec<u8>,
+    /// Bytes from the start of one row to the next, rows may be padded
+    stride: usize,
+    info: FrameInfo,
+}
+
+/// When a frame was captured
+#[derive(Clone, Copy, Debug)]
+pub struct FrameInfo {
+    /// Counts up with every frame sent by the source
+    pub seq: u64,
+    /// PipeWire presentation timestamp, if the source has one
+    pub pts: Option<Duration>,
+    /// When the screen looked like this, derived from the PTS if there is one
+    pub captured: Instant,
+}
+
+#[derive(Clone, Default)]
+pub struct ScreencastMeta {
+    pub width: u32,
+    pub height: u32,
+    pub format: VideoFormat,
+    /// The part of the frame with content, if the source only fills part of its buffers
+    pub crop: Option<URect>,
+    /// Where the captured monitor or window is on the desktop, in compositor coordinates, if the source knows
+    pub area: Option<IRect>,
+}
+
+impl ScreencastMeta {
+    /// The part of the frame to use, the whole frame if the crop region doesn't fit
+    fn content(&self) -> URect {
+        let full = URect::new(0, 0, self.width, self.height);
+        self.crop
+            .map(|crop| crop.intersect(full))
+            .filter(|crop| !crop.is_empty())
+            .unwrap_or(full)
+    }
+}
+
+/// Pixel formats, named like the PipeWire formats they come from
+#[derive(Clone, Debug, Default)]
+// only the portal negotiates all of these
+#[cfg_attr(not(feature = "portal"), allow(dead_code))]
+#[allow(non_camel_case_types)]
+pub enum VideoFormat {
+    #[default]
+    Bgra,
+    Rgba,
+    BGRx,
+    RGBx,
+    xRGB,
+    xBGR,
+    Argb,
+    Abgr,
+    Rgb,
+    Bgr,
+    xRGB_210LE,
+    xBGR_210LE,
+    RGBx_102LE,
+    BGRx_102LE,
+    ARGB_210LE,
+    ABGR_210LE,
+    RGBA_102LE,
+    BGRA_102LE,
+    Other(String),
+}
+
+fn make_source(backend: &CaptureBackend) -> Option<Box<dyn CaptureSource>> {
+    match backend {
+        #[cfg(feature = "portal")]
+        CaptureBackend::Portal { window } => {
+            Some(Box::new(portal::PortalSource::from_disk_or_default(*window)))
+        }
+        #[cfg(feature = "x11")]
+        CaptureBackend::X11 { display, interval } => Some(Box::new(x11::X11Source {
+            display: display.clone(),
+            interval: std::time::Duration::from_secs_f32(*interval),
+        })),
+        #[cfg(feature = "folder")]
+        CaptureBackend::Folder {
+            path,
+            interval,
+            repeat,
+        } => Some(Box::new(folder::FolderSource {
+            path: path.clone(),
+            interval: std::time::Duration::from_secs_f32(*interval),
+            repeat: *repeat,
+        })),
+        #[allow(unreachable_patterns)]
+        other => {
+            error!(
+                "Capture source {} is not available, wf_overlay was built without the `{}` feature",
+                other.name(),
+                other.name()
+            );
+            None
+        }
+    }
+}
+
+/// The channels between a source and the app, with the [`LatestImage`] frames are received into
+fn capture_channels() -> (FrameSink, ScreencastReceiver, LatestImage) {
+    let (tx, rx) = bounded(1);
+    let (tx_m, rx_m) = bounded(1);
+    let (tx_pool, rx_pool) = bounded(POOL_SIZE);
+    let (tx_s, rx_s) = crossbeam_channel::unbounded();
+    let control = Arc::new(CaptureControl::default());
+    let receiver = ScreencastReceiver {
+        frames: rx.clone(),
+        meta: rx_m.clone(),
+        status: rx_s,
+        control: control.clone(),
+    };
+    let latest = LatestImage {
+        recycle: Some(tx_pool.clone()),
+        ..default()
+    };
+    let sink = FrameSink {
+        frames: tx,
+        frames_rx: rx,
+        meta: tx_m,
+        meta_rx: rx_m,
+        status: tx_s,
+        seq: default(),
+        pool: rx_pool,
+        recycle: tx_pool,
+        control,
+    };
+    (sink, receiver, latest)
+}
+
+/// Setup the configured capture source
+fn setup_capture(conf: Res<ConfigManager>, mut commands: Commands) {
+    let (sink, receiver, latest) = capture_channels();
+    commands.insert_resource(receiver);
+    commands.insert_resource(latest);
+
+    if let Some(source) = make_source(&conf.capture) {
+        info!("Starting {} capture", conf.capture.name());
+        source.start(sink);
+    } else {
+        sink.send_status(
+            CaptureState::Failed,
+            Some(format!(
+                "wf_overlay was built without the `{}` feature",
+                conf.capture.name()
+            )),
+        );
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct LatestImage {
+    frame: Vec<u8>,
+    stride: usize,
+    info: Option<FrameInfo>,
+    meta: ScreencastMeta,
+    /// Returns used buffers to the source's pool
+    recycle: Option<Sender<Vec<u8>>>,
+}
+impl LatestImage {
+    fn set_latest_img(&mut self, img: ScreencastFrame) {
+        let old = std::mem::replace(&mut self.frame, img.data);
+        self.stride = img.stride;
+        self.info = Some(img.info);
+        self.recycle(old);
+    }
+    fn set_latest_meta(&mut self, meta: ScreencastMeta) {
+        self.meta = meta;
+    }
+    /// Drop the current frame, so only frames received afterwards are used
+    fn clear(&mut self) {
+        let old = std::mem::take(&mut self.frame);
+        self.recycle(old);
+    }
+    fn recycle(&self, buf: Vec<u8>) {
+        if buf.capacity() > 0
+            && let Some(recycle) = &self.recycle
+        {
+            let _ = recycle.try_send(buf);
+        }
+    }
+    /// Give an image which is no longer needed back, so its buffer can be reused for a later frame
+    pub fn recycle_image(&self, img: RgbaImage) {
+        self.recycle(img.into_raw());
+    }
+    /// Info about the latest frame, if there is one which wasn't taken yet
+    pub fn latest_info(&self) -> Option<FrameInfo> {
+        self.info.filter(|_| !self.frame.is_empty())
+    }
+    /// Take the latest frame, if there is one which wasn't taken yet
+    pub fn get_latest_rgba(&mut self) -> Option<RgbaImage> {
+        if self.frame.len() < 4 {
+            return None;
+        }
+        let ScreencastMeta {
+            width,
+            height,
+            ref format,
+            ..
+        } = self.meta;
+        if let VideoFormat::Other(f) = format {
+            error_once!("Unknown Screencast image format {f}");
+            return None;
+        }
+        let mut frame = std::mem::take(&mut self.frame);
+        let content = self.meta.content();
+        if content.min != UVec2::ZERO {
+            // the rows keep their stride, so only the start of the first row has to go
+            let bpp = format.bytes_per_pixel()?;
+            let start = content.min.y as usize * self.stride + content.min.x as usize * bpp;
+            if start >= frame.len() {
+                warn!("Frame is too small for its crop region {content:?}");
+                self.recycle(frame);
+                return None;
+            }
+            frame.drain(..start);
+        }
+        let img = convert::to_rgba(format, content.width(), content.height(), self.stride, frame);
+        if img.is_none() {
+            warn!("Frame does not match its format {format:?} {width}x{height}");
+        }
+        img
+    }
+    /// Size of the frames after cropping, the space [`Item::capture_bounds`](crate::ocr::Item) are in
+    pub fn capture_size(&self) -> UVec2 {
+        self.meta.content().size()
+    }
+}
+
+fn receive_status(
+    receiver: Res<ScreencastReceiver>,
+    mut next: ResMut<NextState<CaptureState>>,
+    mut message: ResMut<CaptureMessage>,
+) {
+    for status in receiver.status.try_iter() {
+        info!(
+            "Capture {:?}{}",
+            status.state,
+            status
+                .message
+                .as_deref()
+                .map(|m| format!(": {m}"))
+                .unwrap_or_default()
+        );
+        next.set(status.state);
+        message.0 = status.message;
+    }
+}
+
+/// Capture status in the top left corner, hidden while capture works
+#[derive(Component)]
+struct CaptureStatusText;
+
+fn setup_status_text(mut commands: Commands, conf: Res<ConfigManager>) {
+    commands.spawn((
+        CaptureStatusText,
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(12.),
+            left: Val::Px(12.),
+            ..default()
+        },
+        Text::default(),
+        TextFont::from_font_size(conf.font_size * 0.75),
+        TextColor(STATUS_COLOR),
+        TextShadow::default(),
+    ));
+}
+
+fn update_status_text(
+    state: Res<State<CaptureState>>,
+    message: Res<CaptureMessage>,
+    conf: Res<ConfigManager>,
+    mut text: Single<
+        (&mut Text, &mut TextColor, &mut Visibility, &mut TextFont),
+        With<CaptureStatusText>,
+    >,
+) {
+    text.3.font_size = conf.font_size * 0.75;
+    let (title, color) = match state.get() {
+        CaptureState::Streaming => {
+            *text.2 = Visibility::Hidden;
+            return;
+        }
+        CaptureState::Connecting => ("Waiting for screen capture", STATUS_COLOR),
+        CaptureState::Stalled => ("Screen capture stalled", ERROR_COLOR),
+        CaptureState::Failed => ("Screen capture failed", ERROR_COLOR),
+    };
+    text.0.0 = match &message.0 {
+        Some(message) => format!("{title}\n{message}"),
+        None => title.to_string(),
+    };
+    text.1.0 = color;
+    *text.2 = Visibility::Inherited;
+}
+
+/// System to receive frames from the channel and update the resource
+fn receive_frames(
+    receiver_res: Res<ScreencastReceiver>,
+    mut img: ResMut<LatestImage>,
+    mut area: ResMut<CaptureArea>,
+    state: Res<State<CaptureState>>,
+    mut next: ResMut<NextState<CaptureState>>,
+) {
+    // Try to receive frames in a non-blocking way
+    // Try to receive the latest frame (non-blocking)
+    if let Ok(meta) = receiver_res.meta.try_recv() {
+        info!(
+            "Frame meta changed: {}x{} ({:?})",
+            meta.width, meta.height, meta.format
+        );
+        area.set_if_neq(CaptureArea(meta.area));
+        img.set_latest_meta(meta);
+        // Here you would update your texture/image resource
+        // For example, you could convert this to a Bevy Image and update a texture
+    }
+    if let Ok(frame) = receiver_res.frames.try_recv() {
+        img.set_latest_img(frame);
+        if *state.get() == CaptureState::Stalled {
+            next.set(CaptureState::Streaming);
+        }
+    }
+}
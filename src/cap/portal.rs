@@ -0,0 +1,677 @@
+//! xdg-desktop-portal screencast, streamed over PipeWire
+use ashpd::desktop::{
+    PersistMode, ResponseError,
+    screencast::{CursorMode, Screencast, SourceType, Stream},
+};
+use bevy::prelude::*;
+use bevy::tasks::block_on;
+use pipewire as pw;
+use pw::{properties::properties, spa};
+use std::{
+    fs,
+    os::fd::{AsRawFd, OwnedFd},
+    path::PathBuf,
+    ptr::NonNull,
+    time::{Duration, Instant},
+};
+
+use super::{
+    CaptureSource, CaptureState, FrameSink, ScreencastMeta, VideoFormat, convert::chunk_slice,
+};
+use crate::paths;
+
+/// Wait before reconnecting, doubled after every failed attempt
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Screencast through the desktop portal, restoring the previous session if possible
+pub struct PortalSource {
+    /// Session token for restoring the session
+    pub restore_token: Option<String>,
+    /// Capture a single window instead of a monitor
+    pub window: bool,
+}
+impl PortalSource {
+    /// Where the restore token is kept, separately for each source type so they don't restore each other
+    fn file(window: bool) -> PathBuf {
+        paths::screencast_session_file(window)
+    }
+    pub fn from_disk_or_default(window: bool) -> Self {
+        Self {
+            restore_token: fs::read_to_string(Self::file(window)).ok(),
+            window,
+        }
+    }
+    fn save_to_disk(&self) {
+        if let Some(ref token) = self.restore_token
+            && let Err(e) = fs::write(Self::file(self.window), token)
+        {
+            warn!("Could not save screencast session: {e}");
+        }
+    }
+    fn forget_token(&mut self) {
+        self.restore_token = None;
+        let _ = fs::remove_file(Self::file(self.window));
+    }
+
+    /// Keep a screencast running: ask the portal for a stream, and again whenever it ends
+    async fn run(&mut self, sink: FrameSink) {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            sink.send_status(CaptureState::Connecting, None);
+            let source_type = if self.window {
+                SourceType::Window
+            } else {
+                SourceType::Monitor
+            };
+            let (stream, fd, new_token) = match open_portal(
+                self.restore_token.as_deref(),
+                source_type,
+            )
+            .await
+            {
+                Ok(v) => v,
+                // the portal turned the token down. Anything else, like the portal not running
+                // yet at login, is retried with the same token below.
+                Err(e @ ashpd::Error::Response(ResponseError::Other))
+                    if self.restore_token.is_some() =>
+                {
+                    warn!("Could not restore the previous screencast session, asking again: {e}");
+                    self.forget_token();
+                    continue;
+                }
+                Err(ashpd::Error::Response(ResponseError::Cancelled)) => {
+                    sink.send_status(
+                        CaptureState::Failed,
+                        Some("Screen sharing was declined, restart wf_overlay to try again".into()),
+                    );
+                    return;
+                }
+                Err(e) => {
+                    error!("Could not open screencast portal: {e}");
+                    sink.send_status(
+                        CaptureState::Connecting,
+                        Some(format!("{e}, retrying in {}s", backoff.as_secs())),
+                    );
+                    std::thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    continue;
+                }
+            };
+            self.restore_token = new_token;
+            self.save_to_disk();
+            let pipewire_node_id = stream.pipe_wire_node_id();
+            let area = stream.position().zip(stream.size()).map(|((x, y), (w, h))| {
+                IRect::from_corners(IVec2::new(x, y), IVec2::new(x + w, y + h))
+            });
+            debug!("Stream {:?} covers {area:?}", stream.id());
+            // portals usually only give monitor streams a position, without it the overlay
+            // can't be placed over the window
+            if self.window && area.is_none() {
+                sink.send_status(
+                    CaptureState::Failed,
+                    Some(
+                        "The screencast portal doesn't tell where the window is, so the overlay can't be placed over it.\n\
+                        Set window = false in [capture] and [viewport] mode = \"detect\" to find the game on the monitor"
+                            .into(),
+                    ),
+                );
+                return;
+            }
+
+            trace!("node id {}, fd {}", pipewire_node_id, fd.as_raw_fd());
+
+            let reason = match start_streaming(pipewire_node_id, fd, area, sink.clone()).await {
+                Ok(()) => {
+                    // the stream was running, so the next attempt starts over
+                    backoff = MIN_BACKOFF;
+                    "Screencast ended".to_string()
+                }
+                Err(e) => format!("Screencast failed: {e}"),
+            };
+            warn!("{reason}, reconnecting in {}s", backoff.as_secs());
+            sink.send_status(
+                CaptureState::Connecting,
+                Some(format!("{reason}, reconnecting")),
+            );
+            std::thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+}
+
+impl CaptureSource for PortalSource {
+    fn start(mut self: Box<Self>, sink: FrameSink) {
+        // a thread of its own, since waiting to reconnect and the PipeWire main loop both block
+        std::thread::Builder::new()
+            .name("screencast".to_string())
+            .spawn(move || block_on(self.run(sink)))
+            .expect("could spawn thread");
+    }
+}
+
+async fn open_portal(
+    restore_token: Option<&str>,
+    source_type: SourceType,
+) -> ashpd::Result<(Stream, OwnedFd, Option<String>)> {
+    let proxy = Screencast::new().await?;
+    let session = proxy.create_session().await?;
+    proxy
+        .select_sources(
+            &session,
+            CursorMode::Hidden,
+            source_type.into(),
+            false,
+            restore_token,
+            PersistMode::ExplicitlyRevoked,
+        )
+        .await?;
+
+    let response = proxy.start(&session, None).await?.response()?;
+    // the portal answered, but without a stream to capture
+    let stream = response
+        .streams()
+        .first()
+        .ok_or(ashpd::Error::NoResponse)?
+        .to_owned();
+    let restore_token = response.restore_token().map(ToString::to_string);
+
+    let fd = proxy.open_pipe_wire_remote(&session).await?;
+
+    Ok((stream, fd, restore_token))
+}
+
+/// Frames are only needed when the overlay key is pressed, so ask for few while idle
+const IDLE_FRAMERATE: u32 = 5;
+/// Asked for while frames are wanted, like while a capture is requested
+const MAX_FRAMERATE: u32 = 30;
+
+struct UserData {
+    format: spa::param::video::VideoInfoRaw,
+    video_format: VideoFormat,
+    /// The crop region last sent with the meta
+    crop: Option<URect>,
+    /// Where the stream is on the desktop
+    area: Option<IRect>,
+    last_copy: Option<Instant>,
+    /// Whether [`MAX_FRAMERATE`] was asked for instead of [`IDLE_FRAMERATE`]
+    fast: bool,
+}
+
+/// PTS older than this are assumed to be on another clock, and ignored
+const MAX_PTS_AGE: Duration = Duration::from_secs(10);
+
+/// A buffer dequeued from the stream, queued again when dropped.
+///
+/// Unlike [`pw::buffer::Buffer`] this gives access to the buffer's metadata.
+struct StreamBuffer<'s> {
+    stream: &'s pw::stream::Stream,
+    buf: NonNull<pw::sys::pw_buffer>,
+}
+
+impl<'s> StreamBuffer<'s> {
+    fn dequeue(stream: &'s pw::stream::Stream) -> Option<Self> {
+        // SAFETY: the buffer is queued again on drop
+        NonNull::new(unsafe { stream.dequeue_raw_buffer() }).map(|buf| Self { stream, buf })
+    }
+
+    fn spa_buffer(&self) -> Option<&spa::sys::spa_buffer> {
+        // SAFETY: the buffer stays valid until it is queued again
+        unsafe { self.buf.as_ref().buffer.as_ref() }
+    }
+
+    fn datas_mut(&mut self) -> &mut [spa::buffer::Data] {
+        let Some((datas, n_datas)) = self.spa_buffer().map(|b| (b.datas, b.n_datas)) else {
+            return &mut [];
+        };
+        if datas.is_null() {
+            return &mut [];
+        }
+        // SAFETY: `datas` holds `n_datas` entries, and Data is a transparent spa_data
+        unsafe { std::slice::from_raw_parts_mut(datas.cast(), n_datas as usize) }
+    }
+
+    /// The metadata of type `type_`, if the buffer has it.
+    ///
+    /// `T` has to be the struct PipeWire uses for that type.
+    fn meta<T>(&self, type_: u32) -> Option<&T> {
+        let buf = self.spa_buffer()?;
+        if buf.metas.is_null() {
+            return None;
+        }
+        // SAFETY: `metas` holds `n_metas` entries
+        let metas = unsafe { std::slice::from_raw_parts(buf.metas, buf.n_metas as usize) };
+        let meta = metas.iter().find(|m| m.type_ == type_)?;
+        if meta.data.is_null() || (meta.size as usize) < size_of::<T>() {
+            return None;
+        }
+        // SAFETY: checked that `T` fits, the caller picks the right `T` for the type
+        Some(unsafe { &*meta.data.cast::<T>() })
+    }
+
+    /// The presentation timestamp from the header metadata, if the producer sets one
+    fn pts(&self) -> Option<Duration> {
+        let header = self.meta::<spa::sys::spa_meta_header>(spa::sys::SPA_META_Header)?;
+        u64::try_from(header.pts).ok().map(Duration::from_nanos)
+    }
+
+    /// The part of the frame with content, if the producer crops it
+    fn crop(&self) -> Option<URect> {
+        let region = &self
+            .meta::<spa::sys::spa_meta_region>(spa::sys::SPA_META_VideoCrop)?
+            .region;
+        if region.size.width == 0 || region.size.height == 0 {
+            return None;
+        }
+        let min = UVec2::new(
+            region.position.x.max(0) as u32,
+            region.position.y.max(0) as u32,
+        );
+        Some(URect::from_corners(
+            min,
+            min + UVec2::new(region.size.width, region.size.height),
+        ))
+    }
+}
+
+impl Drop for StreamBuffer<'_> {
+    fn drop(&mut self) {
+        // SAFETY: the buffer was dequeued from this stream
+        unsafe { self.stream.queue_raw_buffer(self.buf.as_ptr()) };
+    }
+}
+
+/// When the screen looked like the frame, by comparing its PTS to the monotonic clock
+fn captured_at(pts: Option<Duration>) -> Instant {
+    let now = Instant::now();
+    let Some(pts) = pts else {
+        return now;
+    };
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `ts` is a valid timespec to write to
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) } != 0 {
+        return now;
+    }
+    let monotonic = Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32);
+    monotonic
+        .checked_sub(pts)
+        .filter(|age| *age < MAX_PTS_AGE)
+        .and_then(|age| now.checked_sub(age))
+        .unwrap_or(now)
+}
+
+/// Ask for metadata of type `type_` on buffers, where `T` is the struct PipeWire uses for it
+fn meta_param<T>(type_: u32) -> Vec<u8> {
+    let obj = pw::spa::pod::object!(
+        pw::spa::utils::SpaTypes::ObjectParamMeta,
+        pw::spa::param::ParamType::Meta,
+        pw::spa::pod::Property::new(
+            spa::sys::SPA_PARAM_META_type,
+            pw::spa::pod::Value::Id(pw::spa::utils::Id(type_)),
+        ),
+        pw::spa::pod::Property::new(
+            spa::sys::SPA_PARAM_META_size,
+            pw::spa::pod::Value::Int(size_of::<T>() as i32),
+        ),
+    );
+    pw::spa::pod::serialize::PodSerializer::serialize(
+        std::io::Cursor::new(Vec::new()),
+        &pw::spa::pod::Value::Object(obj),
+    )
+    .unwrap()
+    .0
+    .into_inner()
+}
+
+/// Only accept buffers in shared memory, which `MAP_BUFFERS` maps for us. DMA-BUFs would have to
+/// be mapped and synced by hand, and reading them from the CPU is slow anyway.
+fn buffers_param() -> Vec<u8> {
+    let types = (1 << spa::sys::SPA_DATA_MemPtr) | (1 << spa::sys::SPA_DATA_MemFd);
+    let obj = pw::spa::pod::object!(
+        pw::spa::utils::SpaTypes::ObjectParamBuffers,
+        pw::spa::param::ParamType::Buffers,
+        pw::spa::pod::Property::new(
+            spa::sys::SPA_PARAM_BUFFERS_dataType,
+            pw::spa::pod::Value::Choice(pw::spa::pod::ChoiceValue::Int(pw::spa::utils::Choice(
+                pw::spa::utils::ChoiceFlags::empty(),
+                pw::spa::utils::ChoiceEnum::Flags {
+                    default: types as i32,
+                    flags: Vec::new(),
+                },
+            ))),
+        ),
+    );
+    pw::spa::pod::serialize::PodSerializer::serialize(
+        std::io::Cursor::new(Vec::new()),
+        &pw::spa::pod::Value::Object(obj),
+    )
+    .unwrap()
+    .0
+    .into_inner()
+}
+
+async fn start_streaming(
+    node_id: u32,
+    fd: OwnedFd,
+    area: Option<IRect>,
+    sink: FrameSink,
+) -> Result<(), pw::Error> {
+    pw::init();
+
+    let mainloop = pw::main_loop::MainLoopRc::new(None)?;
+    let context = pw::context::ContextBox::new(mainloop.loop_(), None)?;
+    let core = context.connect_fd(fd, None)?;
+
+    // returning from this function lets the caller reconnect
+    let weak_loop = mainloop.downgrade();
+    let _core_listener = core
+        .add_listener_local()
+        .error(move |id, _seq, res, message| {
+            error!("PipeWire error on {id} ({res}): {message}");
+            if id == pw::core::PW_ID_CORE
+                && let Some(mainloop) = weak_loop.upgrade()
+            {
+                mainloop.quit();
+            }
+        })
+        .register();
+    let weak_loop = mainloop.downgrade();
+    let status_sink = sink.clone();
+
+    let data = UserData {
+        format: Default::default(),
+        video_format: VideoFormat::Other("not negotiated".to_string()),
+        crop: None,
+        area,
+        last_copy: None,
+        fast: false,
+    };
+    let meta_sink = sink.clone();
+
+    let stream = pw::stream::StreamBox::new(
+        &core,
+        "video-test",
+        properties! {
+            *pw::keys::MEDIA_TYPE => "Video",
+            *pw::keys::MEDIA_CATEGORY => "Capture",
+            *pw::keys::MEDIA_ROLE => "Screen",
+        },
+    )?;
+
+    let _listener = stream
+        .add_local_listener_with_user_data(data)
+        .state_changed(move |_, _, old, new| {
+            trace!("State changed: {:?} -> {:?}", old, new);
+            match new {
+                pw::stream::StreamState::Streaming => {
+                    status_sink.send_status(CaptureState::Streaming, None);
+                }
+                pw::stream::StreamState::Error(_) | pw::stream::StreamState::Unconnected => {
+                    if let Some(mainloop) = weak_loop.upgrade() {
+                        mainloop.quit();
+                    }
+                }
+                _ => {}
+            }
+        })
+        .param_changed(move |stream, user_data, id, param| {
+            let Some(param) = param else {
+                return;
+            };
+            if id != pw::spa::param::ParamType::Format.as_raw() {
+                return;
+            }
+
+            let (media_type, media_subtype) =
+                match pw::spa::param::format_utils::parse_format(param) {
+                    Ok(v) => v,
+                    Err(_) => return,
+                };
+
+            if media_type != pw::spa::param::format::MediaType::Video
+                || media_subtype != pw::spa::param::format::MediaSubtype::Raw
+            {
+                return;
+            }
+
+            user_data
+                .format
+                .parse(param)
+                .expect("Failed to parse param changed to VideoInfoRaw");
+
+            // println!("got video format:");
+            // println!(
+            //     "\tformat: {} ({:?})",
+            //     user_data.format.format().as_raw(),
+            //     user_data.format.format()
+            // );
+            // println!(
+            //     "\tsize: {}x{}",
+            //     user_data.format.size().width,
+            //     user_data.format.size().height
+            // );
+            // println!(
+            //     "\tframerate: {}/{}",
+            //     user_data.format.framerate().num,
+            //     user_data.format.framerate().denom
+            // );
+            use spa::param::video::VideoFormat as Spa;
+            let format = match user_data.format.format() {
+                Spa::BGRA => VideoFormat::Bgra,
+                Spa::RGBA => VideoFormat::Rgba,
+                Spa::BGRx => VideoFormat::BGRx,
+                Spa::RGBx => VideoFormat::RGBx,
+                Spa::xRGB => VideoFormat::xRGB,
+                Spa::xBGR => VideoFormat::xBGR,
+                Spa::ARGB => VideoFormat::Argb,
+                Spa::ABGR => VideoFormat::Abgr,
+                Spa::RGB => VideoFormat::Rgb,
+                Spa::BGR => VideoFormat::Bgr,
+                Spa::xRGB_210LE => VideoFormat::xRGB_210LE,
+                Spa::xBGR_210LE => VideoFormat::xBGR_210LE,
+                Spa::RGBx_102LE => VideoFormat::RGBx_102LE,
+                Spa::BGRx_102LE => VideoFormat::BGRx_102LE,
+                Spa::ARGB_210LE => VideoFormat::ARGB_210LE,
+                Spa::ABGR_210LE => VideoFormat::ABGR_210LE,
+                Spa::RGBA_102LE => VideoFormat::RGBA_102LE,
+                Spa::BGRA_102LE => VideoFormat::BGRA_102LE,
+                other => VideoFormat::Other(format!("{:?}", other)),
+            };
+            user_data.video_format = format.clone();
+            // the header contains the PTS, the crop region where the content is
+            let header = meta_param::<spa::sys::spa_meta_header>(spa::sys::SPA_META_Header);
+            let crop = meta_param::<spa::sys::spa_meta_region>(spa::sys::SPA_META_VideoCrop);
+            let buffers = buffers_param();
+            if let Err(e) = stream.update_params(&mut [
+                spa::pod::Pod::from_bytes(&header).unwrap(),
+                spa::pod::Pod::from_bytes(&crop).unwrap(),
+                spa::pod::Pod::from_bytes(&buffers).unwrap(),
+            ]) {
+                warn!("Could not request frame metadata and shared memory buffers: {e}");
+            }
+            user_data.crop = None;
+            let size = user_data.format.size();
+            meta_sink.send_meta(ScreencastMeta {
+                width: size.width,
+                height: size.height,
+                format,
+                crop: None,
+                area: user_data.area,
+            });
+
+            // prepare to render video of this size
+        })
+        .process(move |stream, user_data| {
+            // buffers always have to be dequeued, dropping them hands them back to PipeWire
+            let Some(mut buffer) = StreamBuffer::dequeue(stream) else {
+                trace!("out of buffers");
+                return;
+            };
+            // renegotiate the framerate, so the stream is only fast while frames are wanted
+            let active = sink.is_active();
+            if active != user_data.fast {
+                user_data.fast = active;
+                let framerate = if active {
+                    MAX_FRAMERATE
+                } else {
+                    IDLE_FRAMERATE
+                };
+                debug!("Asking for {framerate} fps");
+                let format = format_param(framerate);
+                if let Err(e) =
+                    stream.update_params(&mut [spa::pod::Pod::from_bytes(&format).unwrap()])
+                {
+                    warn!("Could not change the framerate: {e}");
+                }
+            }
+            if !sink.wants_frame(user_data.last_copy) {
+                return;
+            }
+            let pts = buffer.pts();
+            let captured = captured_at(pts);
+            let crop = buffer.crop();
+            if crop != user_data.crop {
+                debug!("Stream crop region changed to {crop:?}");
+                user_data.crop = crop;
+                let size = user_data.format.size();
+                sink.send_meta(ScreencastMeta {
+                    width: size.width,
+                    height: size.height,
+                    format: user_data.video_format.clone(),
+                    crop,
+                    area: user_data.area,
+                });
+            }
+            let datas = buffer.datas_mut();
+            if datas.is_empty() {
+                return;
+            }
+
+            let data = &mut datas[0];
+            let (offset, size, stride) = {
+                let chunk = data.chunk();
+                (chunk.offset() as usize, chunk.size() as usize, chunk.stride())
+            };
+            // producers may leave the stride unset for tightly packed frames
+            let stride = match usize::try_from(stride) {
+                Ok(stride) if stride > 0 => stride,
+                _ => {
+                    let bpp = user_data.video_format.bytes_per_pixel().unwrap_or(4);
+                    user_data.format.size().width as usize * bpp
+                }
+            };
+            let data_type = data.type_();
+            // only shared memory buffers are negotiated, see buffers_param
+            let Some(mem) = data.data() else {
+                error_once!("Unsupported buffer type {data_type:?}");
+                return;
+            };
+            sink.send_frame_from(chunk_slice(mem, offset, size), stride, captured, pts);
+            user_data.last_copy = Some(Instant::now());
+        })
+        .register()?;
+
+    // println!("Created stream {:#?}", stream);
+
+    let values = format_param(IDLE_FRAMERATE);
+    let mut params = [spa::pod::Pod::from_bytes(&values).unwrap()];
+
+    stream.connect(
+        spa::utils::Direction::Input,
+        Some(node_id),
+        pw::stream::StreamFlags::AUTOCONNECT | pw::stream::StreamFlags::MAP_BUFFERS,
+        &mut params,
+    )?;
+
+    trace!("Connected stream");
+
+    mainloop.run();
+
+    Ok(())
+}
+
+/// The formats we accept, at up to `framerate` frames per second
+fn format_param(framerate: u32) -> Vec<u8> {
+    let obj = pw::spa::pod::object!(
+        pw::spa::utils::SpaTypes::ObjectParamFormat,
+        pw::spa::param::ParamType::EnumFormat,
+        pw::spa::pod::property!(
+            pw::spa::param::format::FormatProperties::MediaType,
+            Id,
+            pw::spa::param::format::MediaType::Video
+        ),
+        pw::spa::pod::property!(
+            pw::spa::param::format::FormatProperties::MediaSubtype,
+            Id,
+            pw::spa::param::format::MediaSubtype::Raw
+        ),
+        pw::spa::pod::property!(
+            pw::spa::param::format::FormatProperties::VideoFormat,
+            Choice,
+            Enum,
+            Id,
+            pw::spa::param::video::VideoFormat::BGRx,
+            pw::spa::param::video::VideoFormat::BGRx,
+            pw::spa::param::video::VideoFormat::BGRA,
+            pw::spa::param::video::VideoFormat::RGBx,
+            pw::spa::param::video::VideoFormat::RGBA,
+            pw::spa::param::video::VideoFormat::xRGB,
+            pw::spa::param::video::VideoFormat::xBGR,
+            pw::spa::param::video::VideoFormat::ARGB,
+            pw::spa::param::video::VideoFormat::ABGR,
+            pw::spa::param::video::VideoFormat::RGB,
+            pw::spa::param::video::VideoFormat::BGR,
+            pw::spa::param::video::VideoFormat::xRGB_210LE,
+            pw::spa::param::video::VideoFormat::xBGR_210LE,
+            pw::spa::param::video::VideoFormat::RGBx_102LE,
+            pw::spa::param::video::VideoFormat::BGRx_102LE,
+            pw::spa::param::video::VideoFormat::ARGB_210LE,
+            pw::spa::param::video::VideoFormat::ABGR_210LE,
+            pw::spa::param::video::VideoFormat::RGBA_102LE,
+            pw::spa::param::video::VideoFormat::BGRA_102LE,
+        ),
+        pw::spa::pod::property!(
+            pw::spa::param::format::FormatProperties::VideoSize,
+            Choice,
+            Range,
+            Rectangle,
+            pw::spa::utils::Rectangle {
+                width: 320,
+                height: 240
+            },
+            pw::spa::utils::Rectangle {
+                width: 1,
+                height: 1
+            },
+            pw::spa::utils::Rectangle {
+                width: 4096,
+                height: 4096
+            }
+        ),
+        pw::spa::pod::property!(
+            pw::spa::param::format::FormatProperties::VideoFramerate,
+            Choice,
+            Range,
+            Fraction,
+            pw::spa::utils::Fraction {
+                num: framerate,
+                denom: 1
+            },
+            pw::spa::utils::Fraction { num: 0, denom: 1 },
+            pw::spa::utils::Fraction {
+                num: framerate,
+                denom: 1
+            }
+        ),
+    );
+    pw::spa::pod::serialize::PodSerializer::serialize(
+        std::io::Cursor::new(Vec::new()),
+        &pw::spa::pod::Value::Object(obj),
+    )
+    .unwrap()
+    .0
+    .into_inner()
+}
@@ -0,0 +1,144 @@
+//! Mapping capture pixels to overlay window coordinates
+use bevy::{
+    prelude::*,
+    window::{Monitor, PrimaryWindow},
+};
+
+use super::{CaptureArea, LatestImage};
+use crate::monitor;
+
+/// How capture pixels map to the overlay window's logical coordinates.
+///
+/// The capture rarely has the window's size: fractional scaling, a 4K monitor under
+/// a lower logical resolution or a cropped stream all change it, so every position
+/// found in a capture has to go through this before it is shown.
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct CaptureTransform {
+    /// Window units per capture pixel
+    pub scale: Vec2,
+    /// Where the capture's top left corner is in the window
+    pub offset: Vec2,
+}
+
+impl Default for CaptureTransform {
+    fn default() -> Self {
+        Self {
+            scale: Vec2::ONE,
+            offset: Vec2::ZERO,
+        }
+    }
+}
+
+/// Aspect ratios closer than this are stretched to fit, further apart ones letterboxed
+const MAX_ASPECT_DIFFERENCE: f32 = 0.01;
+
+impl CaptureTransform {
+    /// Fit a capture of `capture` pixels into `target`, in window logical coordinates.
+    ///
+    /// If the aspect ratios differ the capture is scaled evenly and centered, like the
+    /// compositor letterboxes a stream which doesn't have the monitor's shape.
+    pub fn new(capture: UVec2, target: Rect) -> Self {
+        let size = target.size();
+        if capture.cmpeq(UVec2::ZERO).any() || size.cmple(Vec2::ZERO).any() {
+            return Self::default();
+        }
+        let scale = size / capture.as_vec2();
+        if Self::is_letterboxed(scale) {
+            let scale = Vec2::splat(scale.min_element());
+            Self {
+                scale,
+                offset: target.min + (size - capture.as_vec2() * scale) / 2.,
+            }
+        } else {
+            Self {
+                scale,
+                offset: target.min,
+            }
+        }
+    }
+
+    fn is_letterboxed(scale: Vec2) -> bool {
+        (scale.x / scale.y - 1.).abs() > MAX_ASPECT_DIFFERENCE
+    }
+
+    /// Window position of a position in capture pixels
+    pub fn to_window(self, pos: Vec2) -> Vec2 {
+        pos * self.scale + self.offset
+    }
+}
+
+/// Keep the transform in sync with the capture size and the window.
+///
+/// The overlay fills the monitor, so a captured window maps to where it is on the monitor.
+pub(super) fn update_capture_transform(
+    img: Res<LatestImage>,
+    area: Res<CaptureArea>,
+    monitors: Query<(Entity, &Monitor)>,
+    window: Single<&Window, With<PrimaryWindow>>,
+    mut transform: ResMut<CaptureTransform>,
+) {
+    let capture = img.capture_size();
+    let size = window.size();
+    let target = area
+        .0
+        .and_then(|area| monitor::area_on_monitor(area, monitors.iter()))
+        .map_or(Rect::from_corners(Vec2::ZERO, size), |on_monitor| {
+            Rect::from_corners(on_monitor.min * size, on_monitor.max * size)
+        });
+    let new = CaptureTransform::new(capture, target);
+    if transform.set_if_neq(new) {
+        debug!(
+            "Capture {}x{} maps to {target:?} in the window with scale {} and offset {}",
+            capture.x, capture.y, new.scale, new.offset
+        );
+        if new.offset != target.min {
+            info!(
+                "Capture {}x{} does not have the aspect ratio of the window {}, assuming it's letterboxed",
+                capture.x,
+                capture.y,
+                window.size()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stretches_same_aspect_ratio() {
+        let t = CaptureTransform::new(
+            UVec2::new(3840, 2160),
+            Rect::from_corners(Vec2::ZERO, Vec2::new(1920., 1080.)),
+        );
+        assert_eq!(
+            t.to_window(Vec2::new(3840., 2160.)),
+            Vec2::new(1920., 1080.)
+        );
+    }
+
+    #[test]
+    fn letterboxes_other_aspect_ratio() {
+        // 4:3 in a 16:9 window, with bars left and right
+        let t = CaptureTransform::new(
+            UVec2::new(1440, 1080),
+            Rect::from_corners(Vec2::ZERO, Vec2::new(1920., 1080.)),
+        );
+        assert_eq!(t.to_window(Vec2::ZERO), Vec2::new(240., 0.));
+        assert_eq!(
+            t.to_window(Vec2::new(1440., 1080.)),
+            Vec2::new(1680., 1080.)
+        );
+    }
+
+    #[test]
+    fn maps_into_target_region() {
+        let t = CaptureTransform::new(
+            UVec2::new(800, 600),
+            Rect::from_corners(Vec2::new(100., 50.), Vec2::new(500., 350.)),
+        );
+        assert_eq!(t.to_window(Vec2::ZERO), Vec2::new(100., 50.));
+        assert_eq!(t.to_window(Vec2::new(800., 600.)), Vec2::new(500., 350.));
+    }
+}
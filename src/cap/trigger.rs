@@ -0,0 +1,210 @@
+//! Capturing fresh frames when the overlay key is pressed
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use image::RgbaImage;
+
+use super::{CaptureMessage, CaptureState, FrameInfo, LatestImage, ScreencastReceiver};
+use crate::{AppState, config::ConfigManager};
+
+/// Only every nth pixel in each direction is compared between burst frames
+const SAMPLE_STEP: usize = 8;
+
+/// The frames captured since the overlay key was pressed
+#[derive(Resource)]
+pub struct TriggerCapture {
+    since: Instant,
+    timeout: Duration,
+    wanted: usize,
+    frames: Vec<(RgbaImage, FrameInfo)>,
+    /// The burst was handed over by [`TriggerCapture::take_best`], so nothing is collected anymore
+    delivered: bool,
+}
+
+impl Default for TriggerCapture {
+    fn default() -> Self {
+        Self {
+            since: Instant::now(),
+            timeout: Duration::ZERO,
+            wanted: 1,
+            frames: Vec::new(),
+            delivered: false,
+        }
+    }
+}
+
+impl TriggerCapture {
+    /// Take the frame to use once all frames of the burst arrived.
+    ///
+    /// With several frames, that is the one which changed least from the frame before it,
+    /// so frames where the reward screen is still animating are skipped.
+    pub fn take_best(&mut self) -> Option<RgbaImage> {
+        if self.frames.is_empty() || self.frames.len() < self.wanted {
+            return None;
+        }
+        let frames = std::mem::take(&mut self.frames);
+        self.delivered = true;
+        let best = (1..frames.len())
+            .map(|i| (i, frame_difference(&frames[i - 1].0, &frames[i].0)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(0, |(i, _)| i);
+        let (img, info) = frames.into_iter().nth(best)?;
+        debug!(
+            "Using frame #{} (pts {:?}) from {}ms after the trigger",
+            info.seq,
+            info.pts,
+            info.captured.saturating_duration_since(self.since).as_millis()
+        );
+        Some(img)
+    }
+}
+
+/// Mean difference of sampled pixels, 0-255
+fn frame_difference(a: &RgbaImage, b: &RgbaImage) -> f32 {
+    if a.dimensions() != b.dimensions() {
+        return f32::INFINITY;
+    }
+    let (mut sum, mut count) = (0u64, 0u64);
+    for y in (0..a.height()).step_by(SAMPLE_STEP) {
+        for x in (0..a.width()).step_by(SAMPLE_STEP) {
+            let (pa, pb) = (a.get_pixel(x, y), b.get_pixel(x, y));
+            sum += (0..3).map(|c| pa[c].abs_diff(pb[c]) as u64).sum::<u64>();
+            count += 3;
+        }
+    }
+    sum as f32 / count.max(1) as f32
+}
+
+/// Drop any old frame and ask the source for a new one
+pub(super) fn start_trigger(
+    receiver: Res<ScreencastReceiver>,
+    mut img: ResMut<LatestImage>,
+    mut trigger: ResMut<TriggerCapture>,
+    conf: Res<ConfigManager>,
+) {
+    while let Ok(old) = receiver.frames.try_recv() {
+        img.recycle(old.data);
+    }
+    img.clear();
+    for (frame, _) in trigger.frames.drain(..) {
+        img.recycle_image(frame);
+    }
+    trigger.since = Instant::now();
+    trigger.timeout = Duration::from_secs_f32(conf.trigger.timeout);
+    trigger.wanted = conf.trigger.burst.max(1) as usize;
+    trigger.delivered = false;
+    receiver.request_frame();
+}
+
+/// Collect frames captured after the trigger, until the burst is complete or the timeout is hit
+pub(super) fn collect_trigger_frames(
+    receiver: Res<ScreencastReceiver>,
+    mut img: ResMut<LatestImage>,
+    mut trigger: ResMut<TriggerCapture>,
+    state: Res<State<CaptureState>>,
+    mut next: ResMut<NextState<CaptureState>>,
+    mut message: ResMut<CaptureMessage>,
+    mut commands: Commands,
+) {
+    if trigger.delivered || trigger.frames.len() >= trigger.wanted {
+        return;
+    }
+    match img.latest_info() {
+        Some(info) if info.captured >= trigger.since => {
+            if let Some(frame) = img.get_latest_rgba() {
+                trigger.frames.push((frame, info));
+                if trigger.frames.len() < trigger.wanted {
+                    receiver.request_frame();
+                }
+            }
+            return;
+        }
+        Some(info) => {
+            // copied after the key press, but showing the screen from before it
+            trace!("Dropping frame #{} from before the trigger", info.seq);
+            img.clear();
+            receiver.request_frame();
+        }
+        None => {}
+    }
+
+    let timeout = trigger.timeout;
+    if trigger.since.elapsed() < timeout {
+        return;
+    }
+    if !trigger.frames.is_empty() {
+        warn!(
+            "Only {} of {} burst frames arrived in time",
+            trigger.frames.len(),
+            trigger.wanted
+        );
+        trigger.wanted = trigger.frames.len();
+        return;
+    }
+    warn!(
+        "No new frame within {:.1}s of the trigger",
+        timeout.as_secs_f32()
+    );
+    // connecting or failed sources already tell the user why
+    if *state.get() == CaptureState::Streaming {
+        next.set(CaptureState::Stalled);
+        message.0 = Some(format!(
+            "No new frame within {:.1}s",
+            timeout.as_secs_f32()
+        ));
+    }
+    commands.set_state(AppState::Waiting);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::state::app::StatesPlugin;
+
+    use super::*;
+    use crate::cap::capture_channels;
+
+    #[test]
+    fn no_timeout_after_take_best() {
+        let (_sink, receiver, latest) = capture_channels();
+        let since = Instant::now() - Duration::from_secs(5);
+        let mut trigger = TriggerCapture {
+            since,
+            timeout: Duration::from_secs(2),
+            wanted: 1,
+            frames: vec![(
+                RgbaImage::new(2, 2),
+                FrameInfo {
+                    seq: 0,
+                    pts: None,
+                    captured: since,
+                },
+            )],
+            delivered: false,
+        };
+        assert!(trigger.take_best().is_some());
+
+        let mut app = App::new();
+        app.add_plugins(StatesPlugin)
+            .insert_state(CaptureState::Streaming)
+            .insert_state(AppState::PlatOverlay)
+            .init_resource::<CaptureMessage>()
+            .insert_resource(receiver)
+            .insert_resource(latest)
+            .insert_resource(trigger)
+            .add_systems(Update, collect_trigger_frames);
+        // OCR is still running well past the timeout
+        app.update();
+        app.update();
+
+        let world = app.world();
+        assert_eq!(
+            *world.resource::<State<CaptureState>>().get(),
+            CaptureState::Streaming
+        );
+        assert_eq!(
+            *world.resource::<State<AppState>>().get(),
+            AppState::PlatOverlay
+        );
+        assert!(world.resource::<CaptureMessage>().0.is_none());
+    }
+}
@@ -0,0 +1,218 @@
+//! X11 root window capture, using MIT-SHM when available
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use memmap2::{Mmap, MmapOptions};
+use x11rb::{
+    connection::Connection,
+    protocol::{
+        shm::{self, ConnectionExt as _},
+        xproto::{ConnectionExt as _, Format, ImageFormat, Window},
+    },
+    rust_connection::RustConnection,
+};
+
+use super::{CaptureSource, CaptureState, FrameSink, ScreencastMeta, VideoFormat};
+
+pub struct X11Source {
+    /// The X display to connect to, `$DISPLAY` if not set
+    pub display: Option<String>,
+    /// How often to check whether a frame is wanted
+    pub interval: Duration,
+}
+
+impl CaptureSource for X11Source {
+    fn start(self: Box<Self>, sink: FrameSink) {
+        std::thread::Builder::new()
+            .name("x11 capture".to_string())
+            .spawn(move || {
+                if let Err(e) = self.run(&sink) {
+                    error!("X11 capture failed: {e}");
+                    sink.send_status(CaptureState::Failed, Some(e.to_string()));
+                }
+            })
+            .expect("could spawn thread");
+    }
+}
+
+/// A shared memory segment the X server writes the screen contents into
+struct ShmSegment<'c> {
+    conn: &'c RustConnection,
+    seg: shm::Seg,
+    mem: Mmap,
+}
+
+impl<'c> ShmSegment<'c> {
+    fn new(conn: &'c RustConnection, size: usize) -> anyhow::Result<Self> {
+        conn.shm_query_version()?.reply()?;
+        let seg = conn.generate_id()?;
+        let reply = conn.shm_create_segment(seg, size as u32, false)?.reply()?;
+        // SAFETY: the segment is only ever written by the X server while we wait for the reply
+        let mem = unsafe { MmapOptions::new().len(size).map(&reply.shm_fd)? };
+        Ok(Self { conn, seg, mem })
+    }
+
+    fn grab(
+        &self,
+        root: Window,
+        width: u16,
+        height: u16,
+        stride: usize,
+        sink: &FrameSink,
+    ) -> anyhow::Result<()> {
+        let captured = Instant::now();
+        self.conn
+            .shm_get_image(
+                root,
+                0,
+                0,
+                width,
+                height,
+                !0,
+                ImageFormat::Z_PIXMAP.into(),
+                self.seg,
+                0,
+            )?
+            .reply()?;
+        sink.send_frame_from(&self.mem, stride, captured, None);
+        Ok(())
+    }
+}
+
+impl Drop for ShmSegment<'_> {
+    fn drop(&mut self) {
+        // the server frees the segment once it's detached, our mapping goes with `mem`
+        if let Err(e) = self
+            .conn
+            .shm_detach(self.seg)
+            .and_then(|_| self.conn.flush())
+        {
+            warn!("Could not detach the MIT-SHM segment: {e}");
+        }
+    }
+}
+
+/// Bytes per row of the root window's Z_PIXMAP images. Only 32 bits per pixel are supported,
+/// which the X server sends as BGRx on little endian machines.
+fn root_stride(formats: &[Format], depth: u8, width: u16) -> anyhow::Result<usize> {
+    let Some(format) = formats.iter().find(|f| f.depth == depth) else {
+        anyhow::bail!("Unsupported root window format: no pixmap format for depth {depth}");
+    };
+    if format.bits_per_pixel != 32 {
+        anyhow::bail!(
+            "Unsupported root window format: depth {depth} with {} bits per pixel",
+            format.bits_per_pixel
+        );
+    }
+    let row = width as usize * 4;
+    let pad = (format.scanline_pad as usize / 8).max(1);
+    Ok(row.next_multiple_of(pad))
+}
+
+/// Frames of the whole root window, in the layout [`root_stride`] checked
+fn root_meta(width: u16, height: u16) -> ScreencastMeta {
+    ScreencastMeta {
+        width: width.into(),
+        height: height.into(),
+        format: VideoFormat::BGRx,
+        crop: None,
+        area: None,
+    }
+}
+
+impl X11Source {
+    fn run(&self, sink: &FrameSink) -> anyhow::Result<()> {
+        let (conn, screen_num) = x11rb::connect(self.display.as_deref())?;
+        let screen = &conn.setup().roots[screen_num];
+        let (root, width, height) = (screen.root, screen.width_in_pixels, screen.height_in_pixels);
+
+        let stride = root_stride(&conn.setup().pixmap_formats, screen.root_depth, width)?;
+        sink.send_meta(root_meta(width, height));
+        sink.send_status(CaptureState::Streaming, None);
+
+        let size = stride * height as usize;
+        let shm = match ShmSegment::new(&conn, size) {
+            Ok(shm) => Some(shm),
+            Err(e) => {
+                warn!("MIT-SHM not available, falling back to slower GetImage: {e}");
+                None
+            }
+        };
+
+        let mut last_copy = None;
+        loop {
+            std::thread::sleep(self.interval);
+            if !sink.wants_frame(last_copy) {
+                continue;
+            }
+            match &shm {
+                Some(shm) => shm.grab(root, width, height, stride, sink)?,
+                None => {
+                    let captured = Instant::now();
+                    let image = conn
+                        .get_image(ImageFormat::Z_PIXMAP, root, 0, 0, width, height, !0)?
+                        .reply()?;
+                    sink.send_frame(image.data, stride, captured, None);
+                }
+            }
+            last_copy = Some(Instant::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::state::app::StatesPlugin;
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+    use crate::cap::{CaptureArea, LatestImage, capture_channels, receive_frames};
+
+    fn format(depth: u8, bits_per_pixel: u8) -> Format {
+        Format {
+            depth,
+            bits_per_pixel,
+            scanline_pad: 32,
+        }
+    }
+
+    #[test]
+    fn only_32_bits_per_pixel() {
+        let formats = [format(1, 1), format(16, 16), format(24, 32), format(32, 32)];
+        assert_eq!(root_stride(&formats, 24, 1920).unwrap(), 1920 * 4);
+        assert_eq!(root_stride(&formats, 32, 3).unwrap(), 12);
+        assert!(root_stride(&formats, 16, 1920).is_err());
+        // 24 bits packed into 3 bytes
+        assert!(root_stride(&[format(24, 24)], 24, 1920).is_err());
+        assert!(root_stride(&formats, 30, 1920).is_err());
+    }
+
+    /// What the GetImage fallback sends, as the X server lays out the pixels
+    #[test]
+    fn get_image_frames_are_bgrx() {
+        let expected =
+            RgbaImage::from_fn(3, 2, |x, y| Rgba([x as u8 * 80, y as u8 * 100, 30, 255]));
+        let stride = root_stride(&[format(24, 32)], 24, 3).unwrap();
+        let data: Vec<u8> = expected
+            .pixels()
+            // the padding byte is undefined
+            .flat_map(|p| [p[2], p[1], p[0], 0xaa])
+            .collect();
+
+        let (sink, receiver, latest) = capture_channels();
+        sink.send_meta(root_meta(3, 2));
+        sink.send_frame(data, stride, Instant::now(), None);
+        let mut app = App::new();
+        app.add_plugins(StatesPlugin)
+            .init_state::<CaptureState>()
+            .init_resource::<CaptureArea>()
+            .insert_resource(receiver)
+            .insert_resource(latest)
+            .add_systems(Update, receive_frames);
+        app.update();
+
+        let mut latest = app.world_mut().resource_mut::<LatestImage>();
+        assert_eq!(latest.capture_size(), UVec2::new(3, 2));
+        assert_eq!(latest.get_latest_rgba(), Some(expected));
+    }
+}
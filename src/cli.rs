@@ -0,0 +1,212 @@
+//! Command line subcommands which run instead of the overlay
+use std::path::PathBuf;
+
+use bevy::{ecs::world::FromWorld, prelude::World};
+use image::RgbaImage;
+
+use crate::{
+    config::ConfigManager,
+    control::{self, ControlRequest},
+    history::RewardHistory,
+    paths,
+    pixel_checks::{self, DEFAULT_COUNT},
+};
+
+const USAGE: &str = "\
+Usage: wf_overlay [OPTIONS] [COMMAND]
+
+Without a command, the overlay is started.
+
+Options:
+  --config <FILE>        Config file to use, also set by $WF_OVERLAY_CONFIG
+  --data-dir <DIR>       Keep all files in DIR instead of the XDG directories,
+                         also set by $WF_OVERLAY_DATA_DIR
+
+Commands:
+  history stats          Show session and lifetime reward statistics
+  history export <FILE>  Export the reward history as .csv or .json
+  pixel-checks <SCREENSHOT>... [--negative <FILE|DIR>]... [--layout <N>] [--count <N>] [--write]
+                         Propose pixel checks telling the screen in the screenshots apart
+                         from the negative screenshots (the negatives dir in the data dir
+                         by default), and save them to the layout with --write
+
+Commands for the running overlay:
+  trigger                Read the reward screen and show prices, like the trigger keybind
+  dismiss                Close the overlay, like the dismiss keybind
+  status                 Show the capture, input, market cache and OCR state
+  price <ITEM NAME>      Show the market price of an item
+  reload-config          Load the config file again";
+
+/// Run the subcommand given on the command line, if any.
+///
+/// Returns the exit code if a subcommand was run, or `None` if the overlay should start.
+pub fn run() -> Option<i32> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    match paths::take_overrides(&mut args) {
+        Ok((config, data_dir)) => paths::set_overrides(config, data_dir),
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return Some(2);
+        }
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        [] => {
+            paths::migrate_from_cwd();
+            return None;
+        }
+        ["history", "stats"] => {
+            paths::migrate_from_cwd();
+            history_stats()
+        }
+        ["history", "export", path] => {
+            paths::migrate_from_cwd();
+            history_export(PathBuf::from(path))
+        }
+        ["pixel-checks", args @ ..] if !args.is_empty() => {
+            paths::migrate_from_cwd();
+            pixel_checks(args)
+        }
+        ["price", name @ ..] if !name.is_empty() => remote(ControlRequest::Price(name.join(" "))),
+        [command] if let Some(request) = ControlRequest::parse(command) => remote(request),
+        ["help" | "--help" | "-h"] => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            return Some(2);
+        }
+    };
+    match result {
+        Ok(()) => Some(0),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            Some(1)
+        }
+    }
+}
+
+fn history_stats() -> bevy::prelude::Result<()> {
+    let history = RewardHistory::restore_from_disk_or_empty();
+    println!("Session: {}", history.session_stats());
+    println!("Lifetime: {}", history.lifetime_stats());
+    Ok(())
+}
+
+fn history_export(path: PathBuf) -> bevy::prelude::Result<()> {
+    let history = RewardHistory::restore_from_disk_or_empty();
+    history.export(&path)?;
+    println!(
+        "Exported {} reward screens to {}",
+        history.records().len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Propose pixel checks for the screen in the screenshots, and save them to its layout with `--write`
+fn pixel_checks(args: &[&str]) -> bevy::prelude::Result<()> {
+    let mut positives = Vec::new();
+    let mut negatives = Vec::new();
+    let mut layout = None;
+    let mut count = DEFAULT_COUNT;
+    let mut write = false;
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+        match arg {
+            "--negative" => negatives.push(PathBuf::from(value()?)),
+            "--layout" => layout = Some(value()?.parse::<usize>()?),
+            "--count" => count = value()?.parse()?,
+            "--write" => write = true,
+            path => positives.push(PathBuf::from(path)),
+        }
+    }
+    if positives.is_empty() {
+        return Err("give at least one screenshot of the screen".into());
+    }
+    if negatives.is_empty() && paths::negatives_dir().exists() {
+        negatives.push(paths::negatives_dir());
+    }
+
+    let mut conf = ConfigManager::from_world(&mut World::new());
+    if let Some(error) = conf.error() {
+        return Err(error.into());
+    }
+    let positives = pixel_checks::load_screenshots(&positives, &conf.viewport)?;
+    let (negative_paths, negatives): (Vec<PathBuf>, Vec<RgbaImage>) =
+        pixel_checks::load_screenshots(&negatives, &conf.viewport)?
+            .into_iter()
+            .unzip();
+    let (first, img) = &positives[0];
+    let (width, height) = img.dimensions();
+    let index = match layout {
+        Some(index) => index,
+        None => conf.layout_index_for(img).ok_or_else(|| {
+            format!(
+                "no layout has the aspect ratio of {} ({width}x{height})",
+                first.display()
+            )
+        })?,
+    };
+    let option = conf
+        .layouts
+        .get(index)
+        .ok_or_else(|| format!("there is no layout {index}"))?;
+    for (path, img) in &positives {
+        let (width, height) = img.dimensions();
+        if !option.aspect_ratio_matches(width, height) {
+            return Err(format!(
+                "{} ({width}x{height}) doesn't have the aspect ratio of layout {index}",
+                path.display()
+            )
+            .into());
+        }
+    }
+
+    let positives: Vec<RgbaImage> = positives.into_iter().map(|(_, img)| img).collect();
+    let proposal = pixel_checks::propose(option, &positives, &negatives, count);
+    if proposal.checks.is_empty() {
+        return Err("found no stable pixels outside of the item names".into());
+    }
+    println!(
+        "{} pixel checks for layout {index} ({}:{}), {}",
+        proposal.checks.len(),
+        option.aspect_ratio[0],
+        option.aspect_ratio[1],
+        proposal.summary()
+    );
+    for &i in &proposal.false_positives {
+        println!("  {}", negative_paths[i].display());
+    }
+    let checks: Vec<String> = proposal
+        .checks
+        .iter()
+        .map(|check| format!("\"{check}\""))
+        .collect();
+    println!("pixel_checks = [{}]", checks.join(", "));
+
+    if write {
+        conf.set_pixel_checks(index, proposal.checks)?;
+        println!(
+            "Saved them to layout {index} in {}",
+            paths::config_file().display()
+        );
+    } else {
+        println!("Run again with --write to save them to layout {index} in the config");
+    }
+    Ok(())
+}
+
+/// Send the request to the running overlay and print its reply
+fn remote(request: ControlRequest) -> bevy::prelude::Result<()> {
+    let reply = control::send(&request)?;
+    match reply.strip_prefix("error: ") {
+        Some(error) => Err(error.trim_end().into()),
+        None => {
+            print!("{reply}");
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,366 @@
+//! Checks on areas of the screen, which hold up better than single pixels under HDR,
+//! compression and UI animations.
+//!
+//! Every check gives a score from 0 to 1 for how far the screen clears the check's threshold,
+//! or `None` if it doesn't pass. The layout whose checks score best is used.
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+};
+
+use bevy::{
+    color::{ColorToComponents, ColorToPacked, Srgba, color_difference::EuclideanDistance},
+    math::{URect, UVec2, Vec4},
+};
+use image::{GrayImage, RgbaImage, imageops::FilterType};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{deserialize_color, serialize_color};
+use crate::paths;
+
+/// Bins per color channel of a histogram
+const HISTOGRAM_BINS: usize = 8;
+
+/// A check on an area, given at `reference_resolution` like the rest of the layout
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RegionCheck {
+    /// The average color of the area is within `tolerance` of `color`
+    MeanColor {
+        offset: UVec2,
+        size: UVec2,
+        #[serde(
+            serialize_with = "serialize_color",
+            deserialize_with = "deserialize_color"
+        )]
+        color: Srgba,
+        tolerance: f32,
+    },
+    /// The colors in the area are distributed like the ones in `image`
+    Histogram {
+        offset: UVec2,
+        size: UVec2,
+        image: TemplateImage,
+        min_score: f32,
+    },
+    /// `image` is found somewhere in the area, by normalized cross-correlation
+    Template {
+        offset: UVec2,
+        size: UVec2,
+        image: TemplateImage,
+        min_score: f32,
+    },
+}
+
+impl RegionCheck {
+    /// The area to check, at reference resolution
+    pub fn area(&self) -> URect {
+        let (RegionCheck::MeanColor { offset, size, .. }
+        | RegionCheck::Histogram { offset, size, .. }
+        | RegionCheck::Template { offset, size, .. }) = self;
+        URect::from_corners(*offset, *offset + *size)
+    }
+
+    /// How well the area in `img` matches, `None` if it doesn't pass
+    pub fn score(&self, img: &RgbaImage, reference: UVec2) -> Option<f32> {
+        let size = UVec2::from(img.dimensions());
+        let scale = |p: UVec2| p * size / reference.max(UVec2::ONE);
+        let area = self.area();
+        let area = URect::from_corners(scale(area.min), scale(area.max).min(size));
+        if area.is_empty() {
+            return None;
+        }
+        match self {
+            RegionCheck::MeanColor {
+                color, tolerance, ..
+            } => {
+                let distance = mean_color(img, area).distance(color);
+                (distance <= *tolerance).then(|| margin(1. - distance, 1. - tolerance))
+            }
+            RegionCheck::Histogram {
+                image, min_score, ..
+            } => {
+                let expected = histogram(image.get().ok()?, None);
+                let score = similarity(&expected, &histogram(img, Some(area)));
+                (score >= *min_score).then(|| margin(score, *min_score))
+            }
+            RegionCheck::Template {
+                image, min_score, ..
+            } => {
+                let template = image.get().ok()?;
+                let (width, height) = template.dimensions();
+                let template_size = scale(UVec2::new(width, height)).max(UVec2::ONE);
+                let template = image::imageops::resize(
+                    &image::imageops::grayscale(template.as_ref()),
+                    template_size.x,
+                    template_size.y,
+                    FilterType::Triangle,
+                );
+                let score = best_correlation(&gray(img, area), &template)?;
+                (score >= *min_score).then(|| margin(score, *min_score))
+            }
+        }
+    }
+
+    /// What's wrong with the check, for [`super::Config::validate`]
+    pub fn problems(&self, reference: UVec2) -> Vec<String> {
+        let mut problems = Vec::new();
+        let area = self.area();
+        if area.is_empty() {
+            problems.push("size can't be 0".to_string());
+        } else if !area.max.cmple(reference).all() {
+            problems.push(format!(
+                "offset + size ({}) is outside of reference_resolution ({reference})",
+                area.max
+            ));
+        }
+        match self {
+            RegionCheck::MeanColor { tolerance, .. } => {
+                if tolerance.is_nan() || *tolerance < 0. {
+                    problems.push("tolerance must be 0 or more".to_string());
+                }
+            }
+            RegionCheck::Histogram {
+                image, min_score, ..
+            }
+            | RegionCheck::Template {
+                image, min_score, ..
+            } => {
+                if !(0. ..=1.).contains(min_score) {
+                    problems.push("min_score must be between 0 and 1".to_string());
+                }
+                match image.get() {
+                    Err(e) => problems.push(e.clone()),
+                    Ok(img) if matches!(self, RegionCheck::Template { .. }) => {
+                        let template = UVec2::from(img.dimensions());
+                        if !template.cmple(area.size()).all() {
+                            problems.push(format!(
+                                "{} ({template}) is larger than the area it's searched in ({})",
+                                image,
+                                area.size()
+                            ));
+                        }
+                    }
+                    Ok(_) => {}
+                }
+            }
+        }
+        problems
+    }
+}
+
+/// A PNG referenced by the config, relative to the config file. Loaded when first used.
+#[derive(Clone)]
+pub struct TemplateImage {
+    path: PathBuf,
+    image: OnceLock<Result<Arc<RgbaImage>, String>>,
+}
+
+impl TemplateImage {
+    pub fn get(&self) -> Result<&Arc<RgbaImage>, &String> {
+        self.image
+            .get_or_init(|| {
+                let path = self.resolved();
+                image::open(&path)
+                    .map(|img| Arc::new(img.to_rgba8()))
+                    .map_err(|e| format!("can't read {}: {e}", path.display()))
+            })
+            .as_ref()
+    }
+
+    fn resolved(&self) -> PathBuf {
+        match paths::config_file().parent() {
+            Some(dir) if self.path.is_relative() => dir.join(&self.path),
+            _ => self.path.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for TemplateImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TemplateImage").field(&self.path).finish()
+    }
+}
+
+impl fmt::Display for TemplateImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.path.display().fmt(f)
+    }
+}
+
+impl Serialize for TemplateImage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.path.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TemplateImage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(TemplateImage {
+            path: PathBuf::deserialize(deserializer)?,
+            image: OnceLock::new(),
+        })
+    }
+}
+
+/// How far `score` is above `min_score`, from 0 at the threshold to 1 for a perfect score
+pub(super) fn margin(score: f32, min_score: f32) -> f32 {
+    if min_score >= 1. {
+        return 1.;
+    }
+    ((score - min_score) / (1. - min_score)).clamp(0., 1.)
+}
+
+fn mean_color(img: &RgbaImage, area: URect) -> Srgba {
+    let mut sum = Vec4::ZERO;
+    for y in area.min.y..area.max.y {
+        for x in area.min.x..area.max.x {
+            sum += Srgba::from_u8_array(img.get_pixel(x, y).0).to_vec4();
+        }
+    }
+    Srgba::from_vec4(sum / area.size().element_product().max(1) as f32)
+}
+
+/// Share of the pixels in each color bin, of the whole image if there's no `area`
+fn histogram(img: &RgbaImage, area: Option<URect>) -> Vec<f32> {
+    let area = area.unwrap_or(URect::from_corners(
+        UVec2::ZERO,
+        UVec2::from(img.dimensions()),
+    ));
+    let bin = |c: u8| c as usize * HISTOGRAM_BINS / 256;
+    let mut bins = vec![0.; HISTOGRAM_BINS.pow(3)];
+    for y in area.min.y..area.max.y {
+        for x in area.min.x..area.max.x {
+            let [r, g, b, _] = img.get_pixel(x, y).0;
+            bins[(bin(r) * HISTOGRAM_BINS + bin(g)) * HISTOGRAM_BINS + bin(b)] += 1.;
+        }
+    }
+    let total = area.size().element_product().max(1) as f32;
+    bins.iter_mut().for_each(|b| *b /= total);
+    bins
+}
+
+/// Bhattacharyya coefficient, 1 for the same distribution and 0 for no overlap
+fn similarity(a: &[f32], b: &[f32]) -> f32 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| (a * b).sqrt())
+        .sum::<f32>()
+        .min(1.)
+}
+
+fn gray(img: &RgbaImage, area: URect) -> GrayImage {
+    image::imageops::grayscale(&image::imageops::crop_imm(
+        img,
+        area.min.x,
+        area.min.y,
+        area.width(),
+        area.height(),
+    ))
+}
+
+/// The highest normalized cross-correlation of `template` at any position in `window`,
+/// `None` if it doesn't fit or has no contrast to correlate
+fn best_correlation(window: &GrayImage, template: &GrayImage) -> Option<f32> {
+    let (tw, th) = template.dimensions();
+    let (ww, wh) = window.dimensions();
+    if tw > ww || th > wh {
+        return None;
+    }
+    let n = (tw * th) as f32;
+    let t: Vec<f32> = template.pixels().map(|p| p.0[0] as f32).collect();
+    let t_mean = t.iter().sum::<f32>() / n;
+    let t: Vec<f32> = t.iter().map(|v| v - t_mean).collect();
+    let t_norm = t.iter().map(|v| v * v).sum::<f32>();
+    if t_norm == 0. {
+        return None;
+    }
+
+    let mut best = 0f32;
+    for oy in 0..=wh - th {
+        for ox in 0..=ww - tw {
+            let mut sum = 0.;
+            let mut sum_sq = 0.;
+            let mut cross = 0.;
+            for ty in 0..th {
+                for tx in 0..tw {
+                    let w = window.get_pixel(ox + tx, oy + ty).0[0] as f32;
+                    sum += w;
+                    sum_sq += w * w;
+                    cross += w * t[(ty * tw + tx) as usize];
+                }
+            }
+            // the template's mean is 0, so the window's mean drops out of the cross term
+            let w_norm = sum_sq - sum * sum / n;
+            if w_norm > 0. {
+                best = best.max(cross / (w_norm * t_norm).sqrt());
+            }
+        }
+    }
+    Some(best.clamp(0., 1.))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Luma;
+
+    use super::*;
+
+    fn checkerboard(size: u32) -> GrayImage {
+        GrayImage::from_fn(size, size, |x, y| {
+            Luma([if (x + y) % 2 == 0 { 0 } else { 255 }])
+        })
+    }
+
+    #[test]
+    fn correlation_finds_template() {
+        let mut window = GrayImage::from_pixel(10, 10, Luma([128]));
+        let template = checkerboard(3);
+        image::imageops::replace(&mut window, &template, 5, 2);
+        let score = best_correlation(&window, &template).unwrap();
+        assert!((score - 1.).abs() < 1e-4, "{score}");
+    }
+
+    #[test]
+    fn correlation_of_inverted_template_is_zero() {
+        let window = checkerboard(3);
+        let mut template = window.clone();
+        image::imageops::invert(&mut template);
+        assert_eq!(best_correlation(&window, &template), Some(0.));
+    }
+
+    #[test]
+    fn correlation_needs_fitting_template_with_contrast() {
+        let window = checkerboard(4);
+        assert_eq!(best_correlation(&window, &checkerboard(5)), None);
+        assert_eq!(
+            best_correlation(&window, &GrayImage::from_pixel(2, 2, Luma([7]))),
+            None
+        );
+    }
+
+    #[test]
+    fn similarity_of_histograms() {
+        let red = RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 255]));
+        let blue = RgbaImage::from_pixel(4, 4, image::Rgba([0, 0, 255, 255]));
+        let half = RgbaImage::from_fn(4, 4, |x, _| if x < 2 { red[(0, 0)] } else { blue[(0, 0)] });
+        let (red, blue, half) = (
+            histogram(&red, None),
+            histogram(&blue, None),
+            histogram(&half, None),
+        );
+        assert!((similarity(&red, &red) - 1.).abs() < 1e-6);
+        assert_eq!(similarity(&red, &blue), 0.);
+        // sqrt(1 * 0.5)
+        assert!((similarity(&red, &half) - 0.5f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn margin_is_relative_to_threshold() {
+        assert_eq!(margin(0.9, 0.9), 0.);
+        assert_eq!(margin(1., 0.9), 1.);
+        assert!((margin(0.95, 0.9) - 0.5).abs() < 1e-5);
+        assert_eq!(margin(0.5, 1.), 1.);
+    }
+}
@@ -0,0 +1,247 @@
+//! Upgrading config files written by older versions.
+//!
+//! The steps work on the toml document before it's deserialized, so comments and formatting
+//! survive. To rename a field or change what a value means, bump [`CURRENT_VERSION`] and add a
+//! step to [`STEPS`] which rewrites the old form.
+use anyhow::{Context, anyhow, bail};
+use bevy::log::{info, warn};
+use toml_edit::{Array, Decor, DocumentMut, Item, Table, value};
+
+/// The version of the config format this build reads and writes
+pub const CURRENT_VERSION: i64 = 2;
+
+type Step = fn(&mut DocumentMut) -> anyhow::Result<()>;
+
+/// The step at index `n` upgrades a document from version `n` to `n + 1`
+const STEPS: [Step; CURRENT_VERSION as usize] = [move_overlay_key, clear_capture_pixel_checks];
+
+/// Bring the document up to [`CURRENT_VERSION`].
+///
+/// Returns the version it had if it was changed. Files without a `version` are version 0.
+pub fn migrate(doc: &mut DocumentMut) -> anyhow::Result<Option<i64>> {
+    let version = match doc.get("version") {
+        None => 0,
+        Some(item) => item
+            .as_integer()
+            .ok_or_else(|| anyhow!("version must be a whole number"))?,
+    };
+    if version > CURRENT_VERSION {
+        bail!(
+            "the config is version {version}, from a newer wf_overlay. This one only reads up to version {CURRENT_VERSION}"
+        );
+    }
+    if version == CURRENT_VERSION {
+        return Ok(None);
+    }
+    for (from, step) in STEPS.iter().enumerate().skip(version.max(0) as usize) {
+        step(doc).with_context(|| {
+            format!(
+                "could not upgrade the config from version {from} to {}",
+                from + 1
+            )
+        })?;
+    }
+    doc["version"] = value(CURRENT_VERSION);
+    info!("Upgraded the config from version {version} to {CURRENT_VERSION}");
+    Ok(Some(version))
+}
+
+/// `overlay_key` became `trigger` in [keybinds], with a list of chords
+fn move_overlay_key(doc: &mut DocumentMut) -> anyhow::Result<()> {
+    let Some(key) = remove_keeping_comments(doc, "overlay_key") else {
+        return Ok(());
+    };
+    // the keybinds were already set up, so the old key wasn't used anymore
+    if doc.contains_key("keybinds") {
+        return Ok(());
+    }
+    let key = key
+        .as_str()
+        .ok_or_else(|| anyhow!("overlay_key must be the name of a key"))?;
+    let mut keybinds = Table::new();
+    keybinds["trigger"] = value(Array::from_iter([key]));
+    doc["keybinds"] = Item::Table(keybinds);
+    info!("Moved overlay_key to trigger in [keybinds]");
+    Ok(())
+}
+
+/// `pixel_checks` were at capture pixels and are at `reference_resolution` now. The resolution
+/// they were made at isn't known, so they're cleared, keeping the old ones in a comment.
+fn clear_capture_pixel_checks(doc: &mut DocumentMut) -> anyhow::Result<()> {
+    let Some(layouts) = doc
+        .get_mut("layouts")
+        .and_then(Item::as_array_of_tables_mut)
+    else {
+        return Ok(());
+    };
+    for (i, layout) in layouts.iter_mut().enumerate() {
+        let Some(checks) = layout.get("pixel_checks").and_then(Item::as_array) else {
+            continue;
+        };
+        if checks.is_empty() {
+            continue;
+        }
+        let old: Vec<String> = checks
+            .iter()
+            .map(|c| c.to_string().trim().to_string())
+            .collect();
+        layout["pixel_checks"] = value(Array::new());
+        if let Some(mut key) = layout.key_mut("pixel_checks") {
+            prepend_comments(
+                key.leaf_decor_mut(),
+                format!(
+                    "# before version 2 these were at capture pixels, redo them with `wf_overlay pixel-checks`:\n# [{}]\n",
+                    old.join(", ")
+                ),
+            );
+        }
+        warn!(
+            "Cleared the pixel checks of layout {i}, they are at reference_resolution now. Redo them with `wf_overlay pixel-checks`"
+        );
+    }
+    Ok(())
+}
+
+/// Remove a key, moving the comments above it to whatever comes next so they aren't lost
+fn remove_keeping_comments(table: &mut Table, key: &str) -> Option<Item> {
+    let index = table.iter().position(|(k, _)| k == key)?;
+    let comments = table
+        .key(key)
+        .and_then(|k| k.leaf_decor().prefix())
+        .and_then(|p| p.as_str())
+        .filter(|p| p.contains('#'))
+        .map(str::to_string);
+    let item = table.remove(key)?;
+    let next = table.iter().nth(index).map(|(k, _)| k.to_string());
+    if let Some(comments) = comments
+        && let Some(next) = next
+    {
+        match &mut table[&next] {
+            Item::Table(t) => prepend_comments(t.decor_mut(), comments),
+            Item::ArrayOfTables(a) => {
+                if let Some(t) = a.get_mut(0) {
+                    prepend_comments(t.decor_mut(), comments);
+                }
+            }
+            Item::Value(_) => {
+                if let Some(mut key) = table.key_mut(&next) {
+                    prepend_comments(key.leaf_decor_mut(), comments);
+                }
+            }
+            Item::None => {}
+        }
+    }
+    Some(item)
+}
+
+fn prepend_comments(decor: &mut Decor, comments: String) {
+    let existing = decor.prefix().and_then(|p| p.as_str()).unwrap_or("");
+    let prefix = comments + existing;
+    decor.set_prefix(prefix);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V0: &str = r#"# Config for wf_overlay
+overlay = true
+# the key to press
+overlay_key = "Home"
+# seconds the overlay stays
+close_layout_after = 14.5
+
+# the usual 16:9 screen
+[[layouts]]
+aspect_ratio = "16:9"
+pixel_checks = ["10,20,#ffffff,0.1"] # at 1080p
+"#;
+
+    const V1: &str = r#"version = 1
+# keys
+[keybinds]
+trigger = ["Ctrl+P"]
+
+[[layouts]]
+aspect_ratio = "16:9"
+pixel_checks = ["10,20,#ffffff,0.1"]
+"#;
+
+    fn parse(src: &str) -> DocumentMut {
+        src.parse().unwrap()
+    }
+
+    #[test]
+    fn v0_to_current() {
+        let mut doc = parse(V0);
+        assert_eq!(migrate(&mut doc).unwrap(), Some(0));
+        assert_eq!(doc["version"].as_integer(), Some(CURRENT_VERSION));
+        assert!(!doc.contains_key("overlay_key"));
+        let trigger = doc["keybinds"]["trigger"].as_array().unwrap();
+        assert_eq!(trigger.get(0).and_then(|k| k.as_str()), Some("Home"));
+        assert!(
+            doc["layouts"][0]["pixel_checks"]
+                .as_array()
+                .unwrap()
+                .is_empty()
+        );
+
+        let out = doc.to_string();
+        assert!(out.starts_with("# Config for wf_overlay\n"));
+        // the removed key's comment moved to the next one
+        assert!(
+            out.contains("# the key to press\n# seconds the overlay stays\nclose_layout_after")
+        );
+        assert!(out.contains("# the usual 16:9 screen\n[[layouts]]"));
+        assert!(out.contains(r##"# ["10,20,#ffffff,0.1"]"##));
+    }
+
+    #[test]
+    fn v1_to_current() {
+        let mut doc = parse(V1);
+        assert_eq!(migrate(&mut doc).unwrap(), Some(1));
+        assert_eq!(doc["version"].as_integer(), Some(CURRENT_VERSION));
+        assert!(
+            doc["layouts"][0]["pixel_checks"]
+                .as_array()
+                .unwrap()
+                .is_empty()
+        );
+        let out = doc.to_string();
+        assert!(out.contains("# keys\n[keybinds]\ntrigger = [\"Ctrl+P\"]"));
+        assert!(out.contains("redo them with `wf_overlay pixel-checks`"));
+    }
+
+    #[test]
+    fn current_is_left_alone() {
+        let src = format!("version = {CURRENT_VERSION}\n# comment\noverlay = true\n");
+        let mut doc = parse(&src);
+        assert_eq!(migrate(&mut doc).unwrap(), None);
+        assert_eq!(doc.to_string(), src);
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let mut doc = parse(&format!("version = {}\n", CURRENT_VERSION + 1));
+        assert!(migrate(&mut doc).is_err());
+    }
+
+    #[test]
+    fn existing_keybinds_are_kept() {
+        let mut doc = parse("overlay_key = \"Home\"\n\n[keybinds]\ntrigger = [\"F1\"]\n");
+        move_overlay_key(&mut doc).unwrap();
+        assert!(!doc.contains_key("overlay_key"));
+        assert_eq!(doc["keybinds"]["trigger"].as_array().unwrap().len(), 1);
+        assert!(doc.to_string().contains("[keybinds]\ntrigger = [\"F1\"]\n"));
+    }
+
+    #[test]
+    fn removed_comments_move_to_next_table() {
+        let mut doc = parse("# gone\nold = 1\n\n# kept\n[next]\na = 2\n");
+        let old = remove_keeping_comments(&mut doc, "old").unwrap();
+        assert_eq!(old.as_integer(), Some(1));
+        assert!(doc.to_string().contains("# gone\n"));
+        assert!(doc.to_string().contains("# kept\n[next]"));
+        assert!(remove_keeping_comments(&mut doc, "old").is_none());
+    }
+}
@@ -0,0 +1,1107 @@
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use bevy::{
+    app::{App, AppExit, Last, Startup, Update},
+    color::{ColorToPacked, Srgba, color_difference::EuclideanDistance},
+    ecs::{
+        message::MessageReader,
+        resource::Resource,
+        system::{Res, ResMut},
+        world::FromWorld,
+    },
+    input::ButtonInput,
+    log::{error, info, warn},
+    math::UVec2,
+    platform::collections::{HashMap, HashSet},
+    prelude::{
+        Commands, Component, IntoScheduleConfigs, Node, PositionType, Result, Single, Text,
+        TextColor, TextFont, TextShadow, Val, Visibility, With, resource_changed,
+    },
+    time::common_conditions::on_real_timer,
+    utils::default,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use toml_edit::{DocumentMut, Item, Table, Value};
+
+use crate::{
+    ERROR_COLOR, STATUS_COLOR,
+    input::{Action, Keybinds},
+    paths,
+    template::Template,
+};
+
+mod matching;
+mod migrations;
+
+pub use matching::RegionCheck;
+
+/// How often the config file is checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+pub fn config_plugin(app: &mut App) {
+    app.init_resource::<ConfigManager>()
+        .add_systems(
+            Last,
+            |mut exit: MessageReader<AppExit>, mut conf: ResMut<ConfigManager>| {
+                for e in exit.read() {
+                    // the user's edits would be lost otherwise
+                    if let AppExit::Success = e
+                        && conf.error.is_none()
+                    {
+                        conf.merge_and_save().unwrap();
+                    }
+                }
+            },
+        )
+        .add_systems(Startup, setup_error_text)
+        .add_systems(
+            Update,
+            (
+                reload_on_action,
+                reload_on_change.run_if(on_real_timer(WATCH_INTERVAL)),
+                update_error_text.run_if(resource_changed::<ConfigManager>),
+            )
+                .chain(),
+        );
+}
+
+fn reload_on_action(actions: Res<ButtonInput<Action>>, mut conf: ResMut<ConfigManager>) {
+    if actions.just_pressed(Action::ReloadConfig)
+        && let Err(e) = conf.reload()
+    {
+        error!("Could not reload config, keeping the current one: {e}");
+    }
+}
+
+/// Apply the config as soon as it's saved
+fn reload_on_change(mut conf: ResMut<ConfigManager>) {
+    if conf.modified == file_modified() {
+        return;
+    }
+    if let Err(e) = conf.reload() {
+        error!("Could not reload config, keeping the current one: {e}");
+    }
+}
+
+/// When the config file was last changed, `None` if it can't be read
+fn file_modified() -> Option<SystemTime> {
+    std::fs::metadata(paths::config_file())
+        .and_then(|m| m.modified())
+        .ok()
+}
+
+/// Why the config file isn't used, or which changes need a restart, in the bottom left corner
+#[derive(Component)]
+struct ConfigErrorText;
+
+fn setup_error_text(mut commands: Commands, conf: Res<ConfigManager>) {
+    commands.spawn((
+        ConfigErrorText,
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(12.),
+            left: Val::Px(12.),
+            ..default()
+        },
+        Text::default(),
+        TextFont::from_font_size(conf.font_size * 0.75),
+        TextColor(ERROR_COLOR),
+        TextShadow::default(),
+        Visibility::Hidden,
+    ));
+}
+
+fn update_error_text(
+    conf: Res<ConfigManager>,
+    mut text: Single<
+        (&mut Text, &mut TextFont, &mut TextColor, &mut Visibility),
+        With<ConfigErrorText>,
+    >,
+) {
+    text.1.font_size = conf.font_size * 0.75;
+    let (message, color) = match (&conf.error, conf.restart.as_slice()) {
+        (Some(error), _) => (error.clone(), ERROR_COLOR),
+        (None, []) => {
+            *text.3 = Visibility::Hidden;
+            return;
+        }
+        (None, sections) => (
+            format!(
+                "Restart wf_overlay to apply the changes to {}",
+                sections.join(", ")
+            ),
+            STATUS_COLOR,
+        ),
+    };
+    text.0.0 = message;
+    text.2.0 = color;
+    *text.3 = Visibility::Inherited;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PixelCheck {
+    pub x: u32,
+    pub y: u32,
+    pub color: Srgba,
+    pub tolerance: f32,
+}
+
+impl PixelCheck {
+    /// Check if a pixel matches the expected color
+    pub fn matches_pixel(&self, pixel: &Srgba) -> bool {
+        if self.tolerance == 0. {
+            // Exact match
+            self.color == *pixel
+        } else {
+            let distance = self.color.distance(pixel);
+            distance <= self.tolerance
+        }
+    }
+
+    /// Where the check is in an image of `size`, since it's given at `reference` resolution
+    pub fn position_in(&self, reference: UVec2, size: UVec2) -> UVec2 {
+        UVec2::new(self.x, self.y) * size / reference.max(UVec2::ONE)
+    }
+
+    pub fn matches_image(&self, image: &image::RgbaImage, reference: UVec2) -> bool {
+        self.score(image, reference).is_some()
+    }
+
+    /// How far the pixel is within the tolerance, from 0 at its edge to 1 for the exact color,
+    /// `None` if it doesn't match
+    pub fn score(&self, image: &image::RgbaImage, reference: UVec2) -> Option<f32> {
+        let size = UVec2::from(image.dimensions());
+        let pos = self.position_in(reference, size);
+        // Ensure pixel is within bounds
+        if !pos.cmplt(size).all() {
+            return None;
+        }
+        let pixel = Srgba::from_u8_array(image.get_pixel(pos.x, pos.y).0);
+        self.matches_pixel(&pixel)
+            .then(|| matching::margin(1. - self.color.distance(&pixel), 1. - self.tolerance))
+    }
+}
+
+impl fmt::Display for PixelCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex = self.color.to_hex();
+        write!(f, "{},{},{},{}", self.x, self.y, hex, self.tolerance)
+    }
+}
+
+// Custom serialization for PixelCheck: "x,y,#hexcolor,tolerance"
+impl Serialize for PixelCheck {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for PixelCheck {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let parts: Vec<&str> = s.split(',').collect();
+
+        if parts.len() != 4 {
+            return Err(serde::de::Error::custom(format!(
+                "PixelCheck format must be 'x,y,#hexcolor,tolerance', got: {}",
+                s
+            )));
+        }
+
+        let x = parts[0]
+            .trim()
+            .parse::<u32>()
+            .map_err(|e| serde::de::Error::custom(format!("Invalid x coordinate: {}", e)))?;
+        let y = parts[1]
+            .trim()
+            .parse::<u32>()
+            .map_err(|e| serde::de::Error::custom(format!("Invalid y coordinate: {}", e)))?;
+        let color = Srgba::hex(parts[2].trim())
+            .map_err(|e| serde::de::Error::custom(format!("Invalid hex color: {:?}", e)))?;
+        let tolerance = parts[3]
+            .trim()
+            .parse::<f32>()
+            .map_err(|e| serde::de::Error::custom(format!("Invalid tolerance: {}", e)))?;
+
+        Ok(PixelCheck {
+            x,
+            y,
+            color,
+            tolerance,
+        })
+    }
+}
+
+/// A config variant selected by aspect ratio, and by how well its checks score
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LayoutOption {
+    #[serde(
+        serialize_with = "serialize_aspect_ratio",
+        deserialize_with = "deserialize_aspect_ratio"
+    )]
+    pub aspect_ratio: [u32; 2],
+    /// How far the ratio of a frame can be from `aspect_ratio`, relative to it.
+    /// Marketing ratios like 21:9 are only roughly what monitors have.
+    #[serde(default = "default_aspect_ratio_tolerance")]
+    pub aspect_ratio_tolerance: f32,
+    /// Positions are at `reference_resolution`, like the rest of the layout
+    pub pixel_checks: Vec<PixelCheck>,
+    /// Checks on areas, which hold up better than single pixels
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub region_checks: Vec<RegionCheck>,
+    #[serde(flatten)]
+    pub config: Layout,
+}
+
+fn default_aspect_ratio_tolerance() -> f32 {
+    0.03
+}
+
+impl LayoutOption {
+    /// How far the ratio of the image is from `aspect_ratio`, relative to it
+    pub fn aspect_ratio_error(&self, img_width: u32, img_height: u32) -> f32 {
+        let [w, h] = self.aspect_ratio;
+        let expected = w as f32 / h.max(1) as f32;
+        let actual = img_width as f32 / img_height.max(1) as f32;
+        (actual / expected - 1.).abs()
+    }
+
+    pub fn aspect_ratio_matches(&self, img_width: u32, img_height: u32) -> bool {
+        self.aspect_ratio_error(img_width, img_height) <= self.aspect_ratio_tolerance
+    }
+
+    /// The average score of all checks, `None` if any of them fails. Scores are relative to each
+    /// check's threshold, so different kinds of checks can be averaged.
+    /// A layout without checks scores 0, so one whose checks pass is preferred.
+    pub fn score(&self, image: &image::RgbaImage) -> Option<f32> {
+        let (width, height) = image.dimensions();
+        if !self.aspect_ratio_matches(width, height) {
+            return None;
+        }
+        let reference = self.config.reference_resolution;
+        let scores = self
+            .pixel_checks
+            .iter()
+            .map(|check| check.score(image, reference))
+            .chain(
+                self.region_checks
+                    .iter()
+                    .map(|check| check.score(image, reference)),
+            )
+            .collect::<Option<Vec<f32>>>()?;
+        Some(scores.iter().sum::<f32>() / scores.len().max(1) as f32)
+    }
+
+    pub fn matches(&self, image: &image::RgbaImage) -> bool {
+        self.score(image).is_some()
+    }
+}
+
+fn serialize_aspect_ratio<S>(aspect_ratio: &[u32; 2], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let s = format!("{}:{}", aspect_ratio[0], aspect_ratio[1]);
+    serializer.serialize_str(&s)
+}
+
+fn deserialize_aspect_ratio<'de, D>(deserializer: D) -> Result<[u32; 2], D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    let parts: Vec<&str> = s.split(':').collect();
+
+    if parts.len() != 2 {
+        return Err(serde::de::Error::custom(format!(
+            "Aspect ratio must be in format 'width:height', got: {}",
+            s
+        )));
+    }
+
+    let width = parts[0]
+        .trim()
+        .parse::<u32>()
+        .map_err(|e| serde::de::Error::custom(format!("Invalid width: {}", e)))?;
+    let height = parts[1]
+        .trim()
+        .parse::<u32>()
+        .map_err(|e| serde::de::Error::custom(format!("Invalid height: {}", e)))?;
+
+    Ok([width, height])
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Layout {
+    pub offset: UVec2,
+    pub size: UVec2,
+    pub reference_resolution: UVec2,
+    #[serde(
+        serialize_with = "serialize_color",
+        deserialize_with = "deserialize_color"
+    )]
+    pub theme_text_color: Srgba,
+    pub item_name_distance: u32,
+}
+fn serialize_color<S: Serializer>(color: &Srgba, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&color.to_hex())
+}
+fn deserialize_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Srgba, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    Srgba::hex(s).map_err(|e| serde::de::Error::custom(e.to_string()))
+}
+
+/// Where captured frames come from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum CaptureBackend {
+    /// Screencast through xdg-desktop-portal and PipeWire, for Wayland
+    Portal {
+        /// Ask for a single window instead of a whole monitor
+        #[serde(default)]
+        window: bool,
+    },
+    /// The X11 root window
+    X11 {
+        /// X display to capture, `$DISPLAY` if not set
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        display: Option<String>,
+        /// Seconds between checks whether a frame is wanted
+        #[serde(default = "default_poll_interval")]
+        interval: f32,
+    },
+    /// PNG files from a directory, for testing
+    Folder {
+        path: PathBuf,
+        /// Seconds between frames
+        #[serde(default = "default_capture_interval")]
+        interval: f32,
+        #[serde(default)]
+        repeat: bool,
+    },
+}
+impl Default for CaptureBackend {
+    fn default() -> Self {
+        CaptureBackend::Portal { window: false }
+    }
+}
+fn default_capture_interval() -> f32 {
+    0.5
+}
+fn default_poll_interval() -> f32 {
+    0.05
+}
+impl CaptureBackend {
+    /// The name of the source, which is also the name of the cargo feature enabling it
+    pub fn name(&self) -> &'static str {
+        match self {
+            CaptureBackend::Portal { .. } => "portal",
+            CaptureBackend::X11 { .. } => "x11",
+            CaptureBackend::Folder { .. } => "folder",
+        }
+    }
+}
+
+/// Where the game is inside the captured frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Viewport {
+    /// The game fills the whole frame
+    #[default]
+    Full,
+    /// Find a windowed game by its borders, falling back to the whole frame
+    Detect,
+    /// A fixed rectangle, in capture pixels
+    Fixed {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+}
+
+/// How frames are captured when the overlay key is pressed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TriggerConfig {
+    /// Seconds to wait for a frame newer than the key press
+    pub timeout: f32,
+    /// Capture this many frames and use the one which changed least from the one before it
+    pub burst: u32,
+}
+impl Default for TriggerConfig {
+    fn default() -> Self {
+        Self {
+            timeout: 2.0,
+            burst: 1,
+        }
+    }
+}
+
+/// Which devices are listened to for the keybinds.
+///
+/// Entries match a device by its `vendor:product` id in hex, its path, or part of its name.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InputConfig {
+    /// Only listen to these devices, all keyboards if empty
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Never listen to these devices
+    #[serde(default)]
+    pub deny: Vec<String>,
+    #[serde(default)]
+    pub backend: InputBackend,
+}
+
+/// Where the keybinds are read from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputBackend {
+    /// Read the input devices directly, which needs the `input` group
+    #[default]
+    Evdev,
+    /// Let the desktop handle the shortcuts through the GlobalShortcuts portal,
+    /// falling back to `Evdev` if it isn't available
+    Portal,
+    /// Read the input devices in `wf_input_helper`, which only tells the app about actions
+    Helper,
+}
+
+/// Which reward counts as the best one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PickStrategy {
+    /// Highest average plat
+    #[default]
+    Plat,
+    /// Most ducats
+    Ducats,
+    /// Part of the set with the most parts already picked, then by plat
+    SetCompletion,
+}
+
+/// When a highlight rule applies to a reward
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "when", rename_all = "snake_case")]
+pub enum HighlightCondition {
+    /// The best reward according to the pick strategy
+    Best,
+    PlatAbove { value: f32 },
+    PlatBelow { value: f32 },
+    DucatsAbove { value: u32 },
+    /// Ducats per plat
+    DucatRatioAbove { value: f32 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HighlightRule {
+    #[serde(flatten)]
+    pub condition: HighlightCondition,
+    #[serde(
+        serialize_with = "serialize_color",
+        deserialize_with = "deserialize_color"
+    )]
+    pub color: Srgba,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HighlightConfig {
+    pub strategy: PickStrategy,
+    /// Color of the border around the best reward
+    #[serde(
+        serialize_with = "serialize_color",
+        deserialize_with = "deserialize_color"
+    )]
+    pub border_color: Srgba,
+    /// Applied in order, the last matching rule colors the text
+    pub rules: Vec<HighlightRule>,
+}
+impl Default for HighlightConfig {
+    fn default() -> Self {
+        Self {
+            strategy: PickStrategy::Plat,
+            border_color: Srgba::hex("#FFD700").unwrap(),
+            rules: vec![
+                HighlightRule {
+                    condition: HighlightCondition::PlatBelow { value: 5. },
+                    color: Srgba::hex("#9E9E9E").unwrap(),
+                },
+                HighlightRule {
+                    condition: HighlightCondition::PlatAbove { value: 50. },
+                    color: Srgba::hex("#FFD700").unwrap(),
+                },
+                HighlightRule {
+                    condition: HighlightCondition::Best,
+                    color: Srgba::hex("#7CFC00").unwrap(),
+                },
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// Format of the file, older ones are upgraded when loading them, see migrations.rs
+    #[serde(default)]
+    pub version: i64,
+    pub overlay: bool,
+    #[serde(default)]
+    pub keybinds: Keybinds,
+    pub close_layout_after: f32,
+    pub refresh_market_after: u64,
+    pub show_corner_boxes: f32,
+    pub font_size: f32,
+    /// Text shown for each item, see src/template.rs
+    #[serde(default)]
+    pub overlay_template: Template,
+    pub show_keys: bool,
+    pub save_to_disk: bool,
+    #[serde(default)]
+    pub capture: CaptureBackend,
+    /// Monitor to show the overlay on, by name. Defaults to the monitor being captured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monitor: Option<String>,
+    #[serde(default)]
+    pub trigger: TriggerConfig,
+    #[serde(default)]
+    pub input: InputConfig,
+    #[serde(default)]
+    pub viewport: Viewport,
+    #[serde(default)]
+    pub highlight: HighlightConfig,
+    pub layouts: Vec<LayoutOption>,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: migrations::CURRENT_VERSION,
+            overlay: true,
+            keybinds: default(),
+            close_layout_after: 14.5,
+            refresh_market_after: 60 * 60 * 24 * 2, // 2 days
+            show_corner_boxes: 5.,
+            font_size: 18.0,
+            overlay_template: default(),
+            show_keys: false,
+            save_to_disk: false,
+            capture: default(),
+            monitor: None,
+            trigger: default(),
+            input: default(),
+            viewport: default(),
+            highlight: default(),
+            layouts: vec![LayoutOption {
+                aspect_ratio: [16, 9],
+                aspect_ratio_tolerance: default_aspect_ratio_tolerance(),
+                pixel_checks: vec![],
+                region_checks: vec![],
+                config: Layout {
+                    offset: UVec2::new(478, 411),
+                    size: UVec2::new(965, 49),
+                    reference_resolution: UVec2::new(1920, 1080),
+                    theme_text_color: Srgba::hex("#bea966").unwrap(), // vitruvian
+                    item_name_distance: 90,
+                },
+            }],
+        }
+    }
+}
+
+/// Above 0, which NaN isn't either
+fn is_positive(x: f32) -> bool {
+    x > 0.
+}
+
+impl Config {
+    /// Everything wrong with the config which deserializing doesn't catch, one message each
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !is_positive(self.font_size) {
+            problems.push(format!("font_size must be above 0, is {}", self.font_size));
+        }
+        if self.close_layout_after.is_nan() || self.close_layout_after < 0. {
+            problems.push("close_layout_after can't be negative".to_string());
+        }
+        if !is_positive(self.trigger.timeout) {
+            problems.push("timeout in [trigger] must be above 0".to_string());
+        }
+        if let CaptureBackend::X11 { interval, .. } | CaptureBackend::Folder { interval, .. } =
+            self.capture
+            && !is_positive(interval)
+        {
+            problems.push("interval in [capture] must be above 0".to_string());
+        }
+        if let Viewport::Fixed { width, height, .. } = self.viewport
+            && (width == 0 || height == 0)
+        {
+            problems.push("width and height in [viewport] can't be 0".to_string());
+        }
+        for rule in &self.highlight.rules {
+            if let HighlightCondition::PlatAbove { value }
+            | HighlightCondition::PlatBelow { value }
+            | HighlightCondition::DucatRatioAbove { value } = rule.condition
+                && !value.is_finite()
+            {
+                problems.push(format!(
+                    "highlight rule {:?} needs a number",
+                    rule.condition
+                ));
+            }
+        }
+
+        if self.layouts.is_empty() {
+            problems.push("at least one layout is needed in [[layouts]]".to_string());
+        }
+        for (i, option) in self.layouts.iter().enumerate() {
+            let Layout {
+                offset,
+                size,
+                reference_resolution,
+                ..
+            } = option.config;
+            if option.aspect_ratio.contains(&0) {
+                problems.push(format!("layout {i}: aspect_ratio can't be 0"));
+            }
+            if !(option.aspect_ratio_tolerance >= 0. && option.aspect_ratio_tolerance < 1.) {
+                problems.push(format!(
+                    "layout {i}: aspect_ratio_tolerance must be at least 0 and below 1"
+                ));
+            }
+            if reference_resolution.min_element() == 0 || size.min_element() == 0 {
+                problems.push(format!(
+                    "layout {i}: size and reference_resolution can't be 0"
+                ));
+            } else if !(offset + size).cmple(reference_resolution).all() {
+                problems.push(format!(
+                    "layout {i}: offset + size ({}) is outside of reference_resolution ({reference_resolution})",
+                    offset + size
+                ));
+            }
+            for check in &option.pixel_checks {
+                if check.tolerance.is_nan() || check.tolerance < 0. {
+                    problems.push(format!(
+                        "layout {i}: pixel check at {},{} needs a tolerance of 0 or more",
+                        check.x, check.y
+                    ));
+                }
+                if !UVec2::new(check.x, check.y).cmplt(reference_resolution).all() {
+                    problems.push(format!(
+                        "layout {i}: pixel check at {},{} is outside of reference_resolution ({reference_resolution})",
+                        check.x, check.y
+                    ));
+                }
+            }
+            for (j, check) in option.region_checks.iter().enumerate() {
+                problems.extend(
+                    check
+                        .problems(reference_resolution)
+                        .into_iter()
+                        .map(|p| format!("layout {i}: region check {j}: {p}")),
+                );
+            }
+        }
+
+        problems.extend(
+            self.keybinds
+                .conflicts()
+                .into_iter()
+                .map(|c| format!("keybinds: {c}")),
+        );
+        problems
+    }
+
+    /// Sections whose changes only apply after a restart, since they are only read at startup
+    fn needs_restart(&self, new: &Config) -> Vec<&'static str> {
+        let mut sections = Vec::new();
+        if self.capture != new.capture {
+            sections.push("[capture]");
+        }
+        if self.input != new.input {
+            sections.push("[input]");
+        }
+        // only the evdev backend checks the keybinds itself
+        if new.input.backend != InputBackend::Evdev && self.keybinds != new.keybinds {
+            sections.push("[keybinds]");
+        }
+        sections
+    }
+
+    /// The layout whose checks score best. Of those scoring the same, the one whose aspect
+    /// ratio is closest, then the first one.
+    pub fn find_matching_layout(&self, image: &image::RgbaImage) -> Option<&Layout> {
+        self.matching_layout_index(image)
+            .map(|i| &self.layouts[i].config)
+    }
+
+    /// Index of the layout [`Config::find_matching_layout`] picks
+    pub fn matching_layout_index(&self, image: &image::RgbaImage) -> Option<usize> {
+        let (width, height) = image.dimensions();
+        let mut best: Option<(usize, f32, f32)> = None;
+        for (i, variant) in self.layouts.iter().enumerate() {
+            let Some(score) = variant.score(image) else {
+                continue;
+            };
+            let error = variant.aspect_ratio_error(width, height);
+            if best.is_none_or(|(_, best_score, best_error)| {
+                score > best_score || (score == best_score && error < best_error)
+            }) {
+                best = Some((i, score, error));
+            }
+        }
+        best.map(|(i, _, _)| i)
+    }
+
+    /// The layout whose aspect ratio is closest to the image, whether it's within tolerance or not
+    pub fn closest_layout(&self, width: u32, height: u32) -> Option<&LayoutOption> {
+        self.closest_layout_index(width, height)
+            .map(|i| &self.layouts[i])
+    }
+
+    fn closest_layout_index(&self, width: u32, height: u32) -> Option<usize> {
+        (0..self.layouts.len()).min_by(|&a, &b| {
+            self.layouts[a]
+                .aspect_ratio_error(width, height)
+                .total_cmp(&self.layouts[b].aspect_ratio_error(width, height))
+        })
+    }
+
+    /// Index of the layout a screenshot of the reward screen belongs to, for proposing its pixel
+    /// checks: the matching one if its checks pass, otherwise the closest within tolerance
+    pub fn layout_index_for(&self, image: &image::RgbaImage) -> Option<usize> {
+        let (width, height) = image.dimensions();
+        self.matching_layout_index(image).or_else(|| {
+            self.closest_layout_index(width, height)
+                .filter(|&i| self.layouts[i].aspect_ratio_matches(width, height))
+        })
+    }
+
+    /// Find all matching config variants (useful for debugging)
+    pub fn find_all_matching_layouts(&self, image: &image::RgbaImage) -> Vec<&LayoutOption> {
+        self.layouts
+            .iter()
+            .filter(|variant| variant.matches(image))
+            .collect()
+    }
+}
+
+#[derive(Resource)]
+pub struct ConfigManager {
+    pub config: Config,
+    original_doc: DocumentMut,
+    /// When the file was last read, to notice when it changes
+    modified: Option<SystemTime>,
+    /// Why the file on disk isn't the active config. It isn't saved over while this is set.
+    error: Option<String>,
+    /// The config the app started with, whose sections in [`Config::needs_restart`] still apply
+    startup: Config,
+    /// Sections changed since startup, which only apply after a restart
+    restart: Vec<&'static str>,
+}
+impl Deref for ConfigManager {
+    type Target = Config;
+
+    fn deref(&self) -> &Self::Target {
+        &self.config
+    }
+}
+impl DerefMut for ConfigManager {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.config
+    }
+}
+impl FromWorld for ConfigManager {
+    fn from_world(_world: &mut bevy::ecs::world::World) -> Self {
+        let mut conf = match Self::load() {
+            Ok(conf) => conf,
+            Err(e) if std::fs::exists(paths::config_file()).unwrap_or(true) => {
+                error!("Could not load config, using the defaults until it's fixed: {e}");
+                Self {
+                    modified: file_modified(),
+                    error: Some(format!(
+                        "{} has errors, using the defaults until they're fixed:\n{e}",
+                        paths::config_file().display()
+                    )),
+                    ..Self::blank()
+                }
+            }
+            Err(_) => Self::new_from_default(),
+        };
+        conf.startup = conf.config.clone();
+        conf
+    }
+}
+
+impl ConfigManager {
+    fn blank() -> Self {
+        Self {
+            config: default(),
+            original_doc: default(),
+            modified: None,
+            error: None,
+            startup: default(),
+            restart: Vec::new(),
+        }
+    }
+
+    fn new_from_default() -> Self {
+        let conf = Config::default();
+        let mut doc = toml_edit::ser::to_document(&conf).unwrap();
+        doc.decor_mut().set_prefix("# Config for wf_overlay\n");
+        doc.get_mut("keybinds").map(|i| {
+            i.as_table_mut().map(|t| {
+                t.decor_mut().set_prefix(
+                    "# Available keys can be found in src/input/keycode.rs, modifiers are Ctrl, Shift, Alt and Super\n",
+                )
+            })
+        });
+        let mut this = Self {
+            config: conf,
+            original_doc: doc,
+            ..Self::blank()
+        };
+        this.merge_and_save().unwrap();
+        this.modified = file_modified();
+        this
+    }
+    /// Read, upgrade and validate the config file
+    fn load() -> anyhow::Result<Self> {
+        let mut modified = file_modified();
+        let src = std::fs::read_to_string(paths::config_file())?;
+        let mut original_doc: DocumentMut = src.parse()?;
+        let migrated_from = migrations::migrate(&mut original_doc)?;
+        let cfg: Config = toml_edit::de::from_document(original_doc.clone())?;
+        let problems = cfg.validate();
+        if !problems.is_empty() {
+            anyhow::bail!(problems.join("\n"));
+        }
+        // only written once it's known to work, so a failed upgrade leaves the file alone
+        if let Some(from) = migrated_from {
+            match Self::save_migrated(from, &src, &original_doc) {
+                Ok(()) => modified = file_modified(),
+                Err(e) => warn!("Could not save the upgraded config, using it anyway: {e}"),
+            }
+        }
+        Ok(Self {
+            config: cfg,
+            original_doc,
+            modified,
+            ..Self::blank()
+        })
+    }
+    /// Back up the file as it was before upgrading it from version `from`, then save the upgrade
+    fn save_migrated(from: i64, src: &str, doc: &DocumentMut) -> anyhow::Result<()> {
+        let path = paths::config_file();
+        let backup = path.with_extension(format!("v{from}.bak.toml"));
+        // an earlier attempt already backed up the same version
+        if !backup.exists() {
+            std::fs::write(&backup, src)?;
+            info!("Saved the old config to {}", backup.display());
+        }
+        std::fs::write(path, doc.to_string())?;
+        Ok(())
+    }
+    /// Why the config file on disk isn't the one in use, if it isn't
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+    /// Load the config from disk again, keeping the current one if that fails
+    pub fn reload(&mut self) -> Result<()> {
+        self.modified = file_modified();
+        let mut new = match Self::load() {
+            Ok(new) => new,
+            Err(e) => {
+                self.error = Some(format!(
+                    "{} has errors, keeping the previous config until they're fixed:\n{e}",
+                    paths::config_file().display()
+                ));
+                return Err(e.into());
+            }
+        };
+        new.restart = self.startup.needs_restart(&new.config);
+        if !new.restart.is_empty() && new.restart != self.restart {
+            warn!(
+                "Restart wf_overlay to apply the changes to {}",
+                new.restart.join(", ")
+            );
+        }
+        new.startup = std::mem::take(&mut self.startup);
+        *self = new;
+        info!("Reloaded {}", paths::config_file().display());
+        Ok(())
+    }
+    /// Replace the pixel checks of the layout at `index` and save the config right away
+    pub fn set_pixel_checks(&mut self, index: usize, checks: Vec<PixelCheck>) -> Result<()> {
+        if let Some(error) = &self.error {
+            return Err(format!("fix the config first, {error}").into());
+        }
+        let Some(option) = self.config.layouts.get_mut(index) else {
+            return Err(format!("there is no layout {index}").into());
+        };
+        option.pixel_checks = checks;
+        self.merge_and_save()?;
+        // this isn't a change to reload
+        self.modified = file_modified();
+        Ok(())
+    }
+    fn merge_and_save(&mut self) -> Result<()> {
+        let src_doc: DocumentMut = toml_edit::ser::to_document(&self.config)?;
+        Self::merge_tables(self.original_doc.as_table_mut(), src_doc.as_table());
+        std::fs::write(paths::config_file(), self.original_doc.to_string())?;
+        Ok(())
+    }
+
+    fn merge_tables(dst: &mut Table, src: &Table) {
+        for (key, src_item) in src.iter() {
+            match dst.get_mut(key) {
+                Some(dst_item) => Self::merge_items(dst_item, src_item),
+                None => {
+                    // Key only in src → clone item with its formatting
+                    dst.insert(key, src_item.clone());
+                }
+            }
+        }
+    }
+
+    fn layout_option_key(tbl: &Table) -> Option<String> {
+        tbl.get("aspect_ratio")
+            .and_then(Item::as_value)
+            .and_then(Value::as_str)
+            .map(|s| s.to_string())
+    }
+
+    fn merge_items(dst: &mut Item, src: &Item) {
+        let other = match src.clone().into_table().map(Item::Table) {
+            Ok(i) => i,
+            Err(i) => i,
+        };
+        let src = match other.into_array_of_tables().map(Item::ArrayOfTables) {
+            Ok(i) => i,
+            Err(i) => i,
+        };
+        match (dst, &src) {
+            // recurse
+            (Item::Table(dst_tbl), Item::Table(src_tbl)) => {
+                Self::merge_tables(dst_tbl, src_tbl);
+            }
+
+            // Array of tables (e.g. [[foo]])
+            (Item::ArrayOfTables(dst_aot), Item::ArrayOfTables(src_aot)) => {
+                // If first element on either side is missing unique_key, overwrite fully
+                let dst_first_key = dst_aot.iter().next().and_then(Self::layout_option_key);
+                let src_first_key = src_aot.iter().next().and_then(Self::layout_option_key);
+
+                if dst_first_key.is_none() && src_first_key.is_none() {
+                    dst_aot.clear();
+                    for src_tbl in src_aot.iter() {
+                        dst_aot.push(src_tbl.clone());
+                    }
+                    return;
+                }
+
+                let mut dst_index: HashMap<String, usize> = dst_aot
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(i, tbl)| Self::layout_option_key(tbl).map(|k| (k, i)))
+                    .collect();
+
+                let src_keys: HashSet<String> =
+                    src_aot.iter().flat_map(Self::layout_option_key).collect();
+
+                dst_aot.retain(|tbl| match Self::layout_option_key(tbl) {
+                    None => false,
+                    Some(key) => src_keys.contains(&key),
+                });
+
+                for src_tbl in src_aot.iter() {
+                    let src_key = Self::layout_option_key(src_tbl)
+                        .expect("Source table should never be missing the key at this point");
+
+                    if let Some(&dst_pos) = dst_index.get(&src_key) {
+                        if let Some(dst_tbl) = dst_aot.get_mut(dst_pos) {
+                            for (key, src_item) in src_tbl.iter() {
+                                match dst_tbl.get_mut(key) {
+                                    Some(dst_item) => Self::merge_items(dst_item, src_item),
+                                    None => {
+                                        dst_tbl.insert(key, src_item.clone());
+                                    }
+                                }
+                            }
+                        }
+                    } else {
+                        // append new entries
+                        dst_aot.push(src_tbl.clone());
+                        // and update dst_index
+                        let new_idx = dst_aot.len() - 1;
+                        dst_index.insert(src_key, new_idx);
+                    }
+                }
+            }
+
+            // Primitive values or mismatched kinds:
+            // overwrite value but try to preserve any inline comment already on dst
+            (Item::Value(dst_val), Item::Value(src_val)) => {
+                let decor = dst_val.decor().clone();
+
+                *dst_val = src_val.clone();
+                *dst_val.decor_mut() = decor; // restore decor
+            }
+
+            // fallback: replace fully
+            (dst_item, src_item) => {
+                *dst_item = src_item.clone();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout_option(aspect_ratio: [u32; 2]) -> LayoutOption {
+        LayoutOption {
+            aspect_ratio,
+            ..Config::default().layouts.remove(0)
+        }
+    }
+
+    #[test]
+    fn ultrawide_matches_21_9() {
+        let ultrawide = layout_option([21, 9]);
+        assert!(ultrawide.aspect_ratio_matches(2560, 1080));
+        assert!(ultrawide.aspect_ratio_matches(3440, 1440));
+        assert!(!ultrawide.aspect_ratio_matches(1920, 1200));
+        assert!(!ultrawide.aspect_ratio_matches(1920, 1080));
+    }
+
+    #[test]
+    fn aspect_ratio_error_is_relative() {
+        let wide = layout_option([16, 9]);
+        assert_eq!(wide.aspect_ratio_error(1920, 1080), 0.);
+        // 1.6 instead of 1.78
+        assert!((wide.aspect_ratio_error(1920, 1200) - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn closest_layout_for_other_ratios() {
+        let config = Config {
+            layouts: vec![layout_option([16, 9]), layout_option([21, 9])],
+            ..default()
+        };
+        let closest = config.closest_layout(2560, 1080).unwrap();
+        assert_eq!(closest.aspect_ratio, [21, 9]);
+        let closest = config.closest_layout(1920, 1200).unwrap();
+        assert_eq!(closest.aspect_ratio, [16, 9]);
+    }
+}
@@ -0,0 +1,410 @@
+//! Control of the running overlay over a Unix socket, for scripts and compositor keybinds.
+//!
+//! A client sends one request line like `trigger` or `price Forma Prime Blueprint`, and gets the
+//! reply as text until the connection is closed. Replies for failed requests start with `error: `.
+//! `wf_overlay trigger` and the other subcommands in [`crate::cli`] are such clients.
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::{
+        fs::{DirBuilderExt, MetadataExt, PermissionsExt},
+        net::UnixListener,
+        net::UnixStream,
+    },
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use crossbeam_channel::{Receiver, Sender, bounded, unbounded};
+
+use crate::{
+    AppState, PlatOverlayPhase,
+    cap::{CaptureMessage, CaptureState},
+    config::ConfigManager,
+    input::{Action, ActionSystems, InputDevices, InputState},
+    market::{ItemName, ItemState, PriceLookup},
+    ocr::Item,
+    template::format_age,
+};
+
+const SOCKET_NAME: &str = "wf_overlay.sock";
+/// How long a client waits for the app to answer
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn control_plugin(app: &mut App) {
+    app.add_systems(Startup, start_listening)
+        .add_systems(PreUpdate, handle_requests.after(ActionSystems));
+}
+
+/// Something a client asked the running overlay to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlRequest {
+    /// Same as pressing the trigger keybind
+    Trigger,
+    /// Same as pressing the dismiss keybind
+    Dismiss,
+    /// Capture, input, cache and OCR state
+    Status,
+    /// The market price of the item best matching the name
+    Price(String),
+    ReloadConfig,
+}
+
+impl ControlRequest {
+    pub fn parse(line: &str) -> Option<Self> {
+        let (command, arg) = line
+            .trim()
+            .split_once(' ')
+            .map_or((line.trim(), ""), |(c, a)| (c, a.trim()));
+        Some(match (command, arg) {
+            ("trigger", "") => Self::Trigger,
+            ("dismiss", "") => Self::Dismiss,
+            ("status", "") => Self::Status,
+            ("price", name) if !name.is_empty() => Self::Price(name.to_string()),
+            ("reload-config", "") => Self::ReloadConfig,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for ControlRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Trigger => write!(f, "trigger"),
+            Self::Dismiss => write!(f, "dismiss"),
+            Self::Status => write!(f, "status"),
+            Self::Price(name) => write!(f, "price {name}"),
+            Self::ReloadConfig => write!(f, "reload-config"),
+        }
+    }
+}
+
+/// `$XDG_RUNTIME_DIR/wf_overlay.sock`, or in a per-user dir in the temp dir without one
+pub fn socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join(SOCKET_NAME),
+        _ => {
+            let user = std::env::var("USER").unwrap_or_default();
+            std::env::temp_dir()
+                .join(format!("wf_overlay-{user}"))
+                .join(SOCKET_NAME)
+        }
+    }
+}
+
+/// Send a request to the running overlay and return its reply
+pub fn send(request: &ControlRequest) -> io::Result<String> {
+    let path = socket_path();
+    let mut stream = UnixStream::connect(&path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!(
+                "can't reach wf_overlay at {}, is it running? {e}",
+                path.display()
+            ),
+        )
+    })?;
+    stream.set_read_timeout(Some(REPLY_TIMEOUT * 2))?;
+    writeln!(stream, "{request}")?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply)
+}
+
+/// A request waiting for the app, with where to send the reply
+struct PendingRequest {
+    request: ControlRequest,
+    reply: Sender<Result<String, String>>,
+}
+
+#[derive(Resource)]
+struct ControlReceiver(Receiver<PendingRequest>);
+
+/// Removes the socket file when the app exits
+#[derive(Resource)]
+struct ControlSocket(PathBuf);
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn start_listening(mut commands: Commands) {
+    let path = socket_path();
+    let listener = match bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!("Can't listen on {}: {e}", path.display());
+            return;
+        }
+    };
+    info!("Listening for commands on {}", path.display());
+    let (tx, rx) = unbounded();
+    commands.insert_resource(ControlReceiver(rx));
+    commands.insert_resource(ControlSocket(path));
+    std::thread::Builder::new()
+        .name("control socket".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(|s| serve(s, &tx));
+                if let Err(e) = result {
+                    debug!("Control connection failed: {e}");
+                }
+            }
+        })
+        .expect("could spawn thread");
+}
+
+/// Bind the socket, replacing one left behind by an overlay which didn't exit cleanly
+fn bind(path: &PathBuf) -> io::Result<UnixListener> {
+    if let Some(dir) = path.parent() {
+        private_dir(dir)?;
+    }
+    match UnixListener::bind(path) {
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    e.kind(),
+                    "another wf_overlay is already running",
+                ));
+            }
+            std::fs::remove_file(path)?;
+        }
+        result => return set_private(path, result?),
+    }
+    set_private(path, UnixListener::bind(path)?)
+}
+
+/// Create `dir` so only the user can enter it, or check an existing one is like that.
+///
+/// The socket is created with the umask and only made private after, so in a dir others can
+/// enter, they could connect in between.
+fn private_dir(dir: &Path) -> io::Result<()> {
+    match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        result => return result,
+    }
+    let meta = std::fs::symlink_metadata(dir)?;
+    // owned by the effective user of this process
+    let uid = std::fs::metadata("/proc/self")?.uid();
+    if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} has to be a dir only this user can access",
+                dir.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Only the user running the overlay may control it, also if the dir is opened up later
+fn set_private(path: &PathBuf, listener: UnixListener) -> io::Result<UnixListener> {
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Answer the single request of a connection
+fn serve(mut stream: UnixStream, tx: &Sender<PendingRequest>) -> io::Result<()> {
+    stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let reply = match ControlRequest::parse(&line) {
+        Some(request) => {
+            let (reply, reply_rx) = bounded(1);
+            tx.send(PendingRequest { request, reply })
+                .map_err(|_| io::Error::other("the app stopped"))?;
+            reply_rx
+                .recv_timeout(REPLY_TIMEOUT)
+                .unwrap_or_else(|_| Err("the app didn't answer in time".to_string()))
+        }
+        None => Err(format!("unknown command {:?}", line.trim())),
+    };
+    match reply {
+        Ok(text) => writeln!(stream, "{text}"),
+        Err(e) => writeln!(stream, "error: {e}"),
+    }
+}
+
+/// Answer requests from the socket. Actions are pressed for a single frame, like a quick tap.
+fn handle_requests(
+    receiver: Option<Res<ControlReceiver>>,
+    mut actions: ResMut<ButtonInput<Action>>,
+    mut held: Local<Vec<Action>>,
+    mut conf: ResMut<ConfigManager>,
+    status: StatusParams,
+    prices: PriceLookup,
+    mut commands: Commands,
+) {
+    for action in held.drain(..) {
+        actions.release(action);
+    }
+    let Some(receiver) = receiver else {
+        return;
+    };
+    while let Ok(PendingRequest { request, reply }) = receiver.0.try_recv() {
+        debug!("Control request: {request}");
+        let result = match request {
+            ControlRequest::Trigger => {
+                actions.press(Action::Trigger);
+                held.push(Action::Trigger);
+                Ok("Triggered".to_string())
+            }
+            ControlRequest::Dismiss => {
+                actions.press(Action::Dismiss);
+                held.push(Action::Dismiss);
+                Ok("Dismissed".to_string())
+            }
+            ControlRequest::Status => Ok(status.describe(&prices, conf.error())),
+            ControlRequest::Price(name) => prices.describe(&name, &mut commands),
+            ControlRequest::ReloadConfig => conf
+                .reload()
+                .map(|()| "Reloaded the config".to_string())
+                .map_err(|e| format!("could not reload the config, keeping the current one: {e}")),
+        };
+        let _ = reply.send(result);
+    }
+}
+
+#[derive(SystemParam)]
+struct StatusParams<'w, 's> {
+    app: Res<'w, State<AppState>>,
+    phase: Option<Res<'w, State<PlatOverlayPhase>>>,
+    capture: Res<'w, State<CaptureState>>,
+    capture_message: Res<'w, CaptureMessage>,
+    input: Res<'w, State<InputState>>,
+    devices: Res<'w, InputDevices>,
+    items: Query<'w, 's, (&'static ItemState, Option<&'static ItemName>), With<Item>>,
+}
+
+impl StatusParams<'_, '_> {
+    fn describe(&self, prices: &PriceLookup, config_error: Option<&str>) -> String {
+        let mut lines = Vec::new();
+        if let Some(error) = config_error {
+            lines.push(error.to_string());
+        }
+        let overlay = match self.phase.as_ref() {
+            Some(phase) => format!("{:?} ({:?})", self.app.get(), phase.get()),
+            None => format!("{:?}", self.app.get()),
+        };
+        lines.push(format!("Overlay: {overlay}"));
+
+        let mut capture = format!("Capture: {:?}", self.capture.get());
+        if let Some(message) = &self.capture_message.0 {
+            capture += &format!(", {message}");
+        }
+        lines.push(capture);
+
+        let mut devices: Vec<&str> = self.devices.0.values().map(String::as_str).collect();
+        devices.sort_unstable();
+        let mut input = format!("Input: {:?}, {} device(s)", self.input.get(), devices.len());
+        if !devices.is_empty() {
+            input += &format!(": {}", devices.join(", "));
+        }
+        lines.push(input);
+
+        let cache = prices.cache_summary();
+        let mut market = format!(
+            "Market: {} items known, {} with prices",
+            cache.known, cache.priced
+        );
+        if let Some(oldest) = cache.oldest_age {
+            market += &format!(", oldest {} old", format_age(oldest));
+        }
+        if !prices.index_ready() {
+            market += ", item list not loaded yet";
+        }
+        lines.push(market);
+
+        if self.items.is_empty() {
+            lines.push("Last reward screen: none".to_string());
+        } else {
+            lines.push("Last reward screen:".to_string());
+            for (state, name) in &self.items {
+                let name = name.map_or("(unmatched)", |n| n.0.as_str());
+                lines.push(format!("  {name}: {state:?}"));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_requests() {
+        assert_eq!(
+            ControlRequest::parse("trigger"),
+            Some(ControlRequest::Trigger)
+        );
+        assert_eq!(
+            ControlRequest::parse(" dismiss\n"),
+            Some(ControlRequest::Dismiss)
+        );
+        assert_eq!(
+            ControlRequest::parse("status"),
+            Some(ControlRequest::Status)
+        );
+        assert_eq!(
+            ControlRequest::parse("reload-config"),
+            Some(ControlRequest::ReloadConfig)
+        );
+        assert_eq!(
+            ControlRequest::parse("price  Forma Prime Blueprint \n"),
+            Some(ControlRequest::Price("Forma Prime Blueprint".to_string()))
+        );
+    }
+
+    #[test]
+    fn reject_unknown_requests() {
+        for line in [
+            "",
+            "\n",
+            "trigger now",
+            "price",
+            "price  ",
+            "Trigger",
+            "reload",
+        ] {
+            assert_eq!(ControlRequest::parse(line), None, "{line:?}");
+        }
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for request in [
+            ControlRequest::Trigger,
+            ControlRequest::Dismiss,
+            ControlRequest::Status,
+            ControlRequest::Price("Ash Prime Systems".to_string()),
+            ControlRequest::ReloadConfig,
+        ] {
+            assert_eq!(ControlRequest::parse(&request.to_string()), Some(request));
+        }
+    }
+
+    #[test]
+    fn socket_dir_is_private() {
+        let dir = std::env::temp_dir().join(format!("wf_overlay_control_{}", std::process::id()));
+        let private = dir.join("private");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        private_dir(&private).unwrap();
+        let mode = std::fs::metadata(&private).unwrap().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        // already there
+        private_dir(&private).unwrap();
+
+        let shared = dir.join("shared");
+        std::fs::create_dir(&shared).unwrap();
+        std::fs::set_permissions(&shared, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let error = private_dir(&shared).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+    }
+}
@@ -0,0 +1,141 @@
+//! Highlight the most valuable reward, and color reward text by configurable rules
+use bevy::{math::bounding::BoundingVolume, prelude::*};
+
+use crate::{
+    PlatOverlayPhase, PlatText,
+    config::{ConfigManager, HighlightCondition, HighlightConfig, PickStrategy},
+    history::RewardHistory,
+    market::{ItemData, ItemState, Slug},
+    ocr::{self, ItemsContainer},
+};
+
+pub fn highlight_plugin(app: &mut App) {
+    app.add_systems(
+        Update,
+        (update_best_reward, color_plat_text, draw_best_border)
+            .chain()
+            .run_if(in_state(PlatOverlayPhase::Displaying)),
+    );
+}
+
+/// Marks the best reward on screen according to the configured [`PickStrategy`]
+#[derive(Component, Debug)]
+pub struct BestReward;
+
+/// The set an item belongs to, derived from its slug: `ash_prime_systems_blueprint` -> `ash_prime`
+fn set_of(slug: &str) -> Option<&str> {
+    slug.find("_prime").map(|idx| &slug[..idx + "_prime".len()])
+}
+
+impl PickStrategy {
+    /// A sortable score for a reward, higher is better
+    fn score(&self, slug: &Slug, data: &ItemData, history: &RewardHistory) -> (f32, f32) {
+        let plat = if data.avg.is_finite() { data.avg } else { 0. };
+        match self {
+            PickStrategy::Plat => (plat, 0.),
+            PickStrategy::Ducats => (data.ducats.unwrap_or(0) as f32, plat),
+            PickStrategy::SetCompletion => {
+                let picked: Vec<&str> = history
+                    .records()
+                    .iter()
+                    .filter_map(|r| r.picked.and_then(|i| r.rewards.get(i)))
+                    .filter_map(|r| r.slug.as_deref())
+                    .collect();
+                if picked.contains(&slug.as_str()) {
+                    // already have this part, only worth its plat
+                    return (0., plat);
+                }
+                let set = set_of(slug);
+                let owned = picked
+                    .iter()
+                    .filter(|p| set.is_some() && set_of(p) == set)
+                    .count();
+                (owned as f32 + 1., plat)
+            }
+        }
+    }
+}
+
+impl HighlightCondition {
+    fn matches(&self, data: &ItemData, is_best: bool) -> bool {
+        match *self {
+            HighlightCondition::Best => is_best,
+            HighlightCondition::PlatAbove { value } => data.avg > value,
+            HighlightCondition::PlatBelow { value } => data.avg < value,
+            HighlightCondition::DucatsAbove { value } => data.ducats.is_some_and(|d| d > value),
+            HighlightCondition::DucatRatioAbove { value } => data
+                .ducats
+                .is_some_and(|d| data.avg > 0. && d as f32 / data.avg > value),
+        }
+    }
+}
+
+impl HighlightConfig {
+    /// The text color for a reward: the color of the last matching rule, or white.
+    pub fn color_for(&self, data: &ItemData, is_best: bool) -> Color {
+        self.rules
+            .iter()
+            .rfind(|rule| rule.condition.matches(data, is_best))
+            .map_or(Color::WHITE, |rule| rule.color.into())
+    }
+}
+
+fn update_best_reward(
+    items: Single<&Children, With<ItemsContainer>>,
+    query: Query<(Entity, &Slug, &ItemData, Has<BestReward>)>,
+    changed: Query<(), Changed<ItemData>>,
+    history: Res<RewardHistory>,
+    conf: Res<ConfigManager>,
+    mut commands: Commands,
+) {
+    if !conf.is_changed() && !items.iter().any(|c| changed.contains(c)) {
+        return;
+    }
+    let strategy = conf.highlight.strategy;
+    let best = query
+        .iter_many(items.iter())
+        .map(|(e, slug, data, _)| (e, strategy.score(slug, data, &history)))
+        .max_by(|a, b| a.1.0.total_cmp(&b.1.0).then(a.1.1.total_cmp(&b.1.1)))
+        .map(|(e, _)| e);
+
+    for (e, _, _, is_best) in query.iter_many(items.iter()) {
+        if Some(e) == best && !is_best {
+            commands.entity(e).insert(BestReward);
+        } else if Some(e) != best && is_best {
+            commands.entity(e).remove::<BestReward>();
+        }
+    }
+}
+
+fn color_plat_text(
+    mut texts: Query<(&mut TextColor, &ChildOf), With<PlatText>>,
+    items: Query<(&ItemData, &ItemState, Has<BestReward>)>,
+    conf: Res<ConfigManager>,
+) {
+    for (mut color, child_of) in texts.iter_mut() {
+        let Ok((data, state, is_best)) = items.get(child_of.parent()) else {
+            continue;
+        };
+        let new = match state {
+            ItemState::Fresh => conf.highlight.color_for(data, is_best),
+            // old prices are dimmed, so they stand out less than fresh ones
+            ItemState::Stale { .. } => conf.highlight.color_for(data, is_best).with_alpha(0.6),
+            _ => continue,
+        };
+        color.set_if_neq(TextColor(new));
+    }
+}
+
+fn draw_best_border(
+    mut gizmos: Gizmos,
+    best: Query<&ocr::Item, With<BestReward>>,
+    conf: Res<ConfigManager>,
+) {
+    for item in best.iter() {
+        gizmos.rect_2d(
+            item.bounds.center(),
+            (item.bounds.half_size() * 2.).abs() + Vec2::splat(8.),
+            conf.highlight.border_color,
+        );
+    }
+}
@@ -0,0 +1,472 @@
+//! Persistent log of every reward screen, and the statistics derived from it
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::anyhow;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    PlatOverlayPhase,
+    market::{ItemData, Slug, unix_now},
+    ocr::{self, ItemsContainer},
+    paths,
+    pick::ConfirmedPick,
+};
+
+/// How many entries the "best drops" lists contain
+const BEST_DROPS: usize = 5;
+/// Seconds between reward screens after which a new session starts
+const SESSION_GAP: u64 = 2 * 60 * 60;
+
+pub fn history_plugin(app: &mut App) {
+    app.insert_resource(RewardHistory::restore_from_disk_or_empty())
+        .add_systems(OnExit(PlatOverlayPhase::Displaying), record_reward_screen);
+}
+
+/// A single reward as shown on a reward screen
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RewardEntry {
+    /// The name as read by OCR
+    pub name: String,
+    pub slug: Option<String>,
+    pub avg: Option<f32>,
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+    pub ducats: Option<u32>,
+}
+
+impl RewardEntry {
+    fn new(item: &ocr::Item, slug: Option<&Slug>, data: Option<&ItemData>) -> Self {
+        // NaN means "no data", which serde_json would write as null anyway
+        let finite = |v: f32| v.is_finite().then_some(v);
+        Self {
+            name: item.name.clone(),
+            slug: slug.map(|s| s.0.clone()),
+            avg: data.and_then(|d| finite(d.avg)),
+            min: data.and_then(|d| finite(d.min)),
+            max: data.and_then(|d| finite(d.max)),
+            ducats: data.and_then(|d| d.ducats),
+        }
+    }
+}
+
+/// Everything known about one reward screen
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RewardRecord {
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+    pub rewards: Vec<RewardEntry>,
+    /// Index into `rewards` of the reward the player picked, if it was detected
+    pub picked: Option<usize>,
+}
+
+impl RewardRecord {
+    /// The plat value of this run: the picked reward if known, otherwise the best one on offer.
+    pub fn value(&self) -> Option<f32> {
+        match self.picked.and_then(|i| self.rewards.get(i)) {
+            Some(picked) => picked.avg,
+            None => self
+                .rewards
+                .iter()
+                .filter_map(|r| r.avg)
+                .max_by(f32::total_cmp),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub runs: usize,
+    /// Runs for which a plat value was known
+    pub valued_runs: usize,
+    pub total_value: f32,
+    /// Highest value rewards seen, best first
+    pub best_drops: Vec<RewardEntry>,
+}
+
+impl Stats {
+    fn from_records<'a>(records: impl IntoIterator<Item = &'a RewardRecord>) -> Self {
+        let mut stats = Self::default();
+        let mut drops: Vec<&RewardEntry> = Vec::new();
+        for record in records {
+            stats.runs += 1;
+            if let Some(value) = record.value() {
+                stats.valued_runs += 1;
+                stats.total_value += value;
+            }
+            match record.picked.and_then(|i| record.rewards.get(i)) {
+                Some(picked) => drops.push(picked),
+                None => drops.extend(record.rewards.iter()),
+            }
+        }
+        drops.retain(|r| r.avg.is_some());
+        drops.sort_by(|a, b| b.avg.unwrap_or(0.).total_cmp(&a.avg.unwrap_or(0.)));
+        stats.best_drops = drops.into_iter().take(BEST_DROPS).cloned().collect();
+        stats
+    }
+
+    pub fn average_value(&self) -> f32 {
+        if self.valued_runs == 0 {
+            0.
+        } else {
+            self.total_value / self.valued_runs as f32
+        }
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} runs, {:.1}p total, {:.1}p average per run",
+            self.runs,
+            self.total_value,
+            self.average_value()
+        )?;
+        for drop in &self.best_drops {
+            writeln!(
+                f,
+                "  {:.1}p {}",
+                drop.avg.unwrap_or_default(),
+                drop.slug.as_deref().unwrap_or(&drop.name)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct RewardHistory {
+    records: Vec<RewardRecord>,
+}
+
+impl RewardHistory {
+    pub fn records(&self) -> &[RewardRecord] {
+        &self.records
+    }
+
+    /// Stats of the reward screens since the last break longer than [`SESSION_GAP`], which
+    /// are none if that break is still going on
+    pub fn session_stats(&self) -> Stats {
+        Stats::from_records(&self.records[self.session_start(unix_now())..])
+    }
+
+    /// Index of the first record of the session going on at `now`
+    fn session_start(&self, now: u64) -> usize {
+        let mut start = self.records.len();
+        let mut next = now;
+        for (i, record) in self.records.iter().enumerate().rev() {
+            if next.saturating_sub(record.timestamp) > SESSION_GAP {
+                break;
+            }
+            start = i;
+            next = record.timestamp;
+        }
+        start
+    }
+
+    pub fn lifetime_stats(&self) -> Stats {
+        Stats::from_records(&self.records)
+    }
+
+    fn push(&mut self, record: RewardRecord) {
+        if let Err(e) = Self::append_to_disk(&record) {
+            error!("Could not save reward history: {e}");
+        }
+        self.records.push(record);
+    }
+
+    fn append_to_disk(record: &RewardRecord) -> Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(paths::history_file())?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, record)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn restore_from_disk_or_empty() -> Self {
+        let mut records = Vec::new();
+        let path = paths::history_file();
+        if let Ok(file) = File::open(&path) {
+            for (i, line) in BufReader::new(file).lines().map_while(Result::ok).enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str(&line) {
+                    Ok(record) => records.push(record),
+                    Err(e) => warn!("Skipping broken line {} in {}: {e}", i + 1, path.display()),
+                }
+            }
+        }
+        Self { records }
+    }
+
+    /// Export all records to `path`, as CSV or JSON depending on the file extension.
+    pub fn export(&self, path: &Path) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::to_writer_pretty(&mut writer, &self.records)?,
+            Some("csv") => self.write_csv(&mut writer)?,
+            _ => return Err(anyhow!("Unknown export format, use a .csv or .json file").into()),
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn write_csv(&self, w: &mut impl Write) -> std::io::Result<()> {
+        fn opt<T: ToString>(v: Option<T>) -> String {
+            v.map(|v| v.to_string()).unwrap_or_default()
+        }
+        fn quote(s: &str) -> String {
+            format!("\"{}\"", s.replace('"', "\"\""))
+        }
+
+        writeln!(w, "timestamp,slot,name,slug,avg,min,max,ducats,picked")?;
+        for record in &self.records {
+            let ts = jiff::Timestamp::from_second(record.timestamp as i64)
+                .map(|t| t.to_string())
+                .unwrap_or_default();
+            for (slot, reward) in record.rewards.iter().enumerate() {
+                writeln!(
+                    w,
+                    "{ts},{slot},{},{},{},{},{},{},{}",
+                    quote(&reward.name),
+                    quote(reward.slug.as_deref().unwrap_or_default()),
+                    opt(reward.avg),
+                    opt(reward.min),
+                    opt(reward.max),
+                    opt(reward.ducats),
+                    opt(record.picked.map(|p| p == slot)),
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Marks items which are already in the history, when their overlay is shown again
+#[derive(Component)]
+struct Recorded;
+
+fn record_reward_screen(
+    items: Single<&Children, With<ItemsContainer>>,
+    query: Query<(
+        Entity,
+        &ocr::Item,
+        Option<&Slug>,
+        Option<&ItemData>,
+        Has<ConfirmedPick>,
+        Has<Recorded>,
+    )>,
+    mut history: ResMut<RewardHistory>,
+    mut commands: Commands,
+) {
+    let mut picked = None;
+    let mut recorded = false;
+    let rewards: Vec<RewardEntry> = query
+        .iter_many(items.iter())
+        .enumerate()
+        .map(|(idx, (entity, item, slug, data, is_picked, is_recorded))| {
+            if is_picked {
+                picked = Some(idx);
+            }
+            recorded |= is_recorded;
+            commands.entity(entity).insert(Recorded);
+            RewardEntry::new(item, slug, data)
+        })
+        .collect();
+    if rewards.is_empty() || recorded {
+        return;
+    }
+    history.push(RewardRecord {
+        timestamp: unix_now(),
+        rewards,
+        picked,
+    });
+    info!("Session: {}", history.session_stats());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(timestamps: &[u64]) -> RewardHistory {
+        let records = timestamps
+            .iter()
+            .map(|&timestamp| RewardRecord {
+                timestamp,
+                rewards: Vec::new(),
+                picked: None,
+            })
+            .collect();
+        RewardHistory { records }
+    }
+
+    #[test]
+    fn session_starts_after_long_break() {
+        let now = 100_000;
+        let history = history(&[1_000, now - SESSION_GAP - 600, now - 600, now - 60]);
+        assert_eq!(history.session_start(now), 2);
+    }
+
+    #[test]
+    fn session_is_empty_during_break() {
+        let now = 100_000;
+        let history = history(&[now - SESSION_GAP - 60]);
+        assert_eq!(history.session_start(now), 1);
+    }
+
+    #[test]
+    fn session_spans_short_breaks() {
+        let now = 100_000;
+        // only breaks longer than the gap end a session
+        let history = history(&[
+            now - 3 * SESSION_GAP - 1,
+            now - 2 * SESSION_GAP,
+            now - SESSION_GAP,
+            now,
+        ]);
+        assert_eq!(history.session_start(now), 1);
+    }
+
+    fn entry(name: &str, avg: Option<f32>) -> RewardEntry {
+        RewardEntry {
+            name: name.to_string(),
+            slug: Some(name.to_lowercase().replace(' ', "_")),
+            avg,
+            min: avg.map(|a| a - 1.),
+            max: avg.map(|a| a + 1.),
+            ducats: Some(45),
+        }
+    }
+
+    fn record(timestamp: u64, rewards: Vec<RewardEntry>, picked: Option<usize>) -> RewardRecord {
+        RewardRecord {
+            timestamp,
+            rewards,
+            picked,
+        }
+    }
+
+    #[test]
+    fn stats() {
+        let records = [
+            record(
+                1,
+                vec![
+                    entry("A", Some(10.)),
+                    entry("B", Some(5.)),
+                    entry("C", Some(40.)),
+                ],
+                Some(1),
+            ),
+            // the best one on offer if nothing was picked
+            record(2, vec![entry("D", Some(20.)), entry("E", None)], None),
+            record(3, vec![entry("F", None)], None),
+            // picked, but without a price
+            record(4, vec![entry("G", None), entry("H", Some(30.))], Some(0)),
+        ];
+        let stats = Stats::from_records(&records);
+        assert_eq!(stats.runs, 4);
+        assert_eq!(stats.valued_runs, 2);
+        assert_eq!(stats.total_value, 25.);
+        assert_eq!(stats.average_value(), 12.5);
+        let best: Vec<&str> = stats.best_drops.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(best, ["D", "B"]);
+
+        assert_eq!(Stats::from_records(&[]).average_value(), 0.);
+    }
+
+    #[test]
+    fn best_drops_are_limited() {
+        let records: Vec<RewardRecord> = (0..BEST_DROPS + 2)
+            .map(|i| record(i as u64, vec![entry(&i.to_string(), Some(i as f32))], None))
+            .collect();
+        let stats = Stats::from_records(&records);
+        let best: Vec<f32> = stats.best_drops.iter().filter_map(|r| r.avg).collect();
+        assert_eq!(best, [6., 5., 4., 3., 2.]);
+    }
+
+    #[test]
+    fn session_and_lifetime_stats() {
+        let now = unix_now();
+        let history = RewardHistory {
+            records: vec![
+                record(now - 6 * 3600, vec![entry("A", Some(10.))], Some(0)),
+                record(now - 5 * 3600, vec![entry("B", Some(20.))], Some(0)),
+                record(now - 3600, vec![entry("C", Some(30.))], Some(0)),
+                record(now - 600, vec![entry("D", Some(50.))], Some(0)),
+            ],
+        };
+        let session = history.session_stats();
+        assert_eq!(session.runs, 2);
+        assert_eq!(session.total_value, 80.);
+        let lifetime = history.lifetime_stats();
+        assert_eq!(lifetime.runs, 4);
+        assert_eq!(lifetime.total_value, 110.);
+    }
+
+    fn export_history() -> RewardHistory {
+        let forma = RewardEntry {
+            name: "Forma Blueprint".to_string(),
+            slug: None,
+            avg: None,
+            min: None,
+            max: None,
+            ducats: None,
+        };
+        let ash = RewardEntry {
+            name: "Ash \"Prime\"".to_string(),
+            slug: Some("ash_prime".to_string()),
+            avg: Some(12.5),
+            min: Some(10.),
+            max: Some(15.),
+            ducats: Some(45),
+        };
+        RewardHistory {
+            records: vec![
+                record(1_000, vec![forma.clone(), ash], Some(1)),
+                record(2_000, vec![forma], None),
+            ],
+        }
+    }
+
+    #[test]
+    fn export() {
+        let dir = std::env::temp_dir().join(format!("wf_overlay_history_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let history = export_history();
+
+        history.export(&dir.join("history.csv")).unwrap();
+        let csv = std::fs::read_to_string(dir.join("history.csv")).unwrap();
+        assert_eq!(
+            csv,
+            "timestamp,slot,name,slug,avg,min,max,ducats,picked\n\
+             1970-01-01T00:16:40Z,0,\"Forma Blueprint\",\"\",,,,,false\n\
+             1970-01-01T00:16:40Z,1,\"Ash \"\"Prime\"\"\",\"ash_prime\",12.5,10,15,45,true\n\
+             1970-01-01T00:33:20Z,0,\"Forma Blueprint\",\"\",,,,,\n"
+        );
+
+        history.export(&dir.join("history.json")).unwrap();
+        let json = std::fs::read_to_string(dir.join("history.json")).unwrap();
+        let records: Vec<RewardRecord> = serde_json::from_str(&json).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].timestamp, 1_000);
+        assert_eq!(records[0].picked, Some(1));
+        assert_eq!(records[0].rewards[1].name, "Ash \"Prime\"");
+        assert_eq!(records[0].rewards[1].avg, Some(12.5));
+        assert_eq!(records[1].rewards[0].avg, None);
+        assert_eq!(records[1].picked, None);
+
+        assert!(history.export(&dir.join("history.txt")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
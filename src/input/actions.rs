@@ -0,0 +1,446 @@
+//! Named actions, bound to key chords in the config
+use std::fmt;
+
+use bevy::{platform::collections::HashMap, prelude::*};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::buttons::{GlobalButtons, InputButton};
+
+/// Something the user can do with a keybind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Read the reward screen and show prices
+    Trigger,
+    /// Close the overlay
+    Dismiss,
+    /// Show the overlay for the last reward screen again
+    RepeatLast,
+    /// Toggle the layout editor
+    EditLayout,
+    /// Save the next frame to `images/` in the data dir
+    SaveCapture,
+    ReloadConfig,
+}
+
+impl Action {
+    pub const ALL: [Action; 6] = [
+        Action::Trigger,
+        Action::Dismiss,
+        Action::RepeatLast,
+        Action::EditLayout,
+        Action::SaveCapture,
+        Action::ReloadConfig,
+    ];
+
+    /// The name of the action in the config
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Trigger => "trigger",
+            Action::Dismiss => "dismiss",
+            Action::RepeatLast => "repeat_last",
+            Action::EditLayout => "edit_layout",
+            Action::SaveCapture => "save_capture",
+            Action::ReloadConfig => "reload_config",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.name() == name)
+    }
+
+    /// What the action does, shown by the desktop when binding shortcuts through the portal
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::Trigger => "Read the reward screen and show prices",
+            Action::Dismiss => "Close the overlay",
+            Action::RepeatLast => "Show the last reward screen again",
+            Action::EditLayout => "Toggle the layout editor",
+            Action::SaveCapture => "Save the next captured frame",
+            Action::ReloadConfig => "Reload the config file",
+        }
+    }
+}
+
+/// Modifier keys, either side counts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub super_key: bool,
+}
+
+impl Modifiers {
+    /// The modifiers currently held, apart from `except`, the button of the chord itself
+    fn held(keys: &ButtonInput<KeyCode>, except: InputButton) -> Self {
+        let any = |pair: [KeyCode; 2]| {
+            pair.iter()
+                .any(|k| InputButton::Key(*k) != except && keys.pressed(*k))
+        };
+        Self {
+            ctrl: any([KeyCode::ControlLeft, KeyCode::ControlRight]),
+            shift: any([KeyCode::ShiftLeft, KeyCode::ShiftRight]),
+            alt: any([KeyCode::AltLeft, KeyCode::AltRight]),
+            super_key: any([KeyCode::SuperLeft, KeyCode::SuperRight]),
+        }
+    }
+}
+
+/// A key or button with the modifiers which have to be held with it, like `Ctrl+Shift+KeyP`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyChord {
+    pub modifiers: Modifiers,
+    pub button: InputButton,
+}
+
+impl From<KeyCode> for KeyChord {
+    fn from(key: KeyCode) -> Self {
+        Self {
+            modifiers: default(),
+            button: InputButton::Key(key),
+        }
+    }
+}
+
+impl KeyChord {
+    /// Whether the chord was completed this frame.
+    ///
+    /// The modifiers have to match exactly, so `Ctrl+KeyP` doesn't also trigger `KeyP`.
+    fn just_pressed(&self, buttons: &GlobalButtons) -> bool {
+        buttons.just_pressed(self.button)
+            && Modifiers::held(&buttons.keys, self.button) == self.modifiers
+    }
+}
+
+impl std::str::FromStr for KeyChord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let button = parts.pop().filter(|b| !b.is_empty()).ok_or("missing key")?;
+        let mut modifiers = Modifiers::default();
+        for part in parts {
+            let flag = match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => &mut modifiers.ctrl,
+                "shift" => &mut modifiers.shift,
+                "alt" => &mut modifiers.alt,
+                "super" | "meta" | "win" => &mut modifiers.super_key,
+                _ => return Err(format!("unknown modifier {part}")),
+            };
+            *flag = true;
+        }
+        let button =
+            InputButton::parse(button).ok_or_else(|| format!("unknown key or button {button}"))?;
+        Ok(Self { modifiers, button })
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Modifiers {
+            ctrl,
+            shift,
+            alt,
+            super_key,
+        } = self.modifiers;
+        for (held, name) in [
+            (ctrl, "Ctrl"),
+            (shift, "Shift"),
+            (alt, "Alt"),
+            (super_key, "Super"),
+        ] {
+            if held {
+                write!(f, "{name}+")?;
+            }
+        }
+        write!(f, "{}", self.button)
+    }
+}
+
+impl Serialize for KeyChord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyChord {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|e| serde::de::Error::custom(format!("Invalid keybind {s}: {e}")))
+    }
+}
+
+/// The chords bound to each action, any of them triggers it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Keybinds {
+    pub trigger: Vec<KeyChord>,
+    pub dismiss: Vec<KeyChord>,
+    pub repeat_last: Vec<KeyChord>,
+    pub edit_layout: Vec<KeyChord>,
+    pub save_capture: Vec<KeyChord>,
+    pub reload_config: Vec<KeyChord>,
+}
+
+impl Default for Keybinds {
+    fn default() -> Self {
+        Self {
+            trigger: vec![KeyCode::Equal.into()],
+            dismiss: vec![KeyCode::Escape.into()],
+            repeat_last: vec![KeyChord {
+                modifiers: Modifiers {
+                    ctrl: true,
+                    ..default()
+                },
+                button: InputButton::Key(KeyCode::Equal),
+            }],
+            edit_layout: vec![],
+            save_capture: vec![],
+            reload_config: vec![],
+        }
+    }
+}
+
+impl Keybinds {
+    pub fn iter(&self) -> impl Iterator<Item = (Action, &[KeyChord])> {
+        [
+            (Action::Trigger, self.trigger.as_slice()),
+            (Action::Dismiss, &self.dismiss),
+            (Action::RepeatLast, &self.repeat_last),
+            (Action::EditLayout, &self.edit_layout),
+            (Action::SaveCapture, &self.save_capture),
+            (Action::ReloadConfig, &self.reload_config),
+        ]
+        .into_iter()
+    }
+
+    /// Chords bound to more than one action, only the first of which will work
+    pub fn conflicts(&self) -> Vec<String> {
+        let mut bound: HashMap<KeyChord, Action> = HashMap::new();
+        let mut conflicts = Vec::new();
+        for (action, chords) in self.iter() {
+            for chord in chords {
+                match bound.get(chord) {
+                    Some(first) if *first == action => {}
+                    Some(first) => conflicts.push(format!(
+                        "{chord} is bound to both {} and {}, it only triggers {}",
+                        first.name(),
+                        action.name(),
+                        first.name()
+                    )),
+                    None => {
+                        bound.insert(*chord, action);
+                    }
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Press and release actions as their chords are.
+    ///
+    /// Actions are pressed once when the chord is completed, key repeat doesn't press them again.
+    /// `actions` has to be cleared for this frame already, it may also hold some from the portal.
+    pub fn update(&self, buttons: &GlobalButtons, actions: &mut ButtonInput<Action>) {
+        let mut used = Vec::new();
+        for (action, chords) in self.iter() {
+            for chord in chords {
+                if chord.just_pressed(buttons) && !used.contains(chord) {
+                    used.push(*chord);
+                    actions.press(action);
+                }
+                if buttons.just_released(chord.button) && actions.pressed(action) {
+                    actions.release(action);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::buttons::GlobalButtonsMut;
+
+    fn chord(s: &str) -> KeyChord {
+        s.parse().unwrap()
+    }
+
+    /// Buttons and the actions they press, a frame at a time
+    struct Frames {
+        world: World,
+        keybinds: Keybinds,
+    }
+
+    impl Frames {
+        fn new(keybinds: Keybinds) -> Self {
+            let mut world = World::new();
+            world.init_resource::<ButtonInput<KeyCode>>();
+            world.init_resource::<ButtonInput<MouseButton>>();
+            world.init_resource::<ButtonInput<GamepadButton>>();
+            world.init_resource::<ButtonInput<Action>>();
+            Self { world, keybinds }
+        }
+
+        /// Press and release the buttons, then update the actions like the input systems do
+        fn frame(&mut self, changes: &[(&str, bool)]) -> &ButtonInput<Action> {
+            self.world.resource_mut::<ButtonInput<KeyCode>>().clear();
+            self.world
+                .resource_mut::<ButtonInput<MouseButton>>()
+                .clear();
+            self.world
+                .resource_mut::<ButtonInput<GamepadButton>>()
+                .clear();
+            self.world.resource_mut::<ButtonInput<Action>>().clear();
+            for &(name, pressed) in changes {
+                let button = InputButton::parse(name).unwrap();
+                self.world
+                    .run_system_once(move |mut buttons: GlobalButtonsMut| {
+                        buttons.set(button, pressed)
+                    })
+                    .unwrap();
+            }
+            let keybinds = self.keybinds.clone();
+            self.world
+                .run_system_once(
+                    move |buttons: GlobalButtons, mut actions: ResMut<ButtonInput<Action>>| {
+                        keybinds.update(&buttons, &mut actions)
+                    },
+                )
+                .unwrap();
+            self.world.resource()
+        }
+    }
+
+    #[test]
+    fn parse_chords() {
+        let ctrl_shift = Modifiers {
+            ctrl: true,
+            shift: true,
+            ..default()
+        };
+        assert_eq!(
+            chord("Ctrl+Shift+P"),
+            KeyChord {
+                modifiers: ctrl_shift,
+                button: InputButton::Key(KeyCode::KeyP),
+            }
+        );
+        assert_eq!(chord("shift + control + KeyP"), chord("Ctrl+Shift+P"));
+        assert_eq!(chord("Ctrl+Shift+P").to_string(), "Ctrl+Shift+KeyP");
+        assert_eq!(chord("Meta+F5").to_string(), "Super+F5");
+
+        assert_eq!(
+            chord("MouseBack").button,
+            InputButton::Mouse(MouseButton::Back)
+        );
+        assert_eq!(
+            chord("Alt+Mouse8").button,
+            InputButton::Mouse(MouseButton::Other(8))
+        );
+        assert_eq!(
+            chord("GamepadSouth").button,
+            InputButton::Gamepad(GamepadButton::South)
+        );
+        for s in ["Ctrl+Shift+KeyP", "Alt+Mouse8", "MouseBack", "GamepadSouth"] {
+            assert_eq!(chord(s).to_string(), s);
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!("".parse::<KeyChord>().is_err());
+        assert!("Ctrl+".parse::<KeyChord>().is_err());
+        assert!("Hyper+P".parse::<KeyChord>().is_err());
+        assert!("Ctrl+NoSuchKey".parse::<KeyChord>().is_err());
+        assert!("MouseNoSuchButton".parse::<KeyChord>().is_err());
+    }
+
+    #[test]
+    fn conflicts() {
+        assert!(Keybinds::default().conflicts().is_empty());
+
+        let keybinds = Keybinds {
+            trigger: vec![chord("Equal"), chord("Equal")],
+            dismiss: vec![chord("Escape"), chord("Equal")],
+            ..default()
+        };
+        let conflicts = keybinds.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert!(conflicts[0].contains("trigger") && conflicts[0].contains("dismiss"));
+
+        // only the first action is pressed
+        let mut frames = Frames::new(keybinds);
+        let actions = frames.frame(&[("Equal", true)]);
+        assert!(actions.just_pressed(Action::Trigger));
+        assert!(!actions.pressed(Action::Dismiss));
+    }
+
+    #[test]
+    fn modifiers_match_exactly() {
+        let mut frames = Frames::new(Keybinds::default());
+        let actions = frames.frame(&[("ControlLeft", true), ("Equal", true)]);
+        assert!(actions.just_pressed(Action::RepeatLast));
+        assert!(!actions.pressed(Action::Trigger));
+        frames.frame(&[("ControlLeft", false), ("Equal", false)]);
+
+        let actions = frames.frame(&[("Equal", true)]);
+        assert!(actions.just_pressed(Action::Trigger));
+        assert!(!actions.pressed(Action::RepeatLast));
+        frames.frame(&[("Equal", false)]);
+
+        // a modifier which isn't part of the chord
+        let actions = frames.frame(&[("ShiftRight", true), ("Equal", true)]);
+        assert!(!actions.pressed(Action::Trigger));
+        assert!(!actions.pressed(Action::RepeatLast));
+    }
+
+    #[test]
+    fn press_repeat_release() {
+        let mut frames = Frames::new(Keybinds::default());
+        assert!(
+            frames
+                .frame(&[("Equal", true)])
+                .just_pressed(Action::Trigger)
+        );
+
+        // key repeat presses the key again while it's held
+        let actions = frames.frame(&[("Equal", true)]);
+        assert!(!actions.just_pressed(Action::Trigger));
+        assert!(actions.pressed(Action::Trigger));
+
+        let actions = frames.frame(&[("Equal", false)]);
+        assert!(actions.just_released(Action::Trigger));
+        assert!(!actions.pressed(Action::Trigger));
+    }
+
+    #[test]
+    fn mouse_and_gamepad_buttons() {
+        let mut frames = Frames::new(Keybinds {
+            trigger: vec![chord("MouseBack")],
+            dismiss: vec![chord("GamepadSouth")],
+            edit_layout: vec![chord("Ctrl+Mouse8")],
+            ..default()
+        });
+        let actions = frames.frame(&[("MouseBack", true), ("GamepadSouth", true)]);
+        assert!(actions.just_pressed(Action::Trigger));
+        assert!(actions.just_pressed(Action::Dismiss));
+
+        let actions = frames.frame(&[("MouseBack", false), ("GamepadSouth", false)]);
+        assert!(actions.just_released(Action::Trigger));
+        assert!(actions.just_released(Action::Dismiss));
+
+        assert!(
+            !frames
+                .frame(&[("Mouse8", true)])
+                .pressed(Action::EditLayout)
+        );
+        frames.frame(&[("Mouse8", false)]);
+        let actions = frames.frame(&[("ControlRight", true), ("Mouse8", true)]);
+        assert!(actions.just_pressed(Action::EditLayout));
+    }
+}
@@ -0,0 +1,242 @@
+//! Mouse and gamepad buttons, next to keyboard keys
+use std::fmt;
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use evdev::{AbsoluteAxisCode, EventSummary, InputEvent, KeyCode as Code};
+use winit::{keyboard::PhysicalKey, platform::scancode::PhysicalKeyExtScancode};
+
+use super::keycode;
+
+/// Anything that can be bound to an action
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InputButton {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+}
+
+/// Start of the `BTN_*` codes
+const BTN_MISC: u16 = 0x100;
+/// Start of the buttons of joysticks that aren't gamepads
+const BTN_JOYSTICK: u16 = 0x120;
+/// Extra buttons, like the back paddles of some controllers
+const BTN_TRIGGER_HAPPY: u16 = 0x2c0;
+
+impl InputButton {
+    /// The buttons pressed (`true`) or released by an evdev event. Key repeat changes nothing.
+    pub fn changes(event: &InputEvent) -> Vec<(Self, bool)> {
+        match event.destructure() {
+            EventSummary::Key(_, code, value) => {
+                let button = Self::from_evdev(code).unwrap_or_else(|| {
+                    let physical = PhysicalKey::from_scancode(code.code() as u32);
+                    Self::Key(keycode::convert_physical_key_code(physical))
+                });
+                // 2 is key repeat, the key is still held
+                match value {
+                    0 => vec![(button, false)],
+                    1 => vec![(button, true)],
+                    _ => vec![],
+                }
+            }
+            EventSummary::AbsoluteAxis(_, axis, value) => {
+                let Some((both, pressed)) = Self::from_hat(axis, value) else {
+                    return vec![];
+                };
+                let released = both.into_iter().filter(|b| Some(*b) != pressed);
+                released
+                    .map(|b| (b, false))
+                    .chain(pressed.map(|b| (b, true)))
+                    .collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// The mouse or gamepad button of an evdev key event, `None` for keyboard keys.
+    ///
+    /// Buttons without a bevy name become `Other`, numbered by their offset to the start of their range.
+    fn from_evdev(code: Code) -> Option<Self> {
+        use GamepadButton as G;
+        use MouseButton as M;
+        let button = match code {
+            Code::BTN_LEFT => Self::Mouse(M::Left),
+            Code::BTN_RIGHT => Self::Mouse(M::Right),
+            Code::BTN_MIDDLE => Self::Mouse(M::Middle),
+            Code::BTN_SIDE | Code::BTN_BACK => Self::Mouse(M::Back),
+            Code::BTN_EXTRA | Code::BTN_FORWARD => Self::Mouse(M::Forward),
+            Code::BTN_SOUTH => Self::Gamepad(G::South),
+            Code::BTN_EAST => Self::Gamepad(G::East),
+            Code::BTN_NORTH => Self::Gamepad(G::North),
+            Code::BTN_WEST => Self::Gamepad(G::West),
+            Code::BTN_C => Self::Gamepad(G::C),
+            Code::BTN_Z => Self::Gamepad(G::Z),
+            Code::BTN_TL => Self::Gamepad(G::LeftTrigger),
+            Code::BTN_TR => Self::Gamepad(G::RightTrigger),
+            Code::BTN_TL2 => Self::Gamepad(G::LeftTrigger2),
+            Code::BTN_TR2 => Self::Gamepad(G::RightTrigger2),
+            Code::BTN_SELECT => Self::Gamepad(G::Select),
+            Code::BTN_START => Self::Gamepad(G::Start),
+            Code::BTN_MODE => Self::Gamepad(G::Mode),
+            Code::BTN_THUMBL => Self::Gamepad(G::LeftThumb),
+            Code::BTN_THUMBR => Self::Gamepad(G::RightThumb),
+            Code::BTN_DPAD_UP => Self::Gamepad(G::DPadUp),
+            Code::BTN_DPAD_DOWN => Self::Gamepad(G::DPadDown),
+            Code::BTN_DPAD_LEFT => Self::Gamepad(G::DPadLeft),
+            Code::BTN_DPAD_RIGHT => Self::Gamepad(G::DPadRight),
+            _ => match code.code() {
+                // BTN_0 to BTN_9, used by some mice with many buttons, and BTN_TASK
+                c @ (BTN_MISC..=0x10f | 0x117) => Self::Mouse(M::Other(c - BTN_MISC)),
+                c @ BTN_JOYSTICK..=0x12f => Self::Gamepad(G::Other((c - BTN_JOYSTICK) as u8)),
+                c @ BTN_TRIGGER_HAPPY..=0x2e7 => {
+                    Self::Gamepad(G::Other((c - BTN_TRIGGER_HAPPY) as u8 + 16))
+                }
+                _ => return None,
+            },
+        };
+        Some(button)
+    }
+
+    /// The d-pad buttons pressed by a hat axis at `value`, and the ones released.
+    ///
+    /// Many controllers report their d-pad as such an axis instead of buttons.
+    fn from_hat(axis: AbsoluteAxisCode, value: i32) -> Option<([Self; 2], Option<Self>)> {
+        let (negative, positive) = match axis {
+            AbsoluteAxisCode::ABS_HAT0X => (GamepadButton::DPadLeft, GamepadButton::DPadRight),
+            AbsoluteAxisCode::ABS_HAT0Y => (GamepadButton::DPadUp, GamepadButton::DPadDown),
+            _ => return None,
+        };
+        let both = [Self::Gamepad(negative), Self::Gamepad(positive)];
+        let pressed = match value.signum() {
+            -1 => Some(Self::Gamepad(negative)),
+            1 => Some(Self::Gamepad(positive)),
+            _ => None,
+        };
+        Some((both, pressed))
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        use serde::de::{
+            DeserializeOwned, IntoDeserializer,
+            value::{Error, StrDeserializer},
+        };
+        fn variant<T: DeserializeOwned>(name: &str) -> Option<T> {
+            // `back` or `f5` for `Back` and `F5`
+            let mut chars = name.chars();
+            let name = chars
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())?;
+            let de: StrDeserializer<Error> = name.as_str().into_deserializer();
+            T::deserialize(de).ok()
+        }
+        let prefixed = |prefix: &str| {
+            name.get(..prefix.len())
+                .filter(|p| p.eq_ignore_ascii_case(prefix))
+                .map(|_| &name[prefix.len()..])
+        };
+
+        if let Some(rest) = prefixed("Mouse") {
+            rest.parse()
+                .map(MouseButton::Other)
+                .ok()
+                .or_else(|| variant(rest))
+                .map(Self::Mouse)
+        } else if let Some(rest) = prefixed("Gamepad") {
+            rest.parse()
+                .map(GamepadButton::Other)
+                .ok()
+                .or_else(|| variant(rest))
+                .map(Self::Gamepad)
+        } else {
+            // shorthands for letters and digits
+            let key = match name.as_bytes() {
+                [c] if c.is_ascii_alphabetic() => {
+                    variant(&format!("Key{}", name.to_ascii_uppercase()))
+                }
+                [c] if c.is_ascii_digit() => variant(&format!("Digit{name}")),
+                _ => variant(name),
+            };
+            key.map(Self::Key)
+        }
+    }
+}
+
+impl fmt::Display for InputButton {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Key(key) => write!(f, "{key:?}"),
+            Self::Mouse(MouseButton::Other(n)) => write!(f, "Mouse{n}"),
+            Self::Mouse(button) => write!(f, "Mouse{button:?}"),
+            Self::Gamepad(GamepadButton::Other(n)) => write!(f, "Gamepad{n}"),
+            Self::Gamepad(button) => write!(f, "Gamepad{button:?}"),
+        }
+    }
+}
+
+/// The global state of every kind of button
+#[derive(SystemParam)]
+pub struct GlobalButtons<'w> {
+    pub keys: Res<'w, ButtonInput<KeyCode>>,
+    pub mouse: Res<'w, ButtonInput<MouseButton>>,
+    pub gamepad: Res<'w, ButtonInput<GamepadButton>>,
+}
+
+impl GlobalButtons<'_> {
+    pub fn just_pressed(&self, button: InputButton) -> bool {
+        match button {
+            InputButton::Key(key) => self.keys.just_pressed(key),
+            InputButton::Mouse(button) => self.mouse.just_pressed(button),
+            InputButton::Gamepad(button) => self.gamepad.just_pressed(button),
+        }
+    }
+
+    pub fn just_released(&self, button: InputButton) -> bool {
+        match button {
+            InputButton::Key(key) => self.keys.just_released(key),
+            InputButton::Mouse(button) => self.mouse.just_released(button),
+            InputButton::Gamepad(button) => self.gamepad.just_released(button),
+        }
+    }
+
+    /// Everything pressed this frame
+    pub fn get_just_pressed(&self) -> impl Iterator<Item = InputButton> {
+        let keys = self.keys.get_just_pressed().copied().map(InputButton::Key);
+        let mouse = self
+            .mouse
+            .get_just_pressed()
+            .copied()
+            .map(InputButton::Mouse);
+        let gamepad = self
+            .gamepad
+            .get_just_pressed()
+            .copied()
+            .map(InputButton::Gamepad);
+        keys.chain(mouse).chain(gamepad)
+    }
+}
+
+/// Written from the evdev events
+#[derive(SystemParam)]
+pub struct GlobalButtonsMut<'w> {
+    pub keys: ResMut<'w, ButtonInput<KeyCode>>,
+    pub mouse: ResMut<'w, ButtonInput<MouseButton>>,
+    pub gamepad: ResMut<'w, ButtonInput<GamepadButton>>,
+}
+
+impl GlobalButtonsMut<'_> {
+    pub fn set(&mut self, button: InputButton, pressed: bool) {
+        match (button, pressed) {
+            (InputButton::Key(key), true) => self.keys.press(key),
+            (InputButton::Key(key), false) => self.keys.release(key),
+            (InputButton::Mouse(button), true) => self.mouse.press(button),
+            (InputButton::Mouse(button), false) => self.mouse.release(button),
+            (InputButton::Gamepad(button), true) => self.gamepad.press(button),
+            (InputButton::Gamepad(button), false) => self.gamepad.release(button),
+        }
+    }
+
+    pub fn release_all(&mut self) {
+        self.keys.release_all();
+        self.mouse.release_all();
+        self.gamepad.release_all();
+    }
+}
@@ -0,0 +1,233 @@
+//! Finding input devices, at startup and whenever one is plugged in
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use bevy::{prelude::*, tasks::IoTaskPool};
+use crossbeam_channel::Sender;
+use evdev::{AbsoluteAxisCode, Device, EventSummary, EventType};
+use inotify::{Event, Inotify, WatchMask};
+use serde::{Deserialize, Serialize};
+
+use super::actions::Action;
+
+const INPUT_DIR: &str = "/dev/input";
+/// udev sets the permissions on new device nodes shortly after creating them
+const OPEN_RETRIES: u32 = 10;
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Sent by the device watcher and the streams of each device, or the other backends
+pub enum InputMessage {
+    Event(evdev::InputEvent),
+    Added {
+        id: u64,
+        name: String,
+    },
+    /// The device is gone, or could not be read anymore
+    Removed {
+        id: u64,
+    },
+    /// The backend found everything there was at startup
+    Ready,
+    /// A shortcut bound through the portal, or matched by the helper
+    Action {
+        action: Action,
+        pressed: bool,
+    },
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// An id unique to each time a device was opened
+pub fn next_device_id() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Which devices to listen to, `allow` and `deny` of the `[input]` config
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceFilter {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl DeviceFilter {
+    /// Whether `pattern` names the device, by its `vendor:product` id, path or part of its name
+    fn matches(pattern: &str, path: &Path, device: &Device) -> bool {
+        let id = device.input_id();
+        let name = device.name().unwrap_or_default().to_lowercase();
+        pattern.eq_ignore_ascii_case(&format!("{:04x}:{:04x}", id.vendor(), id.product()))
+            || Path::new(pattern) == path
+            || name.contains(&pattern.to_lowercase())
+    }
+
+    fn accepts(&self, path: &Path, device: &Device) -> bool {
+        let matches = |patterns: &[String]| patterns.iter().any(|p| Self::matches(p, path, device));
+        (self.allow.is_empty() || matches(&self.allow)) && !matches(&self.deny)
+    }
+}
+
+/// Listen to all matching devices, and keep watching for new ones.
+///
+/// Devices present now are opened before this returns.
+pub fn start_watching(conf: DeviceFilter, tx: Sender<InputMessage>) {
+    // watched before the devices are listed, so one plugged in meanwhile isn't missed
+    let inotify = Inotify::init().and_then(|inotify| {
+        inotify.watches().add(INPUT_DIR, WatchMask::CREATE)?;
+        Ok(inotify)
+    });
+    let mut found = 0;
+    let mut listed = HashSet::new();
+    for (path, device) in evdev::enumerate() {
+        found += listen(&conf, &path, device, &tx) as u32;
+        listed.insert(path);
+    }
+    if found == 0 {
+        warn!("No readable keyboard found in {INPUT_DIR}, waiting for one");
+    }
+    let _ = tx.send(InputMessage::Ready);
+
+    let mut inotify = match inotify {
+        Ok(inotify) => inotify,
+        Err(e) => {
+            error!("Can't watch {INPUT_DIR} for new devices: {e}");
+            return;
+        }
+    };
+    std::thread::Builder::new()
+        .name("input devices".to_string())
+        .spawn(move || {
+            if let Err(e) = watch(&mut inotify, listed, &conf, &tx) {
+                error!("Can't watch {INPUT_DIR} for new devices: {e}");
+            }
+        })
+        .expect("could spawn thread");
+}
+
+/// Open devices created in the input directory, until the app exits.
+///
+/// The `listed` devices were opened already, events about them from while listing are skipped.
+fn watch(
+    inotify: &mut Inotify,
+    listed: HashSet<PathBuf>,
+    conf: &DeviceFilter,
+    tx: &Sender<InputMessage>,
+) -> std::io::Result<()> {
+    let mut buffer = [0; 4096];
+    // only the events queued so far can be about listed devices
+    let queued = match inotify.read_events(&mut buffer) {
+        Ok(events) => created_devices(events),
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    for path in queued {
+        if !listed.contains(&path)
+            && let Some(device) = open(&path)
+        {
+            listen(conf, &path, device, tx);
+        }
+    }
+
+    loop {
+        for path in created_devices(inotify.read_events_blocking(&mut buffer)?) {
+            if let Some(device) = open(&path) {
+                listen(conf, &path, device, tx);
+            }
+        }
+    }
+}
+
+/// Paths of the event devices in inotify `CREATE` events
+fn created_devices<'a>(events: impl Iterator<Item = Event<&'a OsStr>>) -> Vec<PathBuf> {
+    events
+        .filter_map(|event| event.name)
+        .filter(|name| name.to_string_lossy().starts_with("event"))
+        .map(|name| Path::new(INPUT_DIR).join(name))
+        .collect()
+}
+
+/// Open a new device node, once it can be read
+fn open(path: &Path) -> Option<Device> {
+    let mut last_error = None;
+    for _ in 0..OPEN_RETRIES {
+        match Device::open(path) {
+            Ok(device) => return Some(device),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(e) => last_error = Some(e),
+        }
+        std::thread::sleep(RETRY_DELAY);
+    }
+    if let Some(e) = last_error {
+        debug!("Can't open new device {}: {e}", path.display());
+    }
+    None
+}
+
+/// Forward the key events of `device` until it goes away. Returns whether it is listened to.
+fn listen(conf: &DeviceFilter, path: &Path, device: Device, tx: &Sender<InputMessage>) -> bool {
+    // Only keyboards, mice and gamepads (devices that support key events)
+    if !device.supported_events().contains(EventType::KEY) {
+        return false;
+    }
+    let name = device.name().unwrap_or("unnamed device").to_string();
+    let id = device.input_id();
+    let description = format!(
+        "{name} ({:04x}:{:04x}, {})",
+        id.vendor(),
+        id.product(),
+        path.display()
+    );
+    if !conf.accepts(path, &device) {
+        debug!("Ignoring {description}, as configured in [input]");
+        return false;
+    }
+    let mut stream = match device.into_event_stream() {
+        Ok(stream) => stream,
+        Err(e) => {
+            warn!("Can't listen to {description}: {e}");
+            return false;
+        }
+    };
+    info!("Listening to {description}");
+
+    let id = next_device_id();
+    let _ = tx.send(InputMessage::Added { id, name });
+    let tx = tx.clone();
+    IoTaskPool::get()
+        .spawn(async move {
+            loop {
+                match stream.next_event().await {
+                    // skip the flood of motion events, apart from d-pads reported as an axis
+                    Ok(event) if !is_button(event.destructure()) => {}
+                    Ok(event) => {
+                        if tx.send(InputMessage::Event(event)).is_err() {
+                            return;
+                        }
+                    }
+                    // usually ENODEV, after the device was unplugged
+                    Err(e) => {
+                        debug!("Stopped reading {description}: {e}");
+                        break;
+                    }
+                }
+            }
+            let _ = tx.send(InputMessage::Removed { id });
+        })
+        .detach();
+    true
+}
+
+fn is_button(event: EventSummary) -> bool {
+    matches!(
+        event,
+        EventSummary::Key(..)
+            | EventSummary::AbsoluteAxis(
+                _,
+                AbsoluteAxisCode::ABS_HAT0X | AbsoluteAxisCode::ABS_HAT0Y,
+                _
+            )
+    )
+}
@@ -0,0 +1,107 @@
+//! Reading the input devices in `wf_input_helper`, so this process never sees the keys pressed.
+//!
+//! The helper gets one end of a Unix socket as its stdin and only sends back actions. It takes
+//! its keybinds from a file only root can change, not from this process.
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    os::{fd::OwnedFd, unix::net::UnixStream},
+    path::PathBuf,
+    process::Command,
+};
+
+use bevy::prelude::*;
+use crossbeam_channel::Sender;
+
+use super::{
+    InputMessage, Keybinds,
+    devices::DeviceFilter,
+    protocol::{HELPER_KEYBINDS_FILE, HelperConfig, HelperMessage},
+};
+
+const HELPER: &str = "wf_input_helper";
+
+/// Start the helper and forward what it sends, until it exits
+pub(super) fn start(keybinds: Keybinds, devices: DeviceFilter, tx: Sender<InputMessage>) {
+    std::thread::Builder::new()
+        .name("input helper".to_string())
+        .spawn(move || {
+            if let Err(e) = run(&keybinds, HelperConfig { devices }, &tx) {
+                error!("Input helper failed: {e}");
+            }
+            // without any devices, so the status shows that keybinds don't work
+            let _ = tx.send(InputMessage::Ready);
+        })
+        .expect("could spawn thread");
+}
+
+/// The helper next to this executable, or the one on `$PATH`
+fn helper_path() -> PathBuf {
+    std::env::current_exe()
+        .map(|exe| exe.with_file_name(HELPER))
+        .ok()
+        .filter(|path| path.exists())
+        .unwrap_or_else(|| PathBuf::from(HELPER))
+}
+
+fn run(keybinds: &Keybinds, config: HelperConfig, tx: &Sender<InputMessage>) -> io::Result<()> {
+    let path = helper_path();
+    let (socket, theirs) = UnixStream::pair()?;
+    let mut child = Command::new(&path)
+        .stdin(OwnedFd::from(theirs))
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("can't start {}: {e}", path.display())))?;
+    info!("Started {}", path.display());
+
+    let mut line = serde_json::to_vec(&config)?;
+    line.push(b'\n');
+    (&socket).write_all(&line)?;
+
+    let mut added = Vec::new();
+    for line in BufReader::new(&socket).lines() {
+        let message = match serde_json::from_str(&line?) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Invalid message from the input helper: {e}");
+                continue;
+            }
+        };
+        let message = match message {
+            HelperMessage::Keybinds(used) => {
+                if used != *keybinds {
+                    warn!(
+                        "The input helper uses the keybinds from {HELPER_KEYBINDS_FILE} (or the defaults without it), \
+                        put the [keybinds] of the config there as root for them to apply"
+                    );
+                }
+                continue;
+            }
+            HelperMessage::Added { id, name } => {
+                added.push(id);
+                InputMessage::Added { id, name }
+            }
+            HelperMessage::Removed { id } => {
+                added.retain(|a| *a != id);
+                InputMessage::Removed { id }
+            }
+            HelperMessage::Ready => InputMessage::Ready,
+            HelperMessage::Pressed(action) => InputMessage::Action {
+                action,
+                pressed: true,
+            },
+            HelperMessage::Released(action) => InputMessage::Action {
+                action,
+                pressed: false,
+            },
+        };
+        if tx.send(message).is_err() {
+            return Ok(());
+        }
+    }
+
+    let status = child.wait()?;
+    warn!("The input helper exited ({status})");
+    for id in added {
+        let _ = tx.send(InputMessage::Removed { id });
+    }
+    Ok(())
+}
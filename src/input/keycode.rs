@@ -1,83 +1,9 @@
-//! evdev based global input for bevy
-use std::process::exit;
-
-use bevy::{
-    input::{InputSystems, keyboard::NativeKeyCode},
-    prelude::*,
-    tasks::IoTaskPool,
-};
-use crossbeam_channel::{Receiver, unbounded};
-use evdev::{Device, EventType};
-use winit::{keyboard::PhysicalKey, platform::scancode::PhysicalKeyExtScancode};
-
-pub fn input_plugin(app: &mut App) {
-    app.add_systems(Startup, setup_input_listening)
-        .add_systems(PreUpdate, handle_input_events.after(InputSystems));
-}
-
-#[derive(Resource)]
-struct InputReceiver(Receiver<evdev::InputEvent>);
-
-fn setup_input_listening(mut commands: Commands) {
-    let (tx, rx) = unbounded(); // std sync channel
-
-    commands.insert_resource(InputReceiver(rx));
-
-    let devices: Vec<Device> = evdev::enumerate()
-        .filter_map(|(_, device)| {
-            // Only keyboards (devices that support key events)
-            if device.supported_events().contains(EventType::KEY) {
-                Some(device)
-            } else {
-                None
-            }
-        })
-        .collect();
-    if devices.is_empty() {
-        warn!(
-            "The OS user running this needs to be added to the `input` group, to allow this app to read global key inputs.
-        Hint: Run `sudo usermod -a -G input $USER` and then reboot!"
-        );
-        exit(1);
-    }
-    for device in devices {
-        let tx = tx.clone();
-
-        if let Ok(mut stream) = device.into_event_stream() {
-            IoTaskPool::get()
-                .spawn(async move {
-                    loop {
-                        if let Ok(event) = stream.next_event().await {
-                            let _ = tx.send(event);
-                        }
-                    }
-                })
-                .detach();
-        }
-    }
-}
-
-fn handle_input_events(
-    receiver: Res<InputReceiver>,
-    mut button_input: ResMut<ButtonInput<KeyCode>>,
-) {
-    while let Ok(event) = receiver.0.try_recv() {
-        if let evdev::EventSummary::Key(_, scancode, value) = event.destructure() {
-            let physical = PhysicalKey::from_scancode(scancode.code() as u32);
-            let keycode = convert_physical_key_code(physical);
-
-            if value == 1 {
-                button_input.press(keycode);
-            } else {
-                button_input.release(keycode);
-            }
-        };
-    }
-}
+//! Converting winit key codes to bevy ones
+use bevy::{input::keyboard::NativeKeyCode, prelude::*};
 
 // copied from private bevy_winit::converters
 // remove if updating to version where https://github.com/bevyengine/bevy/pull/22336 is merged
-fn convert_physical_key_code(virtual_key_code: winit::keyboard::PhysicalKey) -> KeyCode {
+pub(crate) fn convert_physical_key_code(virtual_key_code: winit::keyboard::PhysicalKey) -> KeyCode {
     match virtual_key_code {
         winit::keyboard::PhysicalKey::Unidentified(native_key_code) => {
             KeyCode::Unidentified(convert_physical_native_key_code(native_key_code))
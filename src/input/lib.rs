@@ -0,0 +1,7 @@
+//! Input handling shared by wf_overlay and `wf_input_helper`: the actions and their keybinds,
+//! reading input devices, and what the two send each other
+pub mod actions;
+pub mod buttons;
+pub mod devices;
+mod keycode;
+pub mod protocol;
@@ -0,0 +1,198 @@
+//! evdev based global input for bevy
+use std::collections::HashMap;
+
+use bevy::{input::InputSystems, prelude::*};
+use crossbeam_channel::{Receiver, unbounded};
+
+use crate::{
+    ERROR_COLOR,
+    config::{ConfigManager, InputBackend},
+};
+
+mod helper;
+#[cfg(feature = "portal")]
+mod portal;
+
+use wf_input::{actions, buttons, devices, protocol};
+
+pub use actions::{Action, Keybinds};
+use buttons::GlobalButtonsMut;
+pub use buttons::{GlobalButtons, InputButton};
+use devices::{DeviceFilter, InputMessage};
+
+pub fn input_plugin(app: &mut App) {
+    app.init_state::<InputState>()
+        .init_resource::<InputDevices>()
+        .init_resource::<ButtonInput<Action>>()
+        // bevy only tracks gamepads per entity, through gilrs
+        .init_resource::<ButtonInput<GamepadButton>>()
+        .add_systems(Startup, (setup_input_listening, setup_status_text))
+        .add_systems(
+            PreUpdate,
+            (
+                handle_input_events.after(InputSystems),
+                update_actions,
+                update_status_text
+                    .run_if(state_changed::<InputState>.or(resource_changed::<ConfigManager>)),
+            )
+                .chain()
+                .in_set(ActionSystems),
+        );
+}
+
+/// Updates [`ButtonInput<Action>`] in [`PreUpdate`], to press actions from elsewhere after it
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ActionSystems;
+
+/// Whether any device can be read for the keybinds
+#[derive(States, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum InputState {
+    /// The backend is still looking for devices or talking to the portal
+    #[default]
+    Starting,
+    Listening,
+    /// Nothing readable yet, e.g. because the user isn't in the `input` group
+    NoDevices,
+}
+
+#[derive(Resource)]
+struct InputReceiver(Receiver<InputMessage>);
+
+/// Names of the devices currently listened to, by an id unique to each time one was opened
+#[derive(Resource, Default)]
+pub struct InputDevices(pub HashMap<u64, String>);
+
+fn setup_input_listening(mut commands: Commands, conf: Res<ConfigManager>) {
+    let (tx, rx) = unbounded(); // std sync channel
+
+    commands.insert_resource(InputReceiver(rx));
+    let filter = DeviceFilter {
+        allow: conf.input.allow.clone(),
+        deny: conf.input.deny.clone(),
+    };
+    match conf.input.backend {
+        InputBackend::Evdev => devices::start_watching(filter, tx),
+        #[cfg(feature = "portal")]
+        InputBackend::Portal => portal::start(conf.keybinds.clone(), filter, tx),
+        #[cfg(not(feature = "portal"))]
+        InputBackend::Portal => {
+            warn!(
+                "wf_overlay was built without the `portal` feature, reading input devices instead"
+            );
+            devices::start_watching(filter, tx);
+        }
+        InputBackend::Helper => helper::start(conf.keybinds.clone(), filter, tx),
+    }
+}
+
+fn handle_input_events(
+    receiver: Res<InputReceiver>,
+    mut buttons: GlobalButtonsMut,
+    mut actions: ResMut<ButtonInput<Action>>,
+    mut devices: ResMut<InputDevices>,
+    state: Res<State<InputState>>,
+    mut next: ResMut<NextState<InputState>>,
+) {
+    // cleared by bevy for the others
+    buttons.gamepad.clear();
+    actions.clear();
+    let mut ready = *state.get() != InputState::Starting;
+    while let Ok(message) = receiver.0.try_recv() {
+        let event = match message {
+            InputMessage::Event(event) => event,
+            InputMessage::Added { id, name } => {
+                devices.0.insert(id, name);
+                continue;
+            }
+            InputMessage::Removed { id } => {
+                if let Some(name) = devices.0.remove(&id) {
+                    info!("Stopped listening to {name}");
+                }
+                // its buttons will never be released otherwise
+                buttons.release_all();
+                continue;
+            }
+            InputMessage::Ready => {
+                ready = true;
+                continue;
+            }
+            InputMessage::Action { action, pressed } => {
+                if pressed {
+                    actions.press(action);
+                } else {
+                    actions.release(action);
+                }
+                continue;
+            }
+        };
+        for (button, pressed) in InputButton::changes(&event) {
+            buttons.set(button, pressed);
+        }
+    }
+
+    if !ready {
+        return;
+    }
+    let new = if devices.0.is_empty() {
+        InputState::NoDevices
+    } else {
+        InputState::Listening
+    };
+    if *state.get() != new {
+        next.set(new);
+    }
+}
+
+fn update_actions(
+    buttons: GlobalButtons,
+    conf: Res<ConfigManager>,
+    mut actions: ResMut<ButtonInput<Action>>,
+) {
+    conf.keybinds.update(&buttons, &mut actions);
+}
+
+/// Warning in the top right corner while no device can be read
+#[derive(Component)]
+struct InputStatusText;
+
+fn setup_status_text(mut commands: Commands, conf: Res<ConfigManager>) {
+    commands.spawn((
+        InputStatusText,
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(12.),
+            right: Val::Px(12.),
+            ..default()
+        },
+        Text::new(
+            "No keyboard can be read, keybinds won't work\n\
+            Add your user to the `input` group with `sudo usermod -a -G input $USER` and log in again,\n\
+            or check `allow`, `deny` and `backend` in the [input] config",
+        ),
+        TextFont::from_font_size(conf.font_size * 0.75),
+        TextColor(ERROR_COLOR),
+        TextShadow::default(),
+        Visibility::Hidden,
+    ));
+}
+
+fn update_status_text(
+    state: Res<State<InputState>>,
+    conf: Res<ConfigManager>,
+    mut text: Single<(&mut Visibility, &mut TextFont), With<InputStatusText>>,
+) {
+    text.1.font_size = conf.font_size * 0.75;
+    if !state.is_changed() {
+        return;
+    }
+    *text.0 = match state.get() {
+        InputState::Starting | InputState::Listening => Visibility::Hidden,
+        InputState::NoDevices => {
+            warn!(
+                "No input device can be read. The OS user running this needs to be added to the `input` group, to allow this app to read global key inputs.
+        Hint: Run `sudo usermod -a -G input $USER` and then reboot!"
+            );
+            Visibility::Inherited
+        }
+    };
+}
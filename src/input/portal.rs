@@ -0,0 +1,237 @@
+//! Keybinds through the xdg-desktop-portal GlobalShortcuts interface.
+//!
+//! The desktop tells us when a shortcut is pressed, so no input device has to be readable.
+use std::pin::pin;
+
+use ashpd::desktop::global_shortcuts::{GlobalShortcuts, NewShortcut};
+use bevy::{prelude::*, tasks::IoTaskPool};
+use crossbeam_channel::Sender;
+use futures_lite::{StreamExt, stream};
+
+use super::{
+    Action, InputMessage, Keybinds,
+    actions::{KeyChord, Modifiers},
+    buttons::InputButton,
+    devices::{self, DeviceFilter},
+};
+
+/// Bind the actions through the portal, or read the input devices if it isn't available.
+///
+/// Shortcuts are only bound once, changing the keybinds later has no effect on them.
+pub(super) fn start(keybinds: Keybinds, filter: DeviceFilter, tx: Sender<InputMessage>) {
+    IoTaskPool::get()
+        .spawn(async move {
+            if let Err(e) = run(&keybinds, &tx).await {
+                warn!("Can't use the GlobalShortcuts portal, reading input devices instead: {e}");
+                devices::start_watching(filter, tx);
+            }
+        })
+        .detach();
+}
+
+/// Forward the activated shortcuts until the portal goes away. Errors if they couldn't be bound.
+async fn run(keybinds: &Keybinds, tx: &Sender<InputMessage>) -> ashpd::Result<()> {
+    let proxy = GlobalShortcuts::new().await?;
+    let session = proxy.create_session().await?;
+    let shortcuts: Vec<NewShortcut> = keybinds
+        .iter()
+        .map(|(action, chords)| {
+            let trigger = chords.iter().find_map(preferred_trigger);
+            NewShortcut::new(action.name(), action.description())
+                .preferred_trigger(trigger.as_deref())
+        })
+        .collect();
+
+    // subscribed before binding, so nothing pressed right after is missed
+    let activated = proxy
+        .receive_activated()
+        .await?
+        .map(|s| (s.shortcut_id().to_string(), true));
+    let deactivated = proxy
+        .receive_deactivated()
+        .await?
+        .map(|s| (s.shortcut_id().to_string(), false));
+    let bound = proxy
+        .bind_shortcuts(&session, &shortcuts, None)
+        .await?
+        .response()?;
+    for shortcut in bound.shortcuts() {
+        info!(
+            "Portal shortcut {}: {}",
+            shortcut.id(),
+            shortcut.trigger_description()
+        );
+    }
+
+    let id = devices::next_device_id();
+    let _ = tx.send(InputMessage::Added {
+        id,
+        name: "GlobalShortcuts portal".to_string(),
+    });
+    let _ = tx.send(InputMessage::Ready);
+
+    // the portal only sends these to the owner of the session
+    let mut events = pin!(stream::or(activated, deactivated));
+    while let Some((shortcut_id, pressed)) = events.next().await {
+        let Some(action) = Action::from_name(&shortcut_id) else {
+            debug!("Unknown portal shortcut {shortcut_id}");
+            continue;
+        };
+        if tx.send(InputMessage::Action { action, pressed }).is_err() {
+            return Ok(());
+        }
+    }
+    warn!("The GlobalShortcuts portal stopped sending shortcuts");
+    let _ = tx.send(InputMessage::Removed { id });
+    Ok(())
+}
+
+/// The chord in the format of the shortcuts spec, like `CTRL+SHIFT+p`, for keyboard keys.
+///
+/// This is only a suggestion, the desktop may ask the user to pick something else.
+fn preferred_trigger(chord: &KeyChord) -> Option<String> {
+    let InputButton::Key(key) = chord.button else {
+        return None;
+    };
+    let name = format!("{key:?}");
+    let keysym = if let Some(letter) = name.strip_prefix("Key") {
+        letter.to_lowercase()
+    } else if let Some(digit) = name.strip_prefix("Digit") {
+        digit.to_string()
+    } else if let Some(digit) = name.strip_prefix("Numpad").filter(|d| d.len() == 1) {
+        format!("KP_{digit}")
+    } else if name.starts_with('F') && name[1..].parse::<u8>().is_ok() {
+        name
+    } else {
+        let named = match key {
+            KeyCode::Equal => "equal",
+            KeyCode::Minus => "minus",
+            KeyCode::Backquote => "grave",
+            KeyCode::Comma => "comma",
+            KeyCode::Period => "period",
+            KeyCode::Slash => "slash",
+            KeyCode::Backslash => "backslash",
+            KeyCode::Semicolon => "semicolon",
+            KeyCode::Quote => "apostrophe",
+            KeyCode::BracketLeft => "bracketleft",
+            KeyCode::BracketRight => "bracketright",
+            KeyCode::Space => "space",
+            KeyCode::Enter => "Return",
+            KeyCode::Tab => "Tab",
+            KeyCode::Escape => "Escape",
+            KeyCode::Backspace => "BackSpace",
+            KeyCode::Insert => "Insert",
+            KeyCode::Delete => "Delete",
+            KeyCode::Home => "Home",
+            KeyCode::End => "End",
+            KeyCode::PageUp => "Page_Up",
+            KeyCode::PageDown => "Page_Down",
+            KeyCode::ArrowUp => "Up",
+            KeyCode::ArrowDown => "Down",
+            KeyCode::ArrowLeft => "Left",
+            KeyCode::ArrowRight => "Right",
+            KeyCode::PrintScreen => "Print",
+            KeyCode::ScrollLock => "Scroll_Lock",
+            KeyCode::Pause => "Pause",
+            _ => return None,
+        };
+        named.to_string()
+    };
+
+    let Modifiers {
+        ctrl,
+        shift,
+        alt,
+        super_key,
+    } = chord.modifiers;
+    let mut trigger = String::new();
+    for (held, name) in [
+        (ctrl, "CTRL"),
+        (shift, "SHIFT"),
+        (alt, "ALT"),
+        (super_key, "LOGO"),
+    ] {
+        if held {
+            trigger += name;
+            trigger += "+";
+        }
+    }
+    Some(trigger + &keysym)
+}
+
+/// The stand-in portal from the examples, for the test below
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "../../examples/fake_shortcuts_portal.rs"]
+mod fake_portal;
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use bevy::tasks::block_on;
+
+    use super::*;
+
+    fn trigger(chord: &str) -> Option<String> {
+        preferred_trigger(&chord.parse().unwrap())
+    }
+
+    #[test]
+    fn keys_use_keysym_names() {
+        assert_eq!(trigger("p").as_deref(), Some("p"));
+        assert_eq!(trigger("KeyP").as_deref(), Some("p"));
+        assert_eq!(trigger("1").as_deref(), Some("1"));
+        assert_eq!(trigger("Numpad3").as_deref(), Some("KP_3"));
+        assert_eq!(trigger("F5").as_deref(), Some("F5"));
+        assert_eq!(trigger("F12").as_deref(), Some("F12"));
+        assert_eq!(trigger("Equal").as_deref(), Some("equal"));
+        assert_eq!(trigger("Enter").as_deref(), Some("Return"));
+        assert_eq!(trigger("PageDown").as_deref(), Some("Page_Down"));
+    }
+
+    #[test]
+    fn modifiers_in_spec_order() {
+        assert_eq!(trigger("Ctrl+Shift+p").as_deref(), Some("CTRL+SHIFT+p"));
+        assert_eq!(trigger("Shift+Ctrl+p").as_deref(), Some("CTRL+SHIFT+p"));
+        assert_eq!(
+            trigger("Super+Alt+Enter").as_deref(),
+            Some("ALT+LOGO+Return")
+        );
+    }
+
+    #[test]
+    fn only_keyboard_keys() {
+        assert_eq!(trigger("MouseBack"), None);
+        assert_eq!(trigger("Ctrl+Mouse8"), None);
+        assert_eq!(trigger("GamepadSouth"), None);
+        // no keysym is known for it
+        assert_eq!(trigger("NumpadEnter"), None);
+    }
+
+    #[test]
+    #[ignore = "needs a private session bus, see examples/fake_shortcuts_portal.rs"]
+    fn actions_arrive_through_the_portal() {
+        let portal = fake_portal::FakePortal::start().unwrap();
+        let (tx, rx) = crossbeam_channel::unbounded();
+        thread::spawn(move || block_on(run(&Keybinds::default(), &tx)));
+        let recv = || rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        while !matches!(recv(), InputMessage::Ready) {}
+
+        assert!(portal.activate("trigger").unwrap());
+        assert!(matches!(
+            recv(),
+            InputMessage::Action {
+                action: Action::Trigger,
+                pressed: true
+            }
+        ));
+        assert!(matches!(
+            recv(),
+            InputMessage::Action {
+                action: Action::Trigger,
+                pressed: false
+            }
+        ));
+    }
+}
@@ -0,0 +1,36 @@
+//! What wf_overlay and `wf_input_helper` send each other over their socket, one JSON object per line
+use serde::{Deserialize, Serialize};
+
+use super::{
+    actions::{Action, Keybinds},
+    devices::DeviceFilter,
+};
+
+/// The keybinds the helper matches. Any process of the user can start the helper, so it doesn't
+/// take them from wf_overlay, or it could be made to report every key. Has to be owned by root,
+/// the defaults are used without it.
+pub const HELPER_KEYBINDS_FILE: &str = "/etc/wf_overlay/input_helper.toml";
+
+/// Sent once by the app, right after starting the helper
+#[derive(Serialize, Deserialize)]
+pub struct HelperConfig {
+    pub devices: DeviceFilter,
+}
+
+/// Sent by the helper. Only which actions were pressed, never the keys
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HelperMessage {
+    /// The keybinds the helper uses, sent first
+    Keybinds(Keybinds),
+    Added {
+        id: u64,
+        name: String,
+    },
+    Removed {
+        id: u64,
+    },
+    Ready,
+    Pressed(Action),
+    Released(Action),
+}
@@ -7,23 +7,38 @@ use bevy::{
     math::bounding::BoundingVolume,
     prelude::*,
     sprite::{Anchor, Text2dShadow},
-    window::{CompositeAlphaMode, CursorOptions, WindowMode, WindowResolution},
+    window::{CompositeAlphaMode, CursorOptions, WindowMode},
 };
 
 use crate::{
     config::ConfigManager,
-    market::{ItemData, Slug},
-    ocr::ItemsContainer,
+    input::{Action, GlobalButtons},
+    market::{ItemData, ItemName, ItemState, MatchConfidence, Slug},
+    ocr::{Item, ItemsContainer},
+    template::{TemplateContext, format_age},
 };
 
 mod cap;
+mod cli;
 mod config;
+mod control;
+mod highlight;
+mod history;
 mod input;
 mod market;
 mod market_api;
+mod monitor;
 mod ocr;
+mod paths;
+mod pick;
+mod pixel_checks;
+mod template;
+mod viewport;
 
 fn main() {
+    if let Some(code) = cli::run() {
+        std::process::exit(code);
+    }
     App::new()
         .insert_resource(ClearColor(Color::NONE))
         .add_plugins(DefaultPlugins.set(WindowPlugin {
@@ -34,7 +49,6 @@ fn main() {
                 composite_alpha_mode: CompositeAlphaMode::PreMultiplied,
                 decorations: false,
                 window_level: bevy::window::WindowLevel::AlwaysOnTop,
-                resolution: WindowResolution::default().with_scale_factor_override(1.0),
                 ..default()
             }),
             primary_cursor_options: Some(CursorOptions {
@@ -44,14 +58,30 @@ fn main() {
             ..default()
         }))
         .add_plugins(ocr::ocrs_plugin)
-        .add_plugins(cap::ScreencastPlugin)
+        .add_plugins(cap::CapturePlugin)
+        .add_plugins(monitor::monitor_plugin)
         .add_plugins(market::market_plugin)
         .add_plugins(input::input_plugin)
         .add_plugins(config::config_plugin)
+        .add_plugins(control::control_plugin)
+        .add_plugins(history::history_plugin)
+        .add_plugins(pick::pick_plugin)
+        .add_plugins(pixel_checks::pixel_checks_plugin)
+        .add_plugins(highlight::highlight_plugin)
         .init_state::<AppState>()
         .add_sub_state::<PlatOverlayPhase>()
         .add_systems(Startup, setup)
-        .add_systems(Update, (keybinds, command_after))
+        .add_systems(
+            Update,
+            (keybinds, repeat_last_overlay, command_after, close_overlay),
+        )
+        .add_systems(OnExit(AppState::PlatOverlay), |mut commands: Commands| {
+            commands.remove_resource::<CloseTimer>()
+        })
+        .add_systems(
+            OnExit(PlatOverlayPhase::Displaying),
+            |mut commands: Commands| commands.remove_resource::<RepeatedOverlay>(),
+        )
         .add_observer(display_plat)
         .run();
 }
@@ -158,15 +188,68 @@ pub enum PlatOverlayPhase {
     Displaying,
 }
 
-fn keybinds(kb: Res<ButtonInput<KeyCode>>, conf: Res<ConfigManager>, mut commands: Commands) {
+fn keybinds(
+    buttons: GlobalButtons,
+    actions: Res<ButtonInput<Action>>,
+    conf: Res<ConfigManager>,
+    state: Res<State<AppState>>,
+    mut commands: Commands,
+) {
     if conf.show_keys {
-        kb.get_just_pressed()
-            .for_each(|key| info!("Key event: {key:?}"));
+        buttons
+            .get_just_pressed()
+            .for_each(|button| info!("Key event: {button}"));
+        actions
+            .get_just_pressed()
+            .for_each(|action| info!("Action: {}", action.name()));
     }
-    if kb.just_pressed(conf.overlay_key) {
+    // the layout editor saves its pixel checks with the trigger keybind
+    if actions.just_pressed(Action::Trigger) && *state.get() != AppState::EditOverlay {
         println!("Start capture");
         commands.set_state(AppState::PlatOverlay);
         commands.set_state(PlatOverlayPhase::Ocr);
+    } else if actions.just_pressed(Action::Dismiss) && *state.get() != AppState::Waiting {
+        commands.set_state(AppState::Waiting);
+    } else if actions.just_pressed(Action::EditLayout) {
+        commands.set_state(if *state.get() == AppState::EditOverlay {
+            AppState::Waiting
+        } else {
+            AppState::EditOverlay
+        });
+    }
+}
+
+/// Present while the last reward screen is shown again, instead of one just captured
+#[derive(Resource)]
+pub struct RepeatedOverlay;
+
+/// Show the prices of the last reward screen again, without capturing a new one
+fn repeat_last_overlay(
+    actions: Res<ButtonInput<Action>>,
+    items: Single<Option<&Children>, With<ItemsContainer>>,
+    states: Query<&ItemState>,
+    phase: Option<Res<State<PlatOverlayPhase>>>,
+    conf: Res<ConfigManager>,
+    mut commands: Commands,
+) {
+    if !actions.just_pressed(Action::RepeatLast) {
+        return;
+    }
+    let Some(items) = *items else {
+        info!("No reward screen to show again yet");
+        return;
+    };
+    // the reward screen is gone, so there's no pick to detect anymore
+    if phase.is_none_or(|p| *p.get() != PlatOverlayPhase::Displaying) {
+        commands.insert_resource(RepeatedOverlay);
+    }
+    // when already displaying, setting the states again would exit them and despawn the texts
+    commands.set_state_if_neq(AppState::PlatOverlay);
+    commands.set_state_if_neq(PlatOverlayPhase::Displaying);
+    commands.insert_resource(CloseTimer::new(&conf));
+    // inserting the state again shows its text, see display_plat
+    for (child, state) in items.iter().filter_map(|c| Some((c, states.get(c).ok()?))) {
+        commands.entity(child).insert(*state);
     }
 }
 
@@ -207,6 +290,27 @@ impl<C: FnOnce(Commands) + Send + Sync + 'static> Command for DelayedCommands<C>
 #[derive(Component)]
 struct DelayedCommandQueue(Timer, CommandQueue);
 
+/// Closes the overlay when it runs out, inserting it again restarts it
+#[derive(Resource)]
+struct CloseTimer(Timer);
+
+impl CloseTimer {
+    fn new(conf: &ConfigManager) -> Self {
+        CloseTimer(Timer::from_seconds(
+            conf.close_layout_after,
+            TimerMode::Once,
+        ))
+    }
+}
+
+fn close_overlay(timer: Option<ResMut<CloseTimer>>, time: Res<Time>, mut commands: Commands) {
+    if let Some(mut timer) = timer
+        && timer.0.tick(time.delta()).just_finished()
+    {
+        commands.set_state(AppState::Waiting);
+    }
+}
+
 fn command_after(
     cmds: Query<(Entity, &mut DelayedCommandQueue)>,
     time: Res<Time>,
@@ -223,50 +327,95 @@ fn command_after(
 #[derive(Component)]
 pub struct ShouldDisplay;
 
+/// The price text shown for an item
+#[derive(Component)]
+pub struct PlatText;
+
+const STATUS_COLOR: Color = Color::srgb(0.7, 0.7, 0.7);
+const ERROR_COLOR: Color = Color::srgb(1.0, 0.45, 0.45);
+
 fn display_plat(
-    evt: On<Insert, ItemData>,
+    evt: On<Insert, ItemState>,
     cont: Query<&ItemsContainer>,
-    q: Query<(&ItemData, &Slug, &ChildOf), With<ShouldDisplay>>,
+    q: Query<
+        (
+            &Item,
+            &ItemState,
+            Option<&ItemData>,
+            Option<&Slug>,
+            Option<&ItemName>,
+            Option<&MatchConfidence>,
+            Option<&Children>,
+            &ChildOf,
+        ),
+        With<ShouldDisplay>,
+    >,
+    texts: Query<(), With<PlatText>>,
     conf: Res<ConfigManager>,
     // main_state: Res<State<AppState>>,
     maybe_state: Option<Res<State<PlatOverlayPhase>>>,
     mut commands: Commands,
 ) {
-    if let Some(state) = maybe_state
-        && let PlatOverlayPhase::Ocr = state.get()
+    let Ok((item, state, data, slug, name, confidence, children, child_of)) = q.get(evt.entity)
+    else {
+        return;
+    };
+
+    if let Some(phase) = maybe_state
+        && let PlatOverlayPhase::Ocr = phase.get()
     {
         commands.set_state(PlatOverlayPhase::Displaying);
-        commands.delayed(Duration::from_secs_f32(conf.close_layout_after), |mut c| {
-            c.set_state(AppState::Waiting)
-        });
+        commands.insert_resource(CloseTimer::new(&conf));
     }
 
-    if let Ok((data, slug, child_of)) = q.get(evt.entity) {
-        let mut scale = 0.5;
-        if let Ok(container) = cont.get(child_of.parent()) {
-            let width = container.0.half_size().x * 2.;
-            scale = 2000. / width;
+    // replace the text for the previous state
+    for child in children.iter().flat_map(|c| c.iter()) {
+        if texts.contains(child) {
+            commands.entity(child).despawn();
         }
+    }
 
-        commands.entity(evt.entity).with_child((
-            Transform::from_xyz(150. * scale, -10. * scale, 0.),
-            Text2d(format!(
-                "Avg: {}\nMin: {}\nMax: {}\nDucats: {}\n{}",
-                data.avg,
-                data.min,
-                data.max,
-                data.ducats
-                    .as_ref()
-                    .map_or("-".to_string(), ToString::to_string),
-                slug.0
-            )),
-            TextFont::from_font_size(conf.font_size),
-            Anchor::TOP_CENTER,
-            Text2dShadow {
-                offset: Vec2::new(1. + scale, -(1. + scale)),
-                color: Color::BLACK,
-            },
-            DespawnOnExit(PlatOverlayPhase::Displaying),
-        ));
+    let mut scale = 0.5;
+    if let Ok(container) = cont.get(child_of.parent()) {
+        let width = container.0.half_size().x * 2.;
+        scale = 2000. / width;
     }
+
+    let display_name = name.map_or(item.name.as_str(), |n| n.0.as_str());
+    let (text, color) = match (state, data) {
+        (ItemState::Fresh | ItemState::Stale { .. }, Some(data)) => {
+            let mut text = conf.overlay_template.render(&TemplateContext {
+                ocr: &item.name,
+                name: name.map(|n| n.0.as_str()),
+                slug: slug.map(|s| s.0.as_str()),
+                data: Some(data),
+                confidence: confidence.map(|c| c.0),
+            });
+            if let ItemState::Stale { age } = state {
+                text.push_str(&format!("\n(cached {} ago)", format_age(*age)));
+            }
+            (text, conf.highlight.color_for(data, false))
+        }
+        (ItemState::Matching, _) => ("Matching...".to_string(), STATUS_COLOR),
+        (ItemState::Fetching, _) => (format!("{display_name}\nFetching prices..."), STATUS_COLOR),
+        (ItemState::Untradeable, _) => (format!("{display_name}\nNot tradeable"), STATUS_COLOR),
+        (ItemState::Unknown, _) => (format!("Unknown item\n{}", item.name), ERROR_COLOR),
+        (ItemState::Failed | ItemState::Fresh | ItemState::Stale { .. }, _) => {
+            (format!("{display_name}\nNo price data"), ERROR_COLOR)
+        }
+    };
+
+    commands.entity(evt.entity).with_child((
+        PlatText,
+        Transform::from_xyz(150. * scale, -10. * scale, 0.),
+        Text2d(text),
+        TextFont::from_font_size(conf.font_size),
+        TextColor(color),
+        Anchor::TOP_CENTER,
+        Text2dShadow {
+            offset: Vec2::new(1. + scale, -(1. + scale)),
+            color: Color::BLACK,
+        },
+        DespawnOnExit(PlatOverlayPhase::Displaying),
+    ));
 }
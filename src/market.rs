@@ -6,7 +6,7 @@ use std::{
 };
 
 use bevy::{
-    platform::collections::HashMap, prelude::*, state::commands,
+    ecs::system::SystemParam, platform::collections::HashMap, prelude::*, state::commands,
     time::common_conditions::on_real_timer,
 };
 use bevy_mod_req::{ReqError, ReqPlugin, ReqRequest, ReqResponse, req_type_plugin};
@@ -18,6 +18,8 @@ use crate::{
     DelayedCommandsExt,
     market_api::{ItemsRoot, TopOrdersRoot},
     ocr::{self, ItemsContainer},
+    paths,
+    template::format_age,
 };
 
 const BACKGROUND_FETCH_DELAY: u64 = 8;
@@ -58,7 +60,40 @@ pub fn market_plugin(app: &mut App) {
 struct ItemsRequestHandler;
 
 #[derive(Component, Deref, DerefMut)]
-struct ItemSearchIndex(SimSearch<String>);
+struct ItemSearchIndex {
+    #[deref]
+    engine: SimSearch<String>,
+    /// English market name by slug
+    names: HashMap<String, String>,
+}
+
+/// The market name an OCR'd item was matched to
+#[derive(Component, Clone, Debug, Deref)]
+pub struct ItemName(pub String);
+
+/// How similar the OCR text is to the matched item name, 0 to 1
+#[derive(Component, Clone, Copy, Debug, Deref)]
+pub struct MatchConfidence(pub f32);
+
+/// Levenshtein distance based similarity, ignoring case
+fn similarity(a: &str, b: &str) -> f32 {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+    let max_len = a.len().max(b.len());
+    if max_len == 0 {
+        return 1.;
+    }
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != cb);
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    1. - prev[b.len()] as f32 / max_len as f32
+}
 
 fn setup(mut commands: Commands) {
     commands
@@ -75,15 +110,17 @@ fn setup(mut commands: Commands) {
                     .levenshtein(true)
                     .stop_whitespace(false);
                 let mut engine: SimSearch<String> = SimSearch::new_with(options);
+                let mut names = HashMap::new();
                 engine.insert("".to_string(), "Format Blueprint");
                 items
                     .iter()
                     .filter(|i| i.tags.contains(&"prime".to_string()))
                     .for_each(|i| {
                         engine.insert(i.slug.clone(), &i.i18n.en.name);
+                        names.insert(i.slug.clone(), i.i18n.en.name.clone());
                         data.insert_unknown(i.slug.clone(), i.ducats);
                     });
-                commands.spawn(ItemSearchIndex(engine));
+                commands.spawn(ItemSearchIndex { engine, names });
             },
         );
 }
@@ -101,12 +138,23 @@ fn resolve_items(
             let results = items_index.search(&item.name);
             if results.is_empty() {
                 info!("Unknown item {}, please report", item.name);
+                commands.entity(child).insert(ItemState::Unknown);
                 continue;
             }
             let slug = results[0].clone();
             info!("Matched {} as {}", item.name, results[0]);
-            if !slug.is_empty() {
-                commands.entity(child).insert((Slug(slug), WantsFetch));
+            let mut entity = commands.entity(child);
+            if slug.is_empty() {
+                // untradeable, like the Forma Blueprint
+                entity.insert((ItemName(item.name.clone()), ItemState::Untradeable));
+            } else {
+                if let Some(name) = items_index.names.get(&slug) {
+                    entity.insert((
+                        ItemName(name.clone()),
+                        MatchConfidence(similarity(&item.name, name)),
+                    ));
+                }
+                entity.insert((Slug(slug), WantsFetch));
             }
         }
     }
@@ -115,6 +163,25 @@ fn resolve_items(
 #[derive(Component)]
 pub(crate) struct WantsFetch;
 
+/// How far along getting a price for an item is, and whether the price can be trusted
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ItemState {
+    /// OCR text is waiting to be matched against the item list
+    Matching,
+    /// Waiting for market data, nothing cached
+    Fetching,
+    /// Market data is up to date
+    Fresh,
+    /// Only cached market data of this age in seconds is available
+    Stale { age: u64 },
+    /// Fetching failed and nothing is cached
+    Failed,
+    /// OCR text did not match any item
+    Unknown,
+    /// Matched an item which can't be traded, like the Forma Blueprint
+    Untradeable,
+}
+
 fn fetch_items(
     e: On<Insert, WantsFetch>,
     mut commands: Commands,
@@ -127,10 +194,22 @@ fn fetch_items(
         commands
             .entity(e.entity)
             .remove::<WantsFetch>()
-            .insert((data.clone(), SkipStore));
+            .insert((data.clone(), SkipStore, ItemState::Fresh));
         return;
     };
     let ducats = data.get_ducats(&slug);
+    // show old data until the new data arrives
+    let cached = data.get_cached(&slug).cloned();
+    match cached {
+        Some(cached) => commands.entity(e.entity).insert((
+            ItemState::Stale {
+                age: cached.age_secs(),
+            },
+            cached,
+            SkipStore,
+        )),
+        None => commands.entity(e.entity).insert(ItemState::Fetching),
+    };
     info!("Starting fetch for Item: {slug}");
     commands
         .entity(e.entity)
@@ -151,22 +230,53 @@ fn fetch_items(
                     .fold((0.0f32, f32::MAX, f32::MIN), |acc, p| {
                         (acc.0 + p, acc.1.min(p), acc.2.max(p))
                     });
-                let avg = sum / e.data.data.sell.len() as f32;
+                let (avg, min, max) = if e.data.data.sell.is_empty() {
+                    (f32::NAN, f32::NAN, f32::NAN)
+                } else {
+                    (sum / e.data.data.sell.len() as f32, min, max)
+                };
                 commands
                     .entity(e.entity)
+                    .remove::<(WantsFetch, SkipStore)>()
+                    .insert((
+                        ItemData {
+                            last_fetch: unix_now(),
+                            avg,
+                            min,
+                            max,
+                            ducats,
+                        },
+                        ItemState::Fresh,
+                    ));
+            },
+        )
+        .observe(
+            |e: On<ReqError>,
+             mut commands: Commands,
+             q: Query<(Option<&ItemData>, Has<RemoveOnStore>)>| {
+                let entity = e.event_target();
+                let Ok((data, remove_on_store)) = q.get(entity) else {
+                    return;
+                };
+                if remove_on_store {
+                    commands.entity(entity).try_despawn();
+                    return;
+                }
+                let state = match data {
+                    Some(data) if data.avg.is_finite() => ItemState::Stale {
+                        age: data.age_secs(),
+                    },
+                    _ => ItemState::Failed,
+                };
+                commands
+                    .entity(entity)
                     .remove::<WantsFetch>()
-                    .insert((ItemData {
-                        last_fetch: unix_now(),
-                        avg,
-                        min,
-                        max,
-                        ducats,
-                    },));
+                    .insert(state);
             },
         );
 }
 
-fn unix_now() -> u64 {
+pub(crate) fn unix_now() -> u64 {
     use std::time::{SystemTime, UNIX_EPOCH};
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -185,6 +295,13 @@ pub struct ItemData {
     #[serde(deserialize_with = "deserialize_null_as_nan")]
     pub avg: f32,
 }
+impl ItemData {
+    /// Seconds since this data was fetched
+    pub fn age_secs(&self) -> u64 {
+        unix_now().saturating_sub(self.last_fetch)
+    }
+}
+
 fn deserialize_null_as_nan<'de, D: Deserializer<'de>>(des: D) -> Result<f32, D::Error> {
     let optional = Option::<f32>::deserialize(des)?;
     Ok(optional.unwrap_or(f32::NAN))
@@ -252,6 +369,11 @@ impl DataManager {
         self.map.get(k).and_then(|i| i.ducats)
     }
 
+    /// Cached data of any age, as long as it has a price
+    fn get_cached(&self, k: &String) -> Option<&ItemData> {
+        self.map.get(k).filter(|d| d.avg.is_finite())
+    }
+
     fn get_if_fresh(&self, k: &String) -> Option<&ItemData> {
         let data = self.map.get(k)?;
         if data.last_fetch + MAX_AGE < unix_now() {
@@ -262,14 +384,14 @@ impl DataManager {
     }
 
     fn save_to_disk(&self) {
-        let file = File::create("result.json").unwrap();
+        let file = File::create(paths::market_cache_file()).unwrap();
         let mut writer = BufWriter::new(file);
         serde_json::to_writer(&mut writer, self).unwrap();
         writer.flush().unwrap();
     }
 
     fn restore_from_disk_or_empty() -> Self {
-        if let Ok(file) = File::open("result.json") {
+        if let Ok(file) = File::open(paths::market_cache_file()) {
             let mut reader = BufReader::new(file);
             if let Ok(mut m) = serde_json::from_reader::<_, Self>(&mut reader) {
                 m.ordered = m
@@ -284,6 +406,84 @@ impl DataManager {
     }
 }
 
+/// How much market data is cached
+pub struct CacheSummary {
+    /// Tradeable items in the item list
+    pub known: usize,
+    /// Items with a price, of any age
+    pub priced: usize,
+    /// Seconds since the oldest price was fetched
+    pub oldest_age: Option<u64>,
+}
+
+/// Looking up prices by item name, for requests from outside the overlay
+#[derive(SystemParam)]
+pub struct PriceLookup<'w, 's> {
+    index: Query<'w, 's, &'static ItemSearchIndex>,
+    fetching: Query<'w, 's, &'static Slug, With<WantsFetch>>,
+    data: Res<'w, DataManager>,
+}
+
+impl PriceLookup<'_, '_> {
+    /// Whether the item list was fetched, without it nothing can be looked up
+    pub fn index_ready(&self) -> bool {
+        !self.index.is_empty()
+    }
+
+    pub fn cache_summary(&self) -> CacheSummary {
+        let priced: Vec<&ItemData> = self
+            .data
+            .map
+            .values()
+            .filter(|d| d.avg.is_finite())
+            .collect();
+        CacheSummary {
+            known: self.data.map.len(),
+            priced: priced.len(),
+            oldest_age: priced.iter().map(|d| d.age_secs()).max(),
+        }
+    }
+
+    /// The prices of the item best matching `name`, as text.
+    ///
+    /// Fetches new prices in the background if the cached ones are too old, like for the overlay.
+    pub fn describe(&self, name: &str, commands: &mut Commands) -> Result<String, String> {
+        let index = self
+            .index
+            .single()
+            .map_err(|_| "the item list wasn't loaded yet".to_string())?;
+        let Some(slug) = index.search(name).into_iter().next() else {
+            return Err(format!("no item matches {name:?}"));
+        };
+        let Some(matched) = index.names.get(&slug) else {
+            return Ok(format!("{name}: not tradeable"));
+        };
+        let confidence = similarity(name, matched);
+        let mut text = if confidence < 1. {
+            format!("{matched} ({:.0}% match)", confidence * 100.)
+        } else {
+            matched.clone()
+        };
+
+        let fresh = self.data.get_if_fresh(&slug).filter(|d| d.avg.is_finite());
+        match fresh.or_else(|| self.data.get_cached(&slug)) {
+            Some(data) => {
+                text += &format!("\n{:.0}p avg, {:.0}-{:.0}p", data.avg, data.min, data.max);
+                if let Some(ducats) = data.ducats {
+                    text += &format!(", {ducats} ducats");
+                }
+                text += &format!("\nfetched {} ago", format_age(data.age_secs()));
+            }
+            None => text += "\nno price cached yet",
+        }
+        if fresh.is_none() && !self.fetching.iter().any(|s| s.0 == slug) {
+            commands.spawn((Slug(slug), WantsFetch, RemoveOnStore));
+            text += ", fetching new prices";
+        }
+        Ok(text)
+    }
+}
+
 #[derive(Component, Debug)]
 struct RemoveOnStore;
 
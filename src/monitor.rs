@@ -0,0 +1,116 @@
+//! Keeping the overlay window on the monitor being captured
+use bevy::{
+    prelude::*,
+    window::{Monitor, MonitorSelection, PrimaryWindow, WindowMode},
+};
+
+use crate::{cap::CaptureArea, config::ConfigManager};
+
+pub fn monitor_plugin(app: &mut App) {
+    app.add_systems(
+        Update,
+        place_window.run_if(
+            resource_changed::<CaptureArea>
+                .or(resource_changed::<ConfigManager>)
+                .or(any_match_filter::<Added<Monitor>>)
+                .or(any_component_removed::<Monitor>),
+        ),
+    );
+}
+
+/// How much of `a` and `b` overlap, in square units
+fn overlap(a: IRect, b: IRect) -> i64 {
+    let size = a.intersect(b).size();
+    size.x.max(0) as i64 * size.y.max(0) as i64
+}
+
+/// Where the monitor is in compositor coordinates. Depending on the platform those are logical
+/// or physical, so both are given.
+fn monitor_rects(monitor: &Monitor) -> [IRect; 2] {
+    let size = IVec2::new(
+        monitor.physical_width as i32,
+        monitor.physical_height as i32,
+    );
+    let physical = IRect::from_corners(monitor.physical_position, monitor.physical_position + size);
+    let scale = monitor.scale_factor.max(f64::EPSILON) as f32;
+    let logical = IRect::from_corners(
+        (physical.min.as_vec2() / scale).round().as_ivec2(),
+        (physical.max.as_vec2() / scale).round().as_ivec2(),
+    );
+    [physical, logical]
+}
+
+/// The monitor showing most of `area`, which is in compositor coordinates, with the rect of the
+/// monitor in the same coordinates
+fn find_monitor_rect<'a>(
+    area: IRect,
+    monitors: impl Iterator<Item = (Entity, &'a Monitor)>,
+) -> Option<(Entity, IRect)> {
+    monitors
+        .flat_map(|(entity, monitor)| monitor_rects(monitor).map(|rect| (entity, rect)))
+        .map(|(entity, rect)| (entity, rect, overlap(area, rect)))
+        .filter(|(_, _, overlap)| *overlap > 0)
+        .max_by_key(|(_, _, overlap)| *overlap)
+        .map(|(entity, rect, _)| (entity, rect))
+}
+
+/// The monitor showing most of `area`, which is in compositor coordinates
+fn find_monitor<'a>(
+    area: IRect,
+    monitors: impl Iterator<Item = (Entity, &'a Monitor)>,
+) -> Option<Entity> {
+    find_monitor_rect(area, monitors).map(|(entity, _)| entity)
+}
+
+/// Where `area` is on the monitor showing most of it, from (0, 0) at its top left corner to
+/// (1, 1) at its bottom right. Used to place the overlay over a captured window.
+pub fn area_on_monitor<'a>(
+    area: IRect,
+    monitors: impl Iterator<Item = (Entity, &'a Monitor)>,
+) -> Option<Rect> {
+    let (_, monitor) = find_monitor_rect(area, monitors)?;
+    let size = monitor.size().as_vec2();
+    let relative = |p: IVec2| (p - monitor.min).as_vec2() / size;
+    Some(Rect::from_corners(relative(area.min), relative(area.max)))
+}
+
+/// Move the window to the configured monitor, or the one being captured.
+///
+/// Without either, e.g. when capturing a single window, the primary monitor is used.
+fn place_window(
+    area: Res<CaptureArea>,
+    conf: Res<ConfigManager>,
+    monitors: Query<(Entity, &Monitor)>,
+    mut window: Single<&mut Window, With<PrimaryWindow>>,
+) {
+    let configured = conf.monitor.as_deref().and_then(|name| {
+        let found = monitors
+            .iter()
+            .find(|(_, m)| m.name.as_deref() == Some(name))
+            .map(|(entity, _)| entity);
+        if found.is_none() && !monitors.is_empty() {
+            let names: Vec<_> = monitors
+                .iter()
+                .filter_map(|(_, m)| m.name.as_deref())
+                .collect();
+            warn!(
+                "No monitor named {name}, available are: {}",
+                names.join(", ")
+            );
+        }
+        found
+    });
+    let target = configured.or_else(|| area.0.and_then(|area| find_monitor(area, monitors.iter())));
+
+    let mode = WindowMode::BorderlessFullscreen(
+        target.map_or(MonitorSelection::Primary, MonitorSelection::Entity),
+    );
+    if window.mode != mode {
+        let name = target
+            .and_then(|e| monitors.get(e).ok())
+            .and_then(|(_, m)| m.name.as_deref())
+            .unwrap_or("the primary monitor");
+        info!("Showing the overlay on {name}");
+        window.mode = mode;
+    }
+}
@@ -18,8 +18,11 @@ use rten::Model;
 
 use crate::{
     PlatOverlayPhase, ShouldDisplay,
-    cap::LatestImage,
+    cap::{CaptureTransform, LatestImage, ScreencastReceiver, TriggerCapture},
     config::{ConfigManager, Layout},
+    input::Action,
+    market::ItemState,
+    paths,
 };
 
 fn file_path(path: &str) -> PathBuf {
@@ -31,13 +34,19 @@ fn file_path(path: &str) -> PathBuf {
 pub(crate) fn ocrs_plugin(app: &mut App) {
     app.init_resource::<Engine>()
         .init_resource::<OcrTask>()
-        .add_systems(OnEnter(PlatOverlayPhase::Ocr), start_ocr_task)
         .add_systems(Startup, setup_items_container)
         .add_systems(
             Update,
-            (get_ocr_result, debug_ocr_result)
+            // frames arrive a bit after they were requested, so this waits for one
+            (start_ocr_task, get_ocr_result, debug_ocr_result)
                 .chain()
                 .run_if(in_state(PlatOverlayPhase::Ocr)),
+        )
+        .add_systems(Update, save_debug_capture)
+        // a task left running would keep the next trigger from starting one
+        .add_systems(
+            OnExit(PlatOverlayPhase::Ocr),
+            |mut task: ResMut<OcrTask>| task.0 = None,
         );
 }
 fn setup_items_container(mut commands: Commands) {
@@ -86,6 +95,8 @@ pub struct Word {
 pub struct Item {
     pub name: String,
     pub bounds: Aabb2d,
+    /// Bounds in capture pixels, not converted to world space
+    pub capture_bounds: Aabb2d,
 }
 
 #[derive(Debug)]
@@ -96,8 +107,33 @@ struct OcrResults {
     items: Vec<Item>,
 }
 impl OcrResults {
-    fn convert_aabbs_inplace(&mut self, cam: (&Camera, &GlobalTransform)) {
-        let conv = |v: &mut Vec2| *v = cam.0.viewport_to_world_2d(cam.1, *v).unwrap();
+    /// Move everything by `offset`, for results from a crop of the capture
+    fn translate(&mut self, offset: Vec2) {
+        let move_aabb = |aabb: &mut Aabb2d| {
+            aabb.min += offset;
+            aabb.max += offset;
+        };
+        self.words.iter_mut().for_each(|w| move_aabb(&mut w.bounds));
+        self.lines.iter_mut().for_each(|w| move_aabb(&mut w.bounds));
+        self.items.iter_mut().for_each(|w| {
+            move_aabb(&mut w.bounds);
+            move_aabb(&mut w.capture_bounds);
+        });
+        move_aabb(&mut self.detect_aabb);
+    }
+
+    /// Convert everything but the capture bounds from capture pixels to world space
+    fn convert_aabbs_inplace(
+        &mut self,
+        cam: (&Camera, &GlobalTransform),
+        transform: CaptureTransform,
+    ) {
+        let conv = |v: &mut Vec2| {
+            *v = cam
+                .0
+                .viewport_to_world_2d(cam.1, transform.to_window(*v))
+                .unwrap()
+        };
         let conv_aabb = |aabb: &mut Aabb2d| {
             conv(&mut aabb.max);
             conv(&mut aabb.min)
@@ -154,28 +190,81 @@ pub fn detect_columns(words: &[Word], gap_threshold: f32) -> Vec<Item> {
                 bounds = bounds.merge(&word.bounds);
             }
 
-            Some(Item { name, bounds })
+            Some(Item {
+                name,
+                bounds,
+                capture_bounds: bounds,
+            })
         })
         .collect()
 }
 impl Layout {
-    fn get_scale_factor(&self, img_size: (u32, u32)) -> UVec2 {
-        UVec2::from(img_size) / self.reference_resolution
+    /// Scale a position at `reference_resolution` to the image, which needn't be a multiple of it
+    fn scale_to(&self, pos: UVec2, img_size: (u32, u32)) -> UVec2 {
+        pos * UVec2::from(img_size) / self.reference_resolution.max(UVec2::ONE)
     }
     fn get_scaled_item_name_distance(&self, img_size: (u32, u32)) -> f32 {
-        (self.item_name_distance * self.get_scale_factor(img_size).x) as f32
+        (self.item_name_distance * img_size.0) as f32 / self.reference_resolution.x.max(1) as f32
     }
     fn get_ocr_bounds(&self, img_size: (u32, u32)) -> URect {
-        let factor = self.get_scale_factor(img_size);
-        let offset = self.offset * factor;
-        let size = self.size * factor;
-
         URect {
-            min: offset,
-            max: (offset + size),
+            min: self.scale_to(self.offset, img_size),
+            max: self
+                .scale_to(self.offset + self.size, img_size)
+                .min(UVec2::from(img_size)),
         }
     }
 }
+
+/// Pixels closer than this to the theme text color count as text, see `color_distance_fast`
+const TEXT_COLOR_DISTANCE: f32 = 30.;
+/// Share of text pixels a band needs to be taken for the reward names
+const MIN_TEXT_SHARE: f32 = 0.005;
+
+/// Search the whole frame for the band with the reward names, for when no layout applies.
+///
+/// `base` gives the text color, and the height of the band and distance between names relative
+/// to the frame height, since the game scales its UI with the height.
+fn find_text_band(img: &image::RgbaImage, base: &Layout) -> Option<Layout> {
+    let (width, height) = img.dimensions();
+    let text_color = base.theme_text_color.to_u8_array();
+    let scale = height as f32 / base.reference_resolution.y.max(1) as f32;
+    let band = ((base.size.y as f32 * scale).round() as u32).clamp(1, height);
+    let is_text = |x: u32, y: u32| {
+        color_distance_fast(&img.get_pixel(x, y).0, &text_color) < TEXT_COLOR_DISTANCE
+    };
+
+    let rows: Vec<u32> = (0..height)
+        .map(|y| (0..width).filter(|&x| is_text(x, y)).count() as u32)
+        .collect();
+    let (top, count) = rows
+        .windows(band as usize)
+        .map(|window| window.iter().sum::<u32>())
+        .enumerate()
+        // max_by_key takes the last of equal ones, reversed that's the topmost band
+        .rev()
+        .max_by_key(|&(_, count)| count)?;
+    if (count as f32) < (band * width) as f32 * MIN_TEXT_SHARE {
+        return None;
+    }
+
+    let top = top as u32;
+    // single stray pixels in the band don't count as names
+    let columns: Vec<u32> = (0..width)
+        .filter(|&x| (top..top + band).filter(|&y| is_text(x, y)).count() >= 2)
+        .collect();
+    let (&left, &right) = (columns.first()?, columns.last()?);
+    let item_name_distance = (base.item_name_distance as f32 * scale).round() as u32;
+    let left = left.saturating_sub(item_name_distance);
+    let right = (right + 1 + item_name_distance).min(width);
+    Some(Layout {
+        offset: UVec2::new(left, top),
+        size: UVec2::new(right - left, band),
+        reference_resolution: UVec2::new(width, height),
+        item_name_distance,
+        ..base.clone()
+    })
+}
 #[inline]
 fn color_distance_fast(c1: &[u8; 4], c2: &[u8; 4]) -> f32 {
     let dr = (c1[0] as f32 - c2[0] as f32).abs() * 0.299;
@@ -280,40 +369,104 @@ fn detect_once(engine: Engine, img: image::RgbaImage, layout: Layout) -> Result<
     })
 }
 
+/// The running OCR, which gives no results if there were no reward names in the capture
 #[derive(Resource, Default)]
-struct OcrTask(Option<Task<Result<OcrResults>>>);
+struct OcrTask(Option<Task<Result<Option<OcrResults>>>>);
 const PRINTER: DateTimePrinter = DateTimePrinter::new().separator(b'_').precision(Some(0));
 
-fn start_ocr_task(
+/// Save a capture to the captures dir, named by the current time
+fn save_capture(img: &image::RgbaImage) {
+    let dir = paths::captures_dir();
+    std::fs::create_dir_all(&dir).unwrap();
+    let ts = PRINTER
+        .timestamp_to_string(&jiff::Timestamp::now())
+        .replace(":", "_");
+    if let Err(e) = img.save(dir.join(format!("{ts}.png"))) {
+        error!("Could not save screenshot: {e}");
+    };
+}
+
+/// Save the first frame captured after the save capture action
+fn save_debug_capture(
+    actions: Res<ButtonInput<Action>>,
+    receiver: Res<ScreencastReceiver>,
     mut img: ResMut<LatestImage>,
+    mut pending: Local<Option<Instant>>,
+) {
+    if actions.just_pressed(Action::SaveCapture) {
+        *pending = Some(Instant::now());
+        receiver.request_frame();
+    }
+    let Some(since) = *pending else {
+        return;
+    };
+    if img.latest_info().is_some_and(|info| info.captured >= since)
+        && let Some(frame) = img.get_latest_rgba()
+    {
+        save_capture(&frame);
+        info!("Saved a capture to images/");
+        img.recycle_image(frame);
+        *pending = None;
+    }
+}
+
+fn start_ocr_task(
+    mut trigger: ResMut<TriggerCapture>,
     engine: Res<Engine>,
     conf: Res<ConfigManager>,
     mut current_task: ResMut<OcrTask>,
     mut items: Single<&mut ItemsContainer>,
 ) {
     if current_task.0.is_none()
-        && let Some(img) = img.get_latest_rgba()
+        && let Some(img) = trigger.take_best()
     {
         let engine = engine.clone();
         if conf.save_to_disk {
-            std::fs::create_dir_all("images").unwrap();
-            let ts = PRINTER
-                .timestamp_to_string(&jiff::Timestamp::now())
-                .replace(":", "_");
-            if let Err(e) = img.save(format!("images/{ts}.png")) {
-                error!("Could not save screenshot: {e}");
-            };
+            save_capture(&img);
         }
-        let Some(layout) = conf.find_matching_layout(&img) else {
+        // layouts apply to the game, which might be a window inside the capture
+        let (viewport, img) = conf.viewport.crop(img);
+        let (width, height) = img.dimensions();
+        // whether the layout only gives the text color and scale, and the names have to be searched
+        let (layout, search_band) = if let Some(layout) = conf.find_matching_layout(&img) {
+            (layout.clone(), false)
+        } else if conf
+            .layouts
+            .iter()
+            .any(|l| l.aspect_ratio_matches(width, height))
+        {
+            // a layout fits the frame, so the checks failing means it's another screen
             warn!("Could not detect layout for capture");
             return;
+        } else if let Some(base) = conf.closest_layout(width, height) {
+            (base.config.clone(), true)
+        } else {
+            warn!("No layouts are configured");
+            return;
         };
-        let layout = layout.clone();
         current_task.0 = Some(AsyncComputeTaskPool::get().spawn(async move {
             let start = Instant::now();
+            let layout = if search_band {
+                let Some(layout) = find_text_band(&img, &layout) else {
+                    warn!(
+                        "No layout has the aspect ratio of the {width}x{height} capture, and no reward names were found in it"
+                    );
+                    return Ok(None);
+                };
+                info!(
+                    "No layout has the aspect ratio of the {width}x{height} capture, found the reward names at {}",
+                    layout.offset
+                );
+                layout
+            } else {
+                layout
+            };
             let res = detect_once(engine.clone(), img.clone(), layout);
             debug!("OCR took {}ms", start.elapsed().as_millis());
-            res
+            res.map(|mut res| {
+                res.translate(viewport.min.as_vec2());
+                Some(res)
+            })
         }));
         items.1 = Color::linear_rgb(0.1, 0.9, 0.1);
     }
@@ -327,13 +480,18 @@ fn get_ocr_result(
     mut current_task: ResMut<OcrTask>,
     mut commands: Commands,
     cam: Single<(&Camera, &GlobalTransform)>,
+    transform: Res<CaptureTransform>,
     mut items: Single<(Entity, &mut ItemsContainer)>,
 ) -> Result<()> {
     if let Some(ref mut task) = current_task.0
         && let Some(result) = block_on(future::poll_once(task))
     {
-        let mut result = result?;
-        result.convert_aabbs_inplace(*cam);
+        // finished tasks can't be polled again, even if they failed
+        current_task.0 = None;
+        let Some(mut result) = result? else {
+            return Ok(());
+        };
+        result.convert_aabbs_inplace(*cam, *transform);
         items.1.0 = result.detect_aabb;
         items.1.1 = Color::linear_rgb(0.9, 0.1, 0.9);
 
@@ -348,12 +506,12 @@ fn get_ocr_result(
                 c.spawn((
                     item,
                     ShouldDisplay,
+                    ItemState::Matching,
                     Visibility::Inherited,
                     Transform::from_xyz(center.x, result.detect_aabb.max.y, 0.0),
                 ));
             }
         });
-        current_task.0 = None;
     }
 
     Ok(())
@@ -388,3 +546,45 @@ fn debug_ocr_result(
         timer.finish();
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+
+    fn base() -> Layout {
+        Layout {
+            offset: UVec2::new(30, 50),
+            size: UVec2::new(100, 10),
+            reference_resolution: UVec2::new(160, 90),
+            theme_text_color: Srgba::WHITE,
+            item_name_distance: 4,
+        }
+    }
+
+    #[test]
+    fn text_band_is_found() {
+        // twice the reference height, so the band is 20 high
+        let text = URect::new(60, 100, 200, 110);
+        let img = RgbaImage::from_fn(320, 180, |x, y| {
+            if text.contains(UVec2::new(x, y)) {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([20, 20, 20, 255])
+            }
+        });
+        let band = find_text_band(&img, &base()).unwrap();
+        // the topmost band containing all of the text
+        assert_eq!(band.offset, UVec2::new(60 - 8, 90));
+        assert_eq!(band.size, UVec2::new(140 + 16, 20));
+        assert_eq!(band.reference_resolution, UVec2::new(320, 180));
+        assert_eq!(band.item_name_distance, 8);
+    }
+
+    #[test]
+    fn no_text_no_band() {
+        let img = RgbaImage::from_pixel(320, 180, Rgba([20, 20, 20, 255]));
+        assert!(find_text_band(&img, &base()).is_none());
+    }
+}
@@ -0,0 +1,243 @@
+//! Where the files wf_overlay keeps are, following the XDG base directories:
+//!
+//! - the config in `$XDG_CONFIG_HOME/wf_overlay/`
+//! - the reward history and saved captures in `$XDG_DATA_HOME/wf_overlay/`
+//! - the market prices in `$XDG_CACHE_HOME/wf_overlay/`
+//! - the screencast sessions in `$XDG_STATE_HOME/wf_overlay/`
+//!
+//! `--config`/`WF_OVERLAY_CONFIG` sets the config file, `--data-dir`/`WF_OVERLAY_DATA_DIR` keeps
+//! everything in one directory instead. Files from older versions, which kept them in the working
+//! directory, are moved over once by [`migrate_from_cwd`].
+//!
+//! This all happens before the app starts logging, so messages go straight to stderr.
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+const APP: &str = env!("CARGO_PKG_NAME");
+const CONFIG_ENV: &str = "WF_OVERLAY_CONFIG";
+const DATA_DIR_ENV: &str = "WF_OVERLAY_DATA_DIR";
+
+static PATHS: OnceLock<Paths> = OnceLock::new();
+
+struct Paths {
+    config: PathBuf,
+    data: PathBuf,
+    cache: PathBuf,
+    state: PathBuf,
+}
+
+impl Paths {
+    fn resolve(config: Option<PathBuf>, data_dir: Option<PathBuf>) -> Self {
+        let config = config.or_else(|| env_path(CONFIG_ENV));
+        let data_dir = data_dir.or_else(|| env_path(DATA_DIR_ENV));
+        let paths = match data_dir {
+            Some(dir) => Self {
+                config: config.unwrap_or_else(|| dir.join(format!("{APP}.toml"))),
+                data: dir.clone(),
+                cache: dir.clone(),
+                state: dir,
+            },
+            None => Self {
+                config: config.unwrap_or_else(|| {
+                    xdg_dir("XDG_CONFIG_HOME", ".config").join(format!("{APP}.toml"))
+                }),
+                data: xdg_dir("XDG_DATA_HOME", ".local/share"),
+                cache: xdg_dir("XDG_CACHE_HOME", ".cache"),
+                state: xdg_dir("XDG_STATE_HOME", ".local/state"),
+            },
+        };
+        let config_dir = paths.config.parent().unwrap_or(Path::new(""));
+        for dir in [config_dir, &paths.data, &paths.cache, &paths.state] {
+            if let Err(e) = std::fs::create_dir_all(dir)
+                && !dir.as_os_str().is_empty()
+            {
+                eprintln!("Could not create {}: {e}", dir.display());
+            }
+        }
+        paths
+    }
+}
+
+fn env_path(var: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+}
+
+/// `$var/wf_overlay`, or `~/fallback/wf_overlay` if it isn't set to an absolute path
+fn xdg_dir(var: &str, fallback: &str) -> PathBuf {
+    let base = env_path(var)
+        .filter(|p| p.is_absolute())
+        .or_else(|| env_path("HOME").map(|home| home.join(fallback)))
+        // without a home, like before there were XDG directories
+        .unwrap_or_default();
+    base.join(APP)
+}
+
+fn paths() -> &'static Paths {
+    PATHS.get_or_init(|| Paths::resolve(None, None))
+}
+
+/// Use these instead of the environment and XDG directories. Has to be called before any path
+/// is used, later calls have no effect.
+pub fn set_overrides(config: Option<PathBuf>, data_dir: Option<PathBuf>) {
+    if PATHS.set(Paths::resolve(config, data_dir)).is_err() {
+        eprintln!("Paths were already in use, ignoring --config and --data-dir");
+    }
+}
+
+/// Remove `--config FILE` and `--data-dir DIR` from the arguments, returning their values
+pub fn take_overrides(
+    args: &mut Vec<String>,
+) -> Result<(Option<PathBuf>, Option<PathBuf>), String> {
+    let mut config = None;
+    let mut data_dir = None;
+    let mut i = 0;
+    while i < args.len() {
+        let target = match args[i].as_str() {
+            "--config" => &mut config,
+            "--data-dir" => &mut data_dir,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        let flag = args.remove(i);
+        if i >= args.len() {
+            return Err(format!("{flag} needs a path"));
+        }
+        *target = Some(PathBuf::from(args.remove(i)));
+    }
+    Ok((config, data_dir))
+}
+
+pub fn config_file() -> &'static Path {
+    &paths().config
+}
+
+pub fn history_file() -> PathBuf {
+    paths().data.join("history.jsonl")
+}
+
+/// Where captures are saved for debugging
+pub fn captures_dir() -> PathBuf {
+    paths().data.join("images")
+}
+
+/// Screenshots of screens which aren't the reward screen, to test pixel checks against
+pub fn negatives_dir() -> PathBuf {
+    paths().data.join("negatives")
+}
+
+pub fn market_cache_file() -> PathBuf {
+    paths().cache.join("result.json")
+}
+
+/// The restore token of the screencast portal, separately for windows and monitors
+pub fn screencast_session_file(window: bool) -> PathBuf {
+    let name = if window {
+        "window_session.txt"
+    } else {
+        "screen_session.txt"
+    };
+    paths().state.join(name)
+}
+
+/// Written to the state dir once files were migrated from a working directory
+fn migrated_marker() -> PathBuf {
+    paths().state.join("migrated_from_cwd")
+}
+
+/// Move files older versions kept in the working directory to where they are kept now.
+///
+/// Only happens once, and only if the working directory has the config or a screencast session
+/// of an older version, so unrelated files like an `images/` folder in `~` stay where they are.
+/// The config is copied instead, since it may be the example in a source checkout. Files which
+/// already exist in their new place are left alone.
+pub fn migrate_from_cwd() {
+    let marker = migrated_marker();
+    if marker.exists() {
+        return;
+    }
+    let is_old_dir = [
+        format!("{APP}.toml"),
+        "screen_session.txt".to_string(),
+        "window_session.txt".to_string(),
+    ]
+    .iter()
+    .any(|name| Path::new(name).is_file());
+    if !is_old_dir {
+        return;
+    }
+    let config = config_file();
+    let files = [
+        (
+            PathBuf::from(format!("{APP}.toml")),
+            config.to_path_buf(),
+            true,
+        ),
+        (
+            PathBuf::from(format!("{APP}.bak.toml")),
+            config.with_extension("bak.toml"),
+            true,
+        ),
+        (PathBuf::from("result.json"), market_cache_file(), false),
+        (PathBuf::from("history.jsonl"), history_file(), false),
+        (PathBuf::from("images"), captures_dir(), false),
+        (
+            PathBuf::from("screen_session.txt"),
+            screencast_session_file(false),
+            false,
+        ),
+        (
+            PathBuf::from("window_session.txt"),
+            screencast_session_file(true),
+            false,
+        ),
+    ];
+    for (old, new, copy) in files {
+        if !old.exists() || new.exists() || same_file(&old, &new) {
+            continue;
+        }
+        let (result, verb) = if copy {
+            (std::fs::copy(&old, &new).map(|_| ()), "Copied")
+        } else {
+            (move_path(&old, &new), "Moved")
+        };
+        match result {
+            Ok(()) => eprintln!("{verb} {} to {}", old.display(), new.display()),
+            Err(e) => eprintln!("Could not move {} to {}: {e}", old.display(), new.display()),
+        }
+    }
+    let cwd = std::env::current_dir().unwrap_or_default();
+    if let Err(e) = std::fs::write(&marker, cwd.as_os_str().as_encoded_bytes()) {
+        eprintln!("Could not write {}: {e}", marker.display());
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.parent().map(Path::canonicalize)) {
+        (Ok(a), Some(Ok(dir))) => b.file_name().is_some_and(|name| a == dir.join(name)),
+        _ => false,
+    }
+}
+
+/// Rename, or copy and remove when moving to another filesystem
+fn move_path(old: &Path, new: &Path) -> std::io::Result<()> {
+    if std::fs::rename(old, new).is_ok() {
+        return Ok(());
+    }
+    if old.is_dir() {
+        std::fs::create_dir_all(new)?;
+        for entry in std::fs::read_dir(old)? {
+            let entry = entry?;
+            move_path(&entry.path(), &new.join(entry.file_name()))?;
+        }
+        std::fs::remove_dir(old)
+    } else {
+        std::fs::copy(old, new)?;
+        std::fs::remove_file(old)
+    }
+}
@@ -0,0 +1,281 @@
+//! Detect which reward the player picked, by watching the reward cards for highlights
+use std::time::Duration;
+
+use bevy::{
+    math::bounding::{Aabb2d, BoundingVolume},
+    prelude::*,
+    sprite::{Anchor, Text2dShadow},
+    time::common_conditions::on_real_timer,
+};
+use image::RgbaImage;
+
+use crate::{
+    PlatOverlayPhase, RepeatedOverlay,
+    cap::{LatestImage, ScreencastReceiver},
+    config::ConfigManager,
+    market::ItemData,
+    ocr::{self, ItemsContainer},
+};
+
+/// Mean change in luminance (0-255) a card needs before it counts as highlighted
+const CHANGE_THRESHOLD: f32 = 12.0;
+/// How much more a card has to change than every other card to be the highlighted one
+const DOMINANCE: f32 = 2.0;
+/// Only every nth pixel in each direction is compared
+const SAMPLE_STEP: usize = 4;
+/// How long a card has to stay highlighted before it's confirmed as the pick
+const CONFIRM_AFTER: Duration = Duration::from_millis(1500);
+
+pub fn pick_plugin(app: &mut App) {
+    // a reward screen shown again is already gone from the screen
+    let watching =
+        in_state(PlatOverlayPhase::Displaying).and(not(resource_exists::<RepeatedOverlay>));
+    app.init_resource::<PickDetector>()
+        .add_systems(
+            OnEnter(PlatOverlayPhase::Displaying),
+            reset_pick_detector.run_if(not(resource_exists::<RepeatedOverlay>)),
+        )
+        .add_systems(OnExit(PlatOverlayPhase::Displaying), stop_watching)
+        .add_systems(
+            Update,
+            (
+                detect_pick.run_if(on_real_timer(Duration::from_millis(250))),
+                confirm_pick,
+            )
+                .chain()
+                .run_if(watching),
+        );
+}
+
+/// Marks the reward the player is currently highlighting
+#[derive(Component, Debug)]
+pub struct Picked;
+
+/// Marks the reward which stayed highlighted for [`CONFIRM_AFTER`], the one the player picked
+#[derive(Component, Debug)]
+pub struct ConfirmedPick;
+
+#[derive(Resource, Default)]
+struct PickDetector {
+    /// Card regions in capture pixels, in the same order as the items
+    regions: Vec<URect>,
+    /// Luminance samples of each card, taken from the first frame after display started
+    baseline: Vec<Vec<u8>>,
+    /// When the highlight last moved to another card
+    highlighted_at: Option<Duration>,
+    /// The reward the confirmation is shown for
+    confirmed: Option<Entity>,
+}
+
+fn reset_pick_detector(mut detector: ResMut<PickDetector>, receiver: Res<ScreencastReceiver>) {
+    *detector = default();
+    // the screen needs to be watched for highlights
+    receiver.set_continuous(true);
+}
+
+fn stop_watching(receiver: Res<ScreencastReceiver>) {
+    receiver.set_continuous(false);
+}
+
+/// The card belonging to an item name: the column around it, reaching up above the name.
+fn card_regions(bounds: &[Aabb2d], img_size: UVec2) -> Vec<URect> {
+    let centers: Vec<f32> = bounds.iter().map(|b| b.center().x).collect();
+    bounds
+        .iter()
+        .enumerate()
+        .map(|(i, b)| {
+            // half the distance to the neighbouring names, or the name width for a single item
+            let half_width = match (i.checked_sub(1).map(|p| centers[p]), centers.get(i + 1)) {
+                (Some(prev), _) => (centers[i] - prev) / 2.,
+                (None, Some(next)) => (next - centers[i]) / 2.,
+                (None, None) => b.half_size().x,
+            };
+            // cards are taller than wide, but only the part right above the name is needed
+            let height = half_width * 1.5;
+            let min = Vec2::new(centers[i] - half_width * 0.8, b.min.y - height);
+            let max = Vec2::new(centers[i] + half_width * 0.8, b.min.y - 2.);
+            URect::from_corners(
+                min.max(Vec2::ZERO).as_uvec2().min(img_size),
+                max.max(Vec2::ZERO).as_uvec2().min(img_size),
+            )
+        })
+        .collect()
+}
+
+fn luminance_samples(img: &RgbaImage, region: URect) -> Vec<u8> {
+    let mut samples = Vec::new();
+    for y in (region.min.y..region.max.y).step_by(SAMPLE_STEP) {
+        for x in (region.min.x..region.max.x).step_by(SAMPLE_STEP) {
+            let [r, g, b, _] = img.get_pixel(x, y).0;
+            samples.push((0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32) as u8);
+        }
+    }
+    samples
+}
+
+fn mean_difference(a: &[u8], b: &[u8]) -> f32 {
+    if a.is_empty() || a.len() != b.len() {
+        return 0.;
+    }
+    let sum: u32 = a.iter().zip(b).map(|(a, b)| a.abs_diff(*b) as u32).sum();
+    sum as f32 / a.len() as f32
+}
+
+/// Index of the card which changed clearly more than all others, if any
+fn highlighted_card(changes: &[f32]) -> Option<usize> {
+    let (best, &max) = changes
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))?;
+    let runner_up = changes
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != best)
+        .map(|(_, c)| *c)
+        .fold(0f32, f32::max);
+    (max >= CHANGE_THRESHOLD && max >= runner_up * DOMINANCE).then_some(best)
+}
+
+fn detect_pick(
+    mut img: ResMut<LatestImage>,
+    mut detector: ResMut<PickDetector>,
+    items: Single<&Children, With<ItemsContainer>>,
+    query: Query<(Entity, &ocr::Item, Has<Picked>)>,
+    time: Res<Time<Real>>,
+    mut commands: Commands,
+) {
+    let items: Vec<_> = query.iter_many(items.iter()).collect();
+    if items.is_empty() {
+        return;
+    }
+    let Some(frame) = img.get_latest_rgba() else {
+        return;
+    };
+
+    if detector.baseline.is_empty() {
+        let bounds: Vec<Aabb2d> = items.iter().map(|(_, item, _)| item.capture_bounds).collect();
+        let regions = card_regions(&bounds, frame.dimensions().into());
+        detector.baseline = regions
+            .iter()
+            .map(|r| luminance_samples(&frame, *r))
+            .collect();
+        detector.regions = regions;
+        img.recycle_image(frame);
+        return;
+    }
+
+    let changes: Vec<f32> = detector
+        .regions
+        .iter()
+        .zip(&detector.baseline)
+        .map(|(region, baseline)| mean_difference(baseline, &luminance_samples(&frame, *region)))
+        .collect();
+    img.recycle_image(frame);
+    let Some(highlighted) = highlighted_card(&changes) else {
+        return;
+    };
+    for (idx, (entity, item, picked)) in items.into_iter().enumerate() {
+        if idx == highlighted && !picked {
+            debug!("Highlighted reward changed to {}", item.name);
+            detector.highlighted_at = Some(time.elapsed());
+            commands.entity(entity).insert(Picked);
+        } else if idx != highlighted && picked {
+            commands.entity(entity).remove::<Picked>();
+        }
+    }
+}
+
+#[derive(Component)]
+struct PickConfirmation;
+
+/// Show which reward was picked, once the highlight stayed on it for [`CONFIRM_AFTER`]
+fn confirm_pick(
+    mut detector: ResMut<PickDetector>,
+    picked: Option<Single<(Entity, &ocr::Item, Option<&ItemData>), With<Picked>>>,
+    items: Single<(Entity, &ItemsContainer)>,
+    existing: Query<Entity, With<PickConfirmation>>,
+    time: Res<Time<Real>>,
+    conf: Res<ConfigManager>,
+    mut commands: Commands,
+) {
+    let Some(picked) = picked else {
+        return;
+    };
+    let (entity, item, data) = *picked;
+    if detector.confirmed == Some(entity)
+        || detector
+            .highlighted_at
+            .is_none_or(|at| time.elapsed() - at < CONFIRM_AFTER)
+    {
+        return;
+    }
+    if let Some(previous) = detector.confirmed.replace(entity) {
+        commands.entity(previous).try_remove::<ConfirmedPick>();
+    }
+    commands.entity(entity).insert(ConfirmedPick);
+    existing
+        .iter()
+        .for_each(|e| commands.entity(e).despawn());
+
+    let worth = match data {
+        Some(data) if data.avg.is_finite() => format!(" (worth {:.0}p)", data.avg),
+        _ => String::new(),
+    };
+    let (container, ItemsContainer(area, _)) = *items;
+    commands.entity(container).with_child((
+        PickConfirmation,
+        Transform::from_xyz(area.center().x, area.min.y.max(area.max.y) + 40., 0.),
+        Text2d(format!("You picked {}{worth}", item.name)),
+        TextFont::from_font_size(conf.font_size),
+        Anchor::BOTTOM_CENTER,
+        Text2dShadow {
+            offset: Vec2::new(1.5, -1.5),
+            color: Color::BLACK,
+        },
+        DespawnOnExit(PlatOverlayPhase::Displaying),
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlighted_card_needs_a_clear_change() {
+        assert_eq!(highlighted_card(&[30., 5., 3.]), Some(0));
+        assert_eq!(highlighted_card(&[2., 40., 10.]), Some(1));
+        // two cards changing alike are an animation, not a highlight
+        assert_eq!(highlighted_card(&[30., 20.]), None);
+        assert_eq!(highlighted_card(&[CHANGE_THRESHOLD - 1., 0.]), None);
+        assert_eq!(highlighted_card(&[]), None);
+    }
+
+    fn name(min: Vec2, max: Vec2) -> Aabb2d {
+        Aabb2d { min, max }
+    }
+
+    #[test]
+    fn card_regions_sit_above_the_names() {
+        let bounds = [
+            name(Vec2::new(60., 500.), Vec2::new(140., 520.)),
+            name(Vec2::new(260., 500.), Vec2::new(340., 520.)),
+        ];
+        let regions = card_regions(&bounds, UVec2::new(1000, 1000));
+        // half the distance between the names is 100, of which 80% is used
+        assert_eq!(
+            regions,
+            vec![
+                URect::new(20, 350, 180, 498),
+                URect::new(220, 350, 380, 498)
+            ]
+        );
+    }
+
+    #[test]
+    fn card_regions_stay_inside_the_image() {
+        let bounds = [name(Vec2::new(10., 40.), Vec2::new(90., 60.))];
+        let regions = card_regions(&bounds, UVec2::new(80, 100));
+        // a single name uses its own width, the card would reach above and left of the image
+        assert_eq!(regions, vec![URect::new(18, 0, 80, 38)]);
+    }
+}
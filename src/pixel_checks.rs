@@ -0,0 +1,548 @@
+//! Finding pixel checks which tell a screen apart, from screenshots of it and of other screens.
+//!
+//! Candidates are taken on a grid over the screen, leaving out the item names which change with
+//! every reward. A candidate has to sit in a flat area, so a slightly different scale or
+//! compression still gives the same color. Of those, the ones ruling out the most negative
+//! screenshots (other screens with the same aspect ratio) are picked, spread over the screen.
+//!
+//! `wf_overlay pixel-checks` runs this on files, the layout editor on the current frame.
+use std::{
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use bevy::{
+    color::color_difference::EuclideanDistance,
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task, block_on, futures_lite::future},
+};
+use image::RgbaImage;
+
+use crate::{
+    AppState, ERROR_COLOR, STATUS_COLOR,
+    cap::{CaptureTransform, LatestImage, ScreencastReceiver},
+    config::{ConfigManager, LayoutOption, PixelCheck, Viewport},
+    input::Action,
+    paths,
+};
+
+/// Spacing of the candidates, in reference pixels
+const GRID_STEP: u32 = 8;
+/// How far around a candidate the color has to stay the same, in reference pixels
+const FLAT_RADIUS: u32 = 3;
+/// Candidates whose surroundings vary more than this aren't stable
+const MAX_VARIATION: f32 = 0.06;
+/// Added to the variation for the tolerance of a check
+const TOLERANCE_MARGIN: f32 = 0.05;
+/// Kept free around the item names, in reference pixels
+const NAME_MARGIN: u32 = 16;
+/// Checks are at least this far apart, as a fraction of the reference width
+const MIN_SPACING: f32 = 0.04;
+/// Among checks ruling out the same screenshots, ones closer than this to the others (as a
+/// fraction of the reference width) are less likely to be picked, so they spread out
+const SPREAD: f32 = 0.25;
+/// How many checks are proposed if not told otherwise
+pub const DEFAULT_COUNT: usize = 6;
+
+pub fn pixel_checks_plugin(app: &mut App) {
+    app.add_systems(OnEnter(AppState::EditOverlay), start_editing)
+        .add_systems(OnExit(AppState::EditOverlay), |mut commands: Commands| {
+            commands.remove_resource::<Editor>()
+        })
+        .add_systems(
+            Update,
+            (
+                take_frame,
+                finish_proposal,
+                save_proposal,
+                draw_proposal,
+                update_editor_text,
+            )
+                .chain()
+                .run_if(in_state(AppState::EditOverlay).and(resource_exists::<Editor>)),
+        );
+}
+
+/// Pixel checks for a layout, and how well they tell its screen apart
+#[derive(Debug, Clone)]
+pub struct Proposal {
+    pub checks: Vec<PixelCheck>,
+    /// How many negatives have the layout's aspect ratio, the others are ruled out by that already
+    pub compared: usize,
+    /// Indices of the negatives which pass all checks, and would be taken for this screen
+    pub false_positives: Vec<usize>,
+}
+
+impl Proposal {
+    pub fn summary(&self) -> String {
+        match (self.compared, self.false_positives.len()) {
+            (0, _) => "there are no other screenshots with this aspect ratio to test them against"
+                .to_string(),
+            (compared, 0) => format!("they tell apart all {compared} other screenshots"),
+            (compared, passed) => {
+                format!("{passed} of {compared} other screenshots still pass them")
+            }
+        }
+    }
+}
+
+struct Candidate {
+    check: PixelCheck,
+    /// Distance to the screen's average color, rarer colors tell it apart better
+    contrast: f32,
+    /// Which of the compared negatives fail the check
+    rules_out: Vec<bool>,
+}
+
+/// Propose up to `count` checks which all `positives` pass and as few `negatives` as possible.
+///
+/// The screenshots have to be cropped to the game already.
+pub fn propose(
+    layout: &LayoutOption,
+    positives: &[RgbaImage],
+    negatives: &[RgbaImage],
+    count: usize,
+) -> Proposal {
+    let reference = layout.config.reference_resolution;
+    let compared: Vec<usize> = (0..negatives.len())
+        .filter(|&i| {
+            let (width, height) = negatives[i].dimensions();
+            layout.aspect_ratio_matches(width, height)
+        })
+        .collect();
+    let mut candidates = candidates(layout, positives);
+    for candidate in &mut candidates {
+        candidate.rules_out = compared
+            .iter()
+            .map(|&i| !candidate.check.matches_image(&negatives[i], reference))
+            .collect();
+    }
+
+    // greedily take the check ruling out the most negatives the others let through
+    let mut remaining = vec![true; compared.len()];
+    let mut checks: Vec<PixelCheck> = Vec::new();
+    let width = reference.x as f32;
+    let position = |check: &PixelCheck| UVec2::new(check.x, check.y).as_vec2();
+    while checks.len() < count {
+        // as a fraction of the reference width
+        let spacing = |c: &Candidate| {
+            checks
+                .iter()
+                .map(|chosen| position(chosen).distance(position(&c.check)) / width)
+                .fold(SPREAD, f32::min)
+        };
+        let score = |c: &Candidate| {
+            let new = c
+                .rules_out
+                .iter()
+                .zip(&remaining)
+                .filter(|(out, left)| **out && **left);
+            (new.count(), c.rules_out.iter().filter(|out| **out).count())
+        };
+        let best = candidates
+            .iter()
+            .map(|c| (c, spacing(c)))
+            .filter(|(_, spacing)| *spacing >= MIN_SPACING)
+            .max_by(|(a, a_spacing), (b, b_spacing)| {
+                score(a)
+                    .cmp(&score(b))
+                    .then((a.contrast * a_spacing).total_cmp(&(b.contrast * b_spacing)))
+            });
+        let Some((best, _)) = best else {
+            break;
+        };
+        for (left, out) in remaining.iter_mut().zip(&best.rules_out) {
+            *left &= !out;
+        }
+        checks.push(best.check);
+    }
+
+    Proposal {
+        checks,
+        compared: compared.len(),
+        false_positives: compared
+            .into_iter()
+            .zip(remaining)
+            .filter_map(|(i, left)| left.then_some(i))
+            .collect(),
+    }
+}
+
+/// Stable pixels outside of the item names, which all positives pass
+fn candidates(layout: &LayoutOption, positives: &[RgbaImage]) -> Vec<Candidate> {
+    let Some(first) = positives.first() else {
+        return Vec::new();
+    };
+    let reference = layout.config.reference_resolution;
+    let names = URect::from_corners(
+        layout
+            .config
+            .offset
+            .saturating_sub(UVec2::splat(NAME_MARGIN)),
+        layout.config.offset + layout.config.size + NAME_MARGIN,
+    );
+    let average = average_color(first);
+    let mut candidates = Vec::new();
+    for y in (GRID_STEP / 2..reference.y).step_by(GRID_STEP as usize) {
+        for x in (GRID_STEP / 2..reference.x).step_by(GRID_STEP as usize) {
+            let pos = UVec2::new(x, y);
+            if names.contains(pos) {
+                continue;
+            }
+            let Some((color, variation)) = stable_color(pos, reference, positives) else {
+                continue;
+            };
+            candidates.push(Candidate {
+                check: PixelCheck {
+                    x,
+                    y,
+                    color,
+                    // two decimals are plenty in the config
+                    tolerance: ((variation + TOLERANCE_MARGIN) * 100.).ceil() / 100.,
+                },
+                contrast: color.distance(&average),
+                rules_out: Vec::new(),
+            });
+        }
+    }
+    candidates
+}
+
+/// The average color around `pos` in all images, and how far the colors there are from it.
+/// `None` if they vary too much.
+fn stable_color(pos: UVec2, reference: UVec2, images: &[RgbaImage]) -> Option<(Srgba, f32)> {
+    let mut samples = Vec::with_capacity(images.len() * 9);
+    for img in images {
+        let size = UVec2::from(img.dimensions());
+        let center = (pos * size / reference).as_ivec2();
+        let radius = (FLAT_RADIUS * size.x / reference.x).max(1) as i32;
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let p = center + IVec2::new(dx, dy) * radius;
+                if p.cmplt(IVec2::ZERO).any() || !p.cmplt(size.as_ivec2()).all() {
+                    return None;
+                }
+                samples.push(Srgba::from_u8_array(
+                    img.get_pixel(p.x as u32, p.y as u32).0,
+                ));
+            }
+        }
+    }
+    let sum = samples.iter().fold(Vec4::ZERO, |sum, c| sum + c.to_vec4());
+    // the config stores 8 bit colors
+    let color = Srgba::from_u8_array(Srgba::from_vec4(sum / samples.len() as f32).to_u8_array());
+    let variation = samples
+        .iter()
+        .map(|c| c.distance(&color))
+        .fold(0., f32::max);
+    (variation <= MAX_VARIATION).then_some((color, variation))
+}
+
+fn average_color(img: &RgbaImage) -> Srgba {
+    let pixels: Vec<Srgba> = img
+        .pixels()
+        .step_by(7)
+        .map(|p| Srgba::from_u8_array(p.0))
+        .collect();
+    let sum = pixels.iter().fold(Vec4::ZERO, |sum, c| sum + c.to_vec4());
+    Srgba::from_vec4(sum / pixels.len().max(1) as f32)
+}
+
+/// Load screenshots from files and from the PNGs in directories, cropped to the game
+pub fn load_screenshots(
+    paths: &[PathBuf],
+    viewport: &Viewport,
+) -> Result<Vec<(PathBuf, RgbaImage)>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut pngs: Vec<PathBuf> = std::fs::read_dir(path)
+                .map_err(|e| format!("can't read {}: {e}", path.display()))?
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|p| is_png(p))
+                .collect();
+            pngs.sort();
+            files.extend(pngs);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files
+        .into_iter()
+        .map(|path| {
+            let img = image::open(&path)
+                .map_err(|e| format!("can't read {}: {e}", path.display()))?
+                .to_rgba8();
+            Ok((path, viewport.crop(img).1))
+        })
+        .collect()
+}
+
+fn is_png(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+}
+
+/// The layout editor, which proposes pixel checks for the current screen
+#[derive(Resource)]
+enum Editor {
+    /// Waiting for a frame captured after the editor was opened
+    WaitingForFrame(Instant),
+    Proposing(Task<Result<EditProposal, String>>),
+    Proposed(EditProposal),
+    Failed(String),
+}
+
+struct EditProposal {
+    /// Index of the layout in the config
+    layout: usize,
+    aspect_ratio: [u32; 2],
+    reference: UVec2,
+    /// Where the game is in the capture
+    viewport: URect,
+    proposal: Proposal,
+}
+
+#[derive(Component)]
+struct EditorText;
+
+fn start_editing(receiver: Res<ScreencastReceiver>, mut commands: Commands) {
+    receiver.request_frame();
+    commands.insert_resource(Editor::WaitingForFrame(Instant::now()));
+    commands.spawn((
+        EditorText,
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(12.),
+            left: Val::Px(12.),
+            ..default()
+        },
+        Text::default(),
+        TextColor(STATUS_COLOR),
+        TextShadow::default(),
+        DespawnOnExit(AppState::EditOverlay),
+    ));
+}
+
+/// Propose checks for the first frame captured since the editor was opened
+fn take_frame(mut editor: ResMut<Editor>, mut img: ResMut<LatestImage>, conf: Res<ConfigManager>) {
+    let Editor::WaitingForFrame(since) = *editor else {
+        return;
+    };
+    if img.latest_info().is_none_or(|info| info.captured < since) {
+        return;
+    }
+    let Some(frame) = img.get_latest_rgba() else {
+        return;
+    };
+    let (viewport, frame) = conf.viewport.crop(frame);
+    let (width, height) = frame.dimensions();
+    let Some(layout) = conf.layout_index_for(&frame) else {
+        *editor = Editor::Failed(format!(
+            "No layout in the config has the aspect ratio of the {width}x{height} game"
+        ));
+        return;
+    };
+    let option = conf.layouts[layout].clone();
+    let viewport_conf = conf.viewport;
+    *editor = Editor::Proposing(AsyncComputeTaskPool::get().spawn(async move {
+        let dir = paths::negatives_dir();
+        let negatives = if dir.exists() {
+            load_screenshots(&[dir], &viewport_conf).map_err(|e| e.to_string())?
+        } else {
+            Vec::new()
+        };
+        let negatives: Vec<RgbaImage> = negatives.into_iter().map(|(_, img)| img).collect();
+        let proposal = propose(&option, &[frame], &negatives, DEFAULT_COUNT);
+        if proposal.checks.is_empty() {
+            return Err("Found no stable pixels outside of the item names".to_string());
+        }
+        Ok(EditProposal {
+            layout,
+            aspect_ratio: option.aspect_ratio,
+            reference: option.config.reference_resolution,
+            viewport,
+            proposal,
+        })
+    }));
+}
+
+fn finish_proposal(mut editor: ResMut<Editor>) {
+    let Editor::Proposing(task) = &mut *editor else {
+        return;
+    };
+    if let Some(result) = block_on(future::poll_once(task)) {
+        *editor = match result {
+            Ok(proposal) => Editor::Proposed(proposal),
+            Err(e) => Editor::Failed(e),
+        };
+    }
+}
+
+/// Write the proposed checks into the layout with the trigger keybind
+fn save_proposal(
+    actions: Res<ButtonInput<Action>>,
+    mut editor: ResMut<Editor>,
+    mut conf: ResMut<ConfigManager>,
+    mut commands: Commands,
+) {
+    let Editor::Proposed(edit) = &*editor else {
+        return;
+    };
+    if !actions.just_pressed(Action::Trigger) {
+        return;
+    }
+    match conf.set_pixel_checks(edit.layout, edit.proposal.checks.clone()) {
+        Ok(()) => {
+            info!(
+                "Saved {} pixel checks to layout {}",
+                edit.proposal.checks.len(),
+                edit.layout
+            );
+            commands.set_state(AppState::Waiting);
+        }
+        Err(e) => *editor = Editor::Failed(format!("Could not save the pixel checks: {e}")),
+    }
+}
+
+fn draw_proposal(
+    editor: Res<Editor>,
+    mut gizmos: Gizmos,
+    cam: Single<(&Camera, &GlobalTransform)>,
+    transform: Res<CaptureTransform>,
+) {
+    let Editor::Proposed(edit) = &*editor else {
+        return;
+    };
+    for check in &edit.proposal.checks {
+        let pos = check.position_in(edit.reference, edit.viewport.size()) + edit.viewport.min;
+        let Ok(pos) = cam
+            .0
+            .viewport_to_world_2d(cam.1, transform.to_window(pos.as_vec2()))
+        else {
+            continue;
+        };
+        gizmos.circle_2d(pos, 8., Color::WHITE);
+        gizmos.circle_2d(pos, 3., Color::from(check.color));
+    }
+}
+
+fn update_editor_text(
+    editor: Res<Editor>,
+    conf: Res<ConfigManager>,
+    mut text: Single<(&mut Text, &mut TextColor, &mut TextFont), With<EditorText>>,
+) {
+    if !editor.is_changed() && !conf.is_changed() {
+        return;
+    }
+    text.2.font_size = conf.font_size;
+    let (message, color) = match &*editor {
+        // nothing is shown yet, so it doesn't end up in the frame
+        Editor::WaitingForFrame(_) => (String::new(), STATUS_COLOR),
+        Editor::Proposing(_) => ("Looking for pixel checks...".to_string(), STATUS_COLOR),
+        Editor::Proposed(edit) => (
+            format!(
+                "{} pixel checks for layout {} ({}:{}), {}\nScreenshots of other screens to test against go in {}\nTrigger saves them to the config, dismiss closes the editor",
+                edit.proposal.checks.len(),
+                edit.layout,
+                edit.aspect_ratio[0],
+                edit.aspect_ratio[1],
+                edit.proposal.summary(),
+                paths::negatives_dir().display()
+            ),
+            STATUS_COLOR,
+        ),
+        Editor::Failed(e) => (e.clone(), ERROR_COLOR),
+    };
+    text.0.0 = message;
+    text.1.0 = color;
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+    use crate::config::{Config, Layout};
+
+    const BACKGROUND: Rgba<u8> = Rgba([50, 50, 50, 255]);
+    /// Where the screens differ, in reference pixels
+    const MARKER: URect = URect {
+        min: UVec2::new(8, 8),
+        max: UVec2::new(40, 32),
+    };
+
+    fn layout() -> LayoutOption {
+        let mut option = Config::default().layouts.remove(0);
+        option.config = Layout {
+            offset: UVec2::new(40, 50),
+            size: UVec2::new(80, 10),
+            reference_resolution: UVec2::new(160, 90),
+            ..option.config
+        };
+        option
+    }
+
+    fn screen(width: u32, height: u32, marker: Rgba<u8>) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            if MARKER.contains(UVec2::new(x, y)) {
+                marker
+            } else {
+                BACKGROUND
+            }
+        })
+    }
+
+    #[test]
+    fn checks_rule_out_other_screens() {
+        let layout = layout();
+        let reference = layout.config.reference_resolution;
+        let positive = screen(160, 90, Rgba([200, 30, 30, 255]));
+        let negatives = [
+            // another screen, with a blue marker
+            screen(160, 90, Rgba([30, 30, 200, 255])),
+            // looks the same, so nothing can tell it apart
+            positive.clone(),
+            // another aspect ratio is never compared
+            screen(120, 90, Rgba([30, 30, 200, 255])),
+        ];
+        let proposal = propose(&layout, std::slice::from_ref(&positive), &negatives, 4);
+
+        assert!(!proposal.checks.is_empty());
+        assert!(
+            proposal
+                .checks
+                .iter()
+                .all(|c| c.matches_image(&positive, reference))
+        );
+        // the first check is the one ruling out the most, on the marker
+        let first = proposal.checks[0];
+        assert!(MARKER.contains(UVec2::new(first.x, first.y)), "{first}");
+        assert!(!first.matches_image(&negatives[0], reference));
+
+        assert_eq!(proposal.compared, 2);
+        assert_eq!(proposal.false_positives, vec![1]);
+        assert_eq!(
+            proposal.summary(),
+            "1 of 2 other screenshots still pass them"
+        );
+    }
+
+    #[test]
+    fn summary_counts() {
+        let proposal = |compared, false_positives| Proposal {
+            checks: Vec::new(),
+            compared,
+            false_positives,
+        };
+        assert_eq!(
+            proposal(3, vec![]).summary(),
+            "they tell apart all 3 other screenshots"
+        );
+        assert!(
+            proposal(0, vec![])
+                .summary()
+                .starts_with("there are no other screenshots")
+        );
+    }
+}
@@ -0,0 +1,405 @@
+//! A small template language for the per-item overlay text
+//!
+//! - `{avg}` inserts a field, `{avg:.2}` with a fixed number of decimals
+//! - `{?ducats}...{/ducats}` is only shown if the field has a value
+//! - `{!ducats}...{/ducats}` is only shown if it doesn't
+//! - `{{` and `}}` are literal braces
+use std::fmt::Write;
+
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::market::ItemData;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Avg,
+    Min,
+    Max,
+    Ducats,
+    /// max - min
+    Spread,
+    /// Ducats per plat
+    DucatRatio,
+    Slug,
+    /// The market name of the item, or what OCR read if it wasn't matched
+    Name,
+    /// The text as read by OCR
+    Ocr,
+    /// How old the price data is, e.g. "3h"
+    Age,
+    /// How well the OCR text matched the item name, in percent
+    Confidence,
+}
+
+impl Field {
+    const ALL: &[(&str, Field)] = &[
+        ("avg", Field::Avg),
+        ("min", Field::Min),
+        ("max", Field::Max),
+        ("ducats", Field::Ducats),
+        ("spread", Field::Spread),
+        ("ducat_ratio", Field::DucatRatio),
+        ("slug", Field::Slug),
+        ("name", Field::Name),
+        ("ocr", Field::Ocr),
+        ("age", Field::Age),
+        ("confidence", Field::Confidence),
+    ];
+
+    fn parse(name: &str) -> anyhow::Result<Self> {
+        Self::ALL
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, f)| *f)
+            .ok_or_else(|| {
+                let known: Vec<&str> = Self::ALL.iter().map(|(n, _)| *n).collect();
+                anyhow!("unknown field `{name}`, expected one of {}", known.join(", "))
+            })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Text(String),
+    Field {
+        field: Field,
+        precision: Option<usize>,
+    },
+    Section {
+        field: Field,
+        inverted: bool,
+        body: Vec<Segment>,
+    },
+}
+
+/// Everything a template can show about one item
+pub struct TemplateContext<'a> {
+    pub ocr: &'a str,
+    pub name: Option<&'a str>,
+    pub slug: Option<&'a str>,
+    pub data: Option<&'a ItemData>,
+    /// 0 to 1
+    pub confidence: Option<f32>,
+}
+
+enum Value<'a> {
+    Number(f32),
+    Text(&'a str),
+    Owned(String),
+}
+
+impl TemplateContext<'_> {
+    fn get(&self, field: Field) -> Option<Value<'_>> {
+        let finite = |v: f32| v.is_finite().then_some(Value::Number(v));
+        match field {
+            Field::Avg => self.data.and_then(|d| finite(d.avg)),
+            Field::Min => self.data.and_then(|d| finite(d.min)),
+            Field::Max => self.data.and_then(|d| finite(d.max)),
+            Field::Ducats => self
+                .data
+                .and_then(|d| d.ducats)
+                .map(|d| Value::Number(d as f32)),
+            Field::Spread => self.data.and_then(|d| finite(d.max - d.min)),
+            Field::DucatRatio => self
+                .data
+                .and_then(|d| d.ducats.and_then(|du| finite(du as f32 / d.avg))),
+            Field::Slug => self.slug.map(Value::Text),
+            Field::Name => Some(Value::Text(self.name.unwrap_or(self.ocr))),
+            Field::Ocr => Some(Value::Text(self.ocr)),
+            Field::Age => self.data.map(|d| Value::Owned(format_age(d.age_secs()))),
+            Field::Confidence => self.confidence.map(|c| Value::Number(c * 100.)),
+        }
+    }
+}
+
+pub fn format_age(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> anyhow::Result<Self> {
+        // stack of open sections, the bottom is the template itself
+        let mut stack: Vec<(Option<(Field, bool)>, Vec<Segment>)> = vec![(None, Vec::new())];
+        let mut text = String::new();
+        let mut chars = source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '}' => bail!("unmatched `}}`, use `}}}}` for a literal brace"),
+                '{' => {
+                    let mut tag = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => tag.push(c),
+                            None => bail!("unclosed `{{{tag}`"),
+                        }
+                    }
+                    let body = &mut stack.last_mut().expect("never empty").1;
+                    if !text.is_empty() {
+                        body.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+
+                    if let Some(name) = tag.strip_prefix('/') {
+                        let field = Field::parse(name.trim())?;
+                        match stack.pop() {
+                            Some((Some((open, inverted)), body)) if open == field => {
+                                stack.last_mut().expect("never empty").1.push(
+                                    Segment::Section {
+                                        field,
+                                        inverted,
+                                        body,
+                                    },
+                                );
+                            }
+                            _ => bail!("`{{/{name}}}` does not close an open section"),
+                        }
+                    } else if let Some(name) = tag.strip_prefix('?') {
+                        stack.push((Some((Field::parse(name.trim())?, false)), Vec::new()));
+                    } else if let Some(name) = tag.strip_prefix('!') {
+                        stack.push((Some((Field::parse(name.trim())?, true)), Vec::new()));
+                    } else {
+                        let (name, spec) = match tag.split_once(':') {
+                            Some((name, spec)) => (name, Some(spec)),
+                            None => (tag.as_str(), None),
+                        };
+                        let precision = spec
+                            .map(|spec| {
+                                spec.trim()
+                                    .strip_prefix('.')
+                                    .and_then(|p| p.parse::<usize>().ok())
+                                    .ok_or_else(|| {
+                                        anyhow!("invalid format `{spec}`, expected e.g. `.1`")
+                                    })
+                            })
+                            .transpose()?;
+                        body.push(Segment::Field {
+                            field: Field::parse(name.trim())?,
+                            precision,
+                        });
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            stack
+                .last_mut()
+                .expect("never empty")
+                .1
+                .push(Segment::Text(text));
+        }
+        match stack.pop() {
+            Some((None, segments)) if stack.is_empty() => Ok(Self {
+                source: source.to_string(),
+                segments,
+            }),
+            _ => bail!("unclosed section"),
+        }
+    }
+
+    pub fn render(&self, ctx: &TemplateContext) -> String {
+        let mut out = String::new();
+        Self::render_segments(&self.segments, ctx, &mut out);
+        out
+    }
+
+    fn render_segments(segments: &[Segment], ctx: &TemplateContext, out: &mut String) {
+        for segment in segments {
+            match segment {
+                Segment::Text(text) => out.push_str(text),
+                Segment::Field { field, precision } => {
+                    let _ = match (ctx.get(*field), precision) {
+                        (None, _) => write!(out, "-"),
+                        (Some(Value::Number(n)), Some(p)) => write!(out, "{n:.p$}"),
+                        // whole numbers without decimals, everything else with one
+                        (Some(Value::Number(n)), None) if n.fract() == 0. => write!(out, "{n}"),
+                        (Some(Value::Number(n)), None) => write!(out, "{n:.1}"),
+                        (Some(Value::Text(t)), _) => write!(out, "{t}"),
+                        (Some(Value::Owned(t)), _) => write!(out, "{t}"),
+                    };
+                }
+                Segment::Section {
+                    field,
+                    inverted,
+                    body,
+                } => {
+                    if ctx.get(*field).is_some() != *inverted {
+                        Self::render_segments(body, ctx, out);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Self::parse("Avg: {avg}\nMin: {min}\nMax: {max}\nDucats: {ducats}\n{slug}")
+            .expect("default template is valid")
+    }
+}
+
+impl Serialize for Template {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Template::parse(&s)
+            .map_err(|e| serde::de::Error::custom(format!("Invalid template: {e}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::unix_now;
+
+    fn item(avg: f32, min: f32, max: f32, ducats: Option<u32>) -> ItemData {
+        serde_json::from_value(serde_json::json!({
+            "last_fetch": unix_now() - 3 * 3600,
+            "ducats": ducats,
+            "avg": avg,
+            "min": min,
+            "max": max,
+        }))
+        .unwrap()
+    }
+
+    fn render(source: &str, data: Option<&ItemData>) -> String {
+        let ctx = TemplateContext {
+            ocr: "Ash Prime Systms",
+            name: Some("Ash Prime Systems"),
+            slug: Some("ash_prime_systems"),
+            data,
+            confidence: Some(0.9),
+        };
+        Template::parse(source).unwrap().render(&ctx)
+    }
+
+    fn error(source: &str) -> String {
+        Template::parse(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn fields() {
+        let data = item(12., 10.5, 15., Some(45));
+        assert_eq!(
+            render("{avg} {min} {max} {spread} {ducats}", Some(&data)),
+            "12 10.5 15 4.5 45"
+        );
+        assert_eq!(
+            render("{ducat_ratio}", Some(&item(12., 10., 15., Some(100)))),
+            "8.3"
+        );
+        assert_eq!(
+            render("{name} ({ocr}) {slug}", Some(&data)),
+            "Ash Prime Systems (Ash Prime Systms) ash_prime_systems"
+        );
+        assert_eq!(render("{age} {confidence:.0}%", Some(&data)), "3h 90%");
+        assert_eq!(render("{ avg }", Some(&data)), "12");
+    }
+
+    #[test]
+    fn precision() {
+        let data = item(12.4, 10., 15., None);
+        assert_eq!(render("{avg:.2}", Some(&data)), "12.40");
+        assert_eq!(render("{avg:.0}", Some(&data)), "12");
+        assert_eq!(render("{min:.1}", Some(&data)), "10.0");
+        // only numbers have decimals
+        assert_eq!(render("{slug:.2}", Some(&data)), "ash_prime_systems");
+    }
+
+    #[test]
+    fn missing_values() {
+        assert_eq!(render("{avg} {ducats} {age}", None), "- - -");
+        // no orders
+        let data = item(f32::NAN, f32::NAN, f32::NAN, Some(15));
+        assert_eq!(render("{avg} {spread} {ducat_ratio}", Some(&data)), "- - -");
+    }
+
+    #[test]
+    fn sections() {
+        let source = "{?ducats}{ducats} ducats{/ducats}{!ducats}no ducats{/ducats}";
+        assert_eq!(
+            render(source, Some(&item(12., 10., 15., Some(45)))),
+            "45 ducats"
+        );
+        assert_eq!(
+            render(source, Some(&item(12., 10., 15., None))),
+            "no ducats"
+        );
+        assert_eq!(render(source, None), "no ducats");
+
+        let nested = "{?avg}{avg}p{?ducats}, {ducats}d{/ducats}{/avg}";
+        assert_eq!(
+            render(nested, Some(&item(12., 10., 15., Some(45)))),
+            "12p, 45d"
+        );
+        assert_eq!(render(nested, Some(&item(12., 10., 15., None))), "12p");
+        assert_eq!(render(nested, None), "");
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(render("{{avg}} = {avg}}}", None), "{avg} = -}");
+        assert_eq!(
+            render("{{{avg}}}", Some(&item(12., 10., 15., None))),
+            "{12}"
+        );
+    }
+
+    #[test]
+    fn default_template() {
+        let data = item(12., 10., 15., Some(45));
+        let ctx = TemplateContext {
+            ocr: "",
+            name: None,
+            slug: Some("ash_prime_systems"),
+            data: Some(&data),
+            confidence: None,
+        };
+        assert_eq!(
+            Template::default().render(&ctx),
+            "Avg: 12\nMin: 10\nMax: 15\nDucats: 45\nash_prime_systems"
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(error("{nope}").contains("unknown field `nope`"));
+        assert!(error("{?nope}{/nope}").contains("unknown field `nope`"));
+        assert!(error("Avg: {avg").contains("unclosed `{avg`"));
+        assert!(error("Avg}").contains("unmatched `}`"));
+        assert!(error("{avg:2}").contains("invalid format `2`"));
+        assert!(error("{avg:.x}").contains("invalid format `.x`"));
+        assert!(error("{?ducats}{ducats}").contains("unclosed section"));
+        assert!(error("{ducats}{/ducats}").contains("does not close an open section"));
+        assert!(error("{?avg}{?ducats}{/avg}{/ducats}").contains("does not close"));
+    }
+}
@@ -0,0 +1,184 @@
+//! Locating the game inside the captured frame, for games running in a window
+use bevy::prelude::*;
+use image::RgbaImage;
+
+use crate::config::Viewport;
+
+/// Minimum luminance step between neighbouring pixels to count as an edge
+const EDGE_THRESHOLD: u8 = 24;
+/// Fraction of the rows (or columns) a window border has to cover
+const MIN_EDGE_COVERAGE: f32 = 0.4;
+/// A detected game window is at least this fraction of the frame in each direction
+const MIN_SIZE: f32 = 0.25;
+/// Only every nth pixel along an edge is compared
+const SAMPLE_STEP: usize = 2;
+
+impl Viewport {
+    /// The part of `img` showing the game, in capture pixels
+    pub fn locate(&self, img: &RgbaImage) -> URect {
+        let full = URect::from_corners(UVec2::ZERO, img.dimensions().into());
+        match *self {
+            Viewport::Full => full,
+            Viewport::Fixed {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                let rect = URect::new(x, y, x + width, y + height).intersect(full);
+                if rect.is_empty() {
+                    warn!("Configured viewport is outside the {}x{} frame", full.max.x, full.max.y);
+                    full
+                } else {
+                    rect
+                }
+            }
+            Viewport::Detect => detect_window(img).unwrap_or(full),
+        }
+    }
+
+    /// The part of `img` showing the game, and where it is in the capture
+    pub fn crop(&self, img: RgbaImage) -> (URect, RgbaImage) {
+        let viewport = self.locate(&img);
+        if viewport.size() == UVec2::from(img.dimensions()) {
+            return (viewport, img);
+        }
+        let cropped = image::imageops::crop_imm(
+            &img,
+            viewport.min.x,
+            viewport.min.y,
+            viewport.width(),
+            viewport.height(),
+        )
+        .to_image();
+        (viewport, cropped)
+    }
+}
+
+fn luminance(img: &RgbaImage, x: u32, y: u32) -> u8 {
+    let [r, g, b, _] = img.get_pixel(x, y).0;
+    ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8
+}
+
+/// For every column in `xs`, the fraction of rows in `ys` with an edge to the column before it
+fn column_edges(img: &RgbaImage, xs: std::ops::Range<u32>, ys: std::ops::Range<u32>) -> Vec<f32> {
+    let rows = ys.clone().step_by(SAMPLE_STEP).len().max(1) as f32;
+    xs.map(|x| {
+        if x == 0 {
+            return 0.;
+        }
+        ys.clone()
+            .step_by(SAMPLE_STEP)
+            .filter(|&y| luminance(img, x, y).abs_diff(luminance(img, x - 1, y)) >= EDGE_THRESHOLD)
+            .count() as f32
+            / rows
+    })
+    .collect()
+}
+
+/// For every row in `ys`, the fraction of columns in `xs` with an edge to the row above it
+fn row_edges(img: &RgbaImage, xs: std::ops::Range<u32>, ys: std::ops::Range<u32>) -> Vec<f32> {
+    let columns = xs.clone().step_by(SAMPLE_STEP).len().max(1) as f32;
+    ys.map(|y| {
+        if y == 0 {
+            return 0.;
+        }
+        xs.clone()
+            .step_by(SAMPLE_STEP)
+            .filter(|&x| luminance(img, x, y).abs_diff(luminance(img, x, y - 1)) >= EDGE_THRESHOLD)
+            .count() as f32
+            / columns
+    })
+    .collect()
+}
+
+/// The strongest edge in the first and second half of `edges`, or the ends if there is none
+fn strongest_pair(edges: &[f32], len: u32) -> (u32, u32) {
+    let half = edges.len() / 2;
+    let strongest = |range: std::ops::Range<usize>| {
+        range
+            .filter(|&i| edges[i] >= MIN_EDGE_COVERAGE)
+            .max_by(|&a, &b| edges[a].total_cmp(&edges[b]))
+    };
+    let start = strongest(0..half).map_or(0, |i| i as u32);
+    let end = strongest(half..edges.len()).map_or(len, |i| i as u32);
+    (start, end)
+}
+
+/// Find a game window inside a monitor capture by its borders: long straight edges
+/// running along most of the frame on each side.
+fn detect_window(img: &RgbaImage) -> Option<URect> {
+    let (width, height) = img.dimensions();
+    if width < 2 || height < 2 {
+        return None;
+    }
+    // the borders are found across the whole frame first, then again only within the
+    // window found so far, where they cover a larger fraction
+    let (left, right) = strongest_pair(&column_edges(img, 0..width, 0..height), width);
+    let (top, bottom) = strongest_pair(&row_edges(img, left..right, 0..height), height);
+    let (left, right) = strongest_pair(&column_edges(img, 0..width, top..bottom), width);
+
+    let rect = URect::new(left, top, right, bottom);
+    let size = rect.size().as_vec2() / Vec2::new(width as f32, height as f32);
+    if rect.min == UVec2::ZERO && rect.max == UVec2::new(width, height) {
+        return None;
+    }
+    if size.x < MIN_SIZE || size.y < MIN_SIZE {
+        debug!("Ignoring detected viewport {rect:?}, it is too small");
+        return None;
+    }
+    debug!("Detected game viewport {rect:?}");
+    Some(rect)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    const BACKGROUND: Rgba<u8> = Rgba([30, 30, 40, 255]);
+
+    /// A 400x300 desktop with a window at `window`, with a bit of text in it
+    fn desktop(window: URect) -> RgbaImage {
+        let text = URect::from_center_size(window.center(), UVec2::new(20, 10));
+        // `URect::contains` includes the max corner
+        let inside = |rect: URect, p: UVec2| p.cmpge(rect.min).all() && p.cmplt(rect.max).all();
+        RgbaImage::from_fn(400, 300, |x, y| {
+            let p = UVec2::new(x, y);
+            if inside(text, p) {
+                Rgba([230, 230, 230, 255])
+            } else if inside(window, p) {
+                Rgba([120, 100, 80, 255])
+            } else {
+                BACKGROUND
+            }
+        })
+    }
+
+    #[test]
+    fn window_on_desktop() {
+        let window = URect::new(100, 50, 340, 230);
+        assert_eq!(detect_window(&desktop(window)), Some(window));
+    }
+
+    #[test]
+    fn window_in_corner() {
+        let window = URect::new(0, 0, 300, 200);
+        assert_eq!(detect_window(&desktop(window)), Some(window));
+    }
+
+    #[test]
+    fn full_screen_game() {
+        let img = desktop(URect::new(0, 0, 400, 300));
+        assert_eq!(detect_window(&img), None);
+        assert_eq!(Viewport::Detect.locate(&img), URect::new(0, 0, 400, 300));
+    }
+
+    #[test]
+    fn small_windows_are_ignored() {
+        // tall enough for its borders to be found, but too narrow
+        let img = desktop(URect::new(180, 20, 250, 280));
+        assert_eq!(detect_window(&img), None);
+    }
+}